@@ -0,0 +1,239 @@
+//! Typed packing of the four data words of a message
+//!
+//! Fields are packed in order from the lowest bit of `data0` up. A field that doesn't fit in what's left of a word
+//! starts at the bottom of the next one, so no field is ever split between words. Requests declared with a command
+//! put it in the lowest byte of `data0`, which is what servers dispatch on before decoding the rest
+
+use crate::{status_enum, ipc::{Message, PayloadMessage, Pid}};
+
+status_enum! {
+    pub enum DecodeError {
+        /// A field holds a value its type doesn't have
+        InvalidField = 10,
+        /// The message is for a different command
+        WrongCommand = 11,
+    }
+}
+
+/// A value that packs into a fixed number of bits of a message word
+pub trait Field: Copy {
+    const BITS: u32;
+
+    fn into_bits(self) -> u64;
+    fn from_bits(bits: u64) -> Result<Self, DecodeError>;
+}
+
+macro_rules! int_field {
+    ($($ty:ty),*) => {
+        $(
+            impl Field for $ty {
+                const BITS: u32 = <$ty>::BITS;
+
+                fn into_bits(self) -> u64 {
+                    self as u64
+                }
+
+                fn from_bits(bits: u64) -> Result<Self, DecodeError> {
+                    Ok(bits as $ty)
+                }
+            }
+        )*
+    };
+}
+
+int_field!(u8, u16, u32, u64);
+
+impl Field for bool {
+    const BITS: u32 = 1;
+
+    fn into_bits(self) -> u64 {
+        self as u64
+    }
+
+    fn from_bits(bits: u64) -> Result<Self, DecodeError> {
+        Ok(bits != 0)
+    }
+}
+
+/// Finds where a field of `bits` bits starts if the previous one ended at `bit` of `word`
+const fn place(word: usize, bit: u32, bits: u32) -> (usize, u32) {
+    if bit + bits > 64 {
+        (word + 1, 0)
+    } else {
+        (word, bit)
+    }
+}
+
+/// Counts how many words fields with these sizes take up
+pub const fn words_for(fields: &[u32]) -> usize {
+    let (mut word, mut bit) = (0, 0);
+    let mut i = 0;
+
+    while i < fields.len() {
+        (word, bit) = place(word, bit, fields[i]);
+        bit += fields[i];
+        i += 1;
+    }
+
+    if bit > 0 { word + 1 } else { word }
+}
+
+/// Packs fields into message words one after another
+pub struct Encoder {
+    words: [u64; 4],
+    word: usize,
+    bit: u32,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self { words: [0; 4], word: 0, bit: 0 }
+    }
+
+    pub fn push<F: Field>(&mut self, field: F) {
+        (self.word, self.bit) = place(self.word, self.bit, F::BITS);
+
+        self.words[self.word] |= field.into_bits() << self.bit;
+        self.bit += F::BITS;
+    }
+
+    pub fn finish(self) -> [u64; 4] {
+        self.words
+    }
+}
+
+/// Unpacks fields in the same order an `Encoder` packed them
+pub struct Decoder {
+    words: [u64; 4],
+    word: usize,
+    bit: u32,
+}
+
+impl Decoder {
+    pub fn new(words: [u64; 4]) -> Self {
+        Self { words, word: 0, bit: 0 }
+    }
+
+    pub fn pop<F: Field>(&mut self) -> Result<F, DecodeError> {
+        (self.word, self.bit) = place(self.word, self.bit, F::BITS);
+
+        let mask = if F::BITS == 64 { u64::MAX } else { (1 << F::BITS) - 1 };
+        let bits = (self.words[self.word] >> self.bit) & mask;
+
+        self.bit += F::BITS;
+
+        F::from_bits(bits)
+    }
+}
+
+/// Data that packs into the data words of a message, usually declared with `message!`
+pub trait MessageData: Sized {
+    /// How many of the four data words the fields take up
+    const WORDS: usize;
+
+    fn encode(&self) -> [u64; 4];
+    fn decode(words: [u64; 4]) -> Result<Self, DecodeError>;
+
+    fn into_message(&self, pid: Pid) -> Message {
+        let [data0, data1, data2, data3] = self.encode();
+
+        Message { pid, data0, data1, data2, data3 }
+    }
+
+    fn from_message(message: &Message) -> Result<Self, DecodeError> {
+        Self::decode([message.data0, message.data1, message.data2, message.data3])
+    }
+
+    /// Packs into a payload message carrying `payload`, which leaves room for two words
+    fn into_payload_message(&self, pid: Pid, payload: &[u8]) -> PayloadMessage {
+        assert!(Self::WORDS <= 2, "Message data doesn't fit next to a payload");
+
+        let [data0, data1, ..] = self.encode();
+
+        PayloadMessage { pid, data0, data1, payload: payload.as_ptr() as u64, payload_len: payload.len() as u64 }
+    }
+
+    fn from_payload_message(message: &PayloadMessage) -> Result<Self, DecodeError> {
+        Self::decode([message.data0, message.data1, 0, 0])
+    }
+}
+
+/// Message data for one command of a server
+pub trait Request: MessageData {
+    const COMMAND: u8;
+}
+
+/// Gets the command a request was sent for
+pub fn command(message: &Message) -> u8 {
+    message.data0 as u8
+}
+
+/// Declares a struct along with its `MessageData` implementation, and `Request` if it's given a command
+///
+/// ```ignore
+/// message! {
+///     pub struct Open(Command::open) {
+///         pub flags: u8,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! message {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident $(($command:path))? {
+            $(
+                $(#[$field_meta:meta])*
+                $field_vis:vis $field:ident: $ty:ty
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $(
+                $(#[$field_meta])*
+                $field_vis $field: $ty,
+            )*
+        }
+
+        impl $crate::encoding::MessageData for $name {
+            const WORDS: usize = $crate::encoding::words_for(&[
+                $({ let _ = $command; <u8 as $crate::encoding::Field>::BITS },)?
+                $(<$ty as $crate::encoding::Field>::BITS,)*
+            ]);
+
+            #[allow(unused_mut)]
+            fn encode(&self) -> [u64; 4] {
+                let mut encoder = $crate::encoding::Encoder::new();
+
+                $(encoder.push($command as u8);)?
+                $(encoder.push(self.$field);)*
+
+                encoder.finish()
+            }
+
+            #[allow(unused_mut, unused_variables)]
+            fn decode(words: [u64; 4]) -> Result<Self, $crate::encoding::DecodeError> {
+                let mut decoder = $crate::encoding::Decoder::new(words);
+
+                $(
+                    if decoder.pop::<u8>()? != $command as u8 {
+                        return Err($crate::encoding::DecodeError::WrongCommand);
+                    }
+                )?
+
+                Ok(Self {
+                    $($field: decoder.pop()?,)*
+                })
+            }
+        }
+
+        $(
+            impl $crate::encoding::Request for $name {
+                const COMMAND: u8 = $command as u8;
+            }
+        )?
+
+        const _: () = assert!(<$name as $crate::encoding::MessageData>::WORDS <= 4, "Message data doesn't fit in four words");
+    };
+}
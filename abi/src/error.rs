@@ -0,0 +1,178 @@
+//! Status codes and the error type shared by every syscall and server
+//!
+//! Statuses below 10 mean success, anything 10 and up is an error. Every status enum also has `Unknown` at 0xFE for
+//! codes it doesn't have, like the one the kernel returns for a syscall it doesn't implement
+
+use core::fmt;
+
+#[derive(Clone, Copy, Debug)]
+pub struct InvalidStatusCode;
+
+/// An error status returned by a syscall or a server
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Error {
+    /// The status enum the error came from, like `"SendStatus"`
+    pub kind: &'static str,
+    /// The name of the variant, like `"InvalidRecipient"`
+    pub name: &'static str,
+    pub code: u8,
+}
+
+impl Error {
+    /// Checks if this error was created from `status`
+    pub fn is<S: Status>(&self, status: S) -> bool
+    where
+        u8: From<S>
+    {
+        self.kind == S::NAME && self.code == u8::from(status)
+    }
+}
+
+impl From<InvalidStatusCode> for Error {
+    fn from(_: InvalidStatusCode) -> Self {
+        Self {
+            kind: "InvalidStatusCode",
+            name: "InvalidStatusCode",
+            code: 0xFF,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}::{} ({})", self.kind, self.name, self.code)
+    }
+}
+
+pub trait Status where
+    Self: Sized + Copy,
+    u8: From<Self>
+{
+    /// The name of the status enum
+    const NAME: &'static str;
+
+    /// The name of this variant
+    fn name(self) -> &'static str;
+
+    /// Turns a raw code into a status, `Unknown` if it isn't one of the variants
+    fn from_code(code: u64) -> Self;
+
+    fn is_err(self) -> bool {
+        let num: u8 = u8::from(self);
+        num >= 10
+    }
+
+    /// Turns error statuses into an `Error`, passing successful ones through
+    fn into_result(self) -> Result<Self, Error> {
+        if self.is_err() {
+            Err(Error {
+                kind: Self::NAME,
+                name: self.name(),
+                code: u8::from(self),
+            })
+        } else {
+            Ok(self)
+        }
+    }
+}
+
+/// Declares a status enum along with its conversions from and to raw codes, `Status`, `SyscallOutput`, `Error` and
+/// `encoding::Field`
+///
+/// ```ignore
+/// status_enum! {
+///     pub enum OpenStatus {
+///         Success = 0,
+///         NotExists = 10,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! status_enum {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident {
+            $(
+                $(#[$variant_meta:meta])*
+                $variant:ident = $value:literal
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        #[repr(u8)]
+        $vis enum $name {
+            $(
+                $(#[$variant_meta])*
+                $variant = $value,
+            )*
+            /// A code that isn't any of the other variants
+            Unknown = 0xFE,
+        }
+
+        impl TryFrom<u64> for $name {
+            type Error = $crate::InvalidStatusCode;
+
+            fn try_from(value: u64) -> Result<Self, Self::Error> {
+                match value {
+                    $($value => Ok(Self::$variant),)*
+                    0xFE => Ok(Self::Unknown),
+                    _ => Err($crate::InvalidStatusCode),
+                }
+            }
+        }
+
+        impl From<$name> for u8 {
+            fn from(value: $name) -> Self {
+                value as u8
+            }
+        }
+
+        impl $crate::Status for $name {
+            const NAME: &'static str = stringify!($name);
+
+            fn name(self) -> &'static str {
+                match self {
+                    $(Self::$variant => stringify!($variant),)*
+                    Self::Unknown => "Unknown",
+                }
+            }
+
+            fn from_code(code: u64) -> Self {
+                code.try_into().unwrap_or(Self::Unknown)
+            }
+        }
+
+        impl From<$name> for $crate::Error {
+            fn from(value: $name) -> Self {
+                $crate::Error {
+                    kind: stringify!($name),
+                    name: $crate::Status::name(value),
+                    code: value as u8,
+                }
+            }
+        }
+
+        impl $crate::SyscallOutput for $name {
+            fn into_regs(self) -> [u64; 6] {
+                [self as u64, 0, 0, 0, 0, 0]
+            }
+
+            fn from_regs(regs: [u64; 6]) -> Self {
+                $crate::Status::from_code(regs[0])
+            }
+        }
+
+        impl $crate::encoding::Field for $name {
+            const BITS: u32 = 8;
+
+            fn into_bits(self) -> u64 {
+                self as u64
+            }
+
+            fn from_bits(bits: u64) -> Result<Self, $crate::encoding::DecodeError> {
+                bits.try_into().map_err(|_| $crate::encoding::DecodeError::InvalidField)
+            }
+        }
+    };
+}
@@ -0,0 +1,30 @@
+//! Where everything lives in the lower half of a process' address space
+//!
+//! Every region the kernel maps into a process is at a fixed address from this list,
+//! except for memory shares, which go wherever the process asks
+
+/// Pages for `map_memory` start here
+pub const MAP_START: u64 = 0x0000_4000_0000_0000;
+/// Pages for `map_memory` end here
+pub const MAP_END: u64 = HEAP_START;
+
+/// Where `grow_heap` starts mapping pages
+pub const HEAP_START: u64 = 0x0000_5000_0000_0000;
+/// The most a process heap can grow to
+pub const HEAP_MAX: u64 = 0x0000_0100_0000_0000;
+
+/// Where programs are linked, see the linker scripts
+pub const IMAGE_START: u64 = 0x0000_6000_0000_0000;
+
+pub const STACK_BOTTOM: u64 = 0x0000_6800_0000_0000;
+pub const STACK_SIZE: u64 = 4096 * 16;
+
+/// Where `request_fb` maps the framebuffer
+pub const FB_START: u64 = 0x0000_7fff_0000_0000;
+
+/// Where `config_rbuffer` maps the response buffer
+pub const RESPONSE_BUFFER: u64 = 0x0000_7fff_0400_0000;
+pub const RESPONSE_BUFFER_SIZE: u64 = 0x0200_0000;
+
+/// Holds the user stack pointer while the kernel is running, only accessible by the kernel
+pub const USER_GS: u64 = 0x0000_7fff_ffff_f000;
@@ -1,7 +1,12 @@
 #![no_std]
 
+pub mod encoding;
+pub mod error;
+pub mod layout;
+pub mod protocol;
 pub mod syscalls;
 pub mod servers;
 
+pub use error::*;
 pub use syscalls::*;
 pub use servers::*;
\ No newline at end of file
@@ -0,0 +1,267 @@
+//! Server protocols declared once and shared by both ends
+//!
+//! `protocol!` takes a table of methods like `define_syscalls!` does. It generates the `Command` enum, the `requests`
+//! encoders, the `Client` stub programs call the server through, and the `Server` trait and `dispatch` function the
+//! server implements. Every protocol also answers a handshake, so a client can check it's talking to a server that
+//! speaks the same version before relying on the rest
+
+use crate::{status_enum, raw, Status, Error, encoding::{Encoder, Decoder}, ipc::{CallResponse, Message, Pid}};
+
+status_enum! {
+    /// Replies a server sends when it couldn't run a method at all, which use codes no method status does
+    pub enum ProtocolStatus {
+        Success = 0,
+        /// The server speaks a different version of the protocol
+        VersionMismatch = 0xF0,
+        /// The server doesn't have a method with that command
+        UnknownCommand = 0xF1,
+        /// The arguments couldn't be decoded
+        InvalidRequest = 0xF2,
+    }
+}
+
+/// The command of the handshake, which no method can use
+pub const HANDSHAKE: u8 = 0xFF;
+
+/// Checks that the server at `pid` speaks `version` of its protocol
+pub fn handshake(pid: Pid, version: u8) -> Result<(), Error> {
+    let mut encoder = Encoder::new();
+    encoder.push(HANDSHAKE);
+    encoder.push(version);
+
+    let [data0, data1, data2, data3] = encoder.finish();
+    let response = unsafe { raw::call(pid, data0, data1, data2, data3) };
+
+    read_reply::<ProtocolStatus>(response)?;
+
+    Ok(())
+}
+
+/// Answers a handshake from a client that wants `version` of the protocol
+pub fn answer_handshake(request: &Message, version: u8) -> Message {
+    let mut decoder = Decoder::new([request.data0, 0, 0, 0]);
+    let _ = decoder.pop::<u8>();
+
+    let status = match decoder.pop::<u8>() {
+        Ok(theirs) if theirs == version => ProtocolStatus::Success,
+        _ => ProtocolStatus::VersionMismatch,
+    };
+
+    reply(request.pid, status.into())
+}
+
+/// Builds a reply carrying a status code
+pub fn reply(pid: Pid, status: u8) -> Message {
+    Message { pid, data0: status as u64, ..Default::default() }
+}
+
+/// Turns the reply to a call into the status of the method that was called
+pub fn read_reply<S>(response: CallResponse) -> Result<S, Error>
+where
+    S: Status,
+    u8: From<S>,
+{
+    response.status.into_result()?;
+
+    let code = response.message.unwrap().data0;
+
+    if let Ok(status) = ProtocolStatus::try_from(code) {
+        status.into_result()?;
+    }
+
+    Ok(S::from_code(code).into_result()?)
+}
+
+/// Declares the methods of a server, generating both ends of its protocol
+///
+/// Methods can take any `encoding::Field` as arguments, and a payload after `with`. Arguments are packed after the
+/// command, into four words or two if there's a payload. The server's reply is the method's status
+///
+/// ```ignore
+/// protocol! {
+///     version 1;
+///
+///     /// Opens the file at the path in the payload
+///     0x00 => fn open(flags: u8) with path -> OpenStatus;
+/// }
+/// ```
+#[macro_export]
+macro_rules! protocol {
+    (
+        version $version:literal;
+
+        $(
+            $(#[$meta:meta])*
+            $opcode:literal => fn $name:ident($($arg:ident: $ty:ty),* $(,)?) $(with $payload:ident)? -> $status:ty;
+        )*
+    ) => {
+        /// The version of the protocol, which has to change whenever a method does
+        pub const VERSION: u8 = $version;
+
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        #[repr(u8)]
+        #[allow(non_camel_case_types)]
+        pub enum Command {
+            $($name = $opcode,)*
+        }
+
+        impl TryFrom<u8> for Command {
+            type Error = $crate::protocol::ProtocolStatus;
+
+            fn try_from(value: u8) -> Result<Self, Self::Error> {
+                match value {
+                    $($opcode => Ok(Self::$name),)*
+                    _ => Err($crate::protocol::ProtocolStatus::UnknownCommand),
+                }
+            }
+        }
+
+        const _: () = assert!($(Command::$name as u8 != $crate::protocol::HANDSHAKE &&)* true, "Command is taken by the handshake");
+
+        /// Packs the arguments of each method into message words, after its command
+        pub mod requests {
+            use super::*;
+
+            $(
+                $crate::protocol!(@request $name($($arg: $ty),*) [$($payload)?]);
+            )*
+        }
+
+        /// A server that speaks this protocol
+        #[derive(Clone, Copy, Debug)]
+        pub struct Client {
+            pub pid: $crate::ipc::Pid,
+        }
+
+        impl Client {
+            /// Connects to the server at `pid`, failing if it speaks a different version of the protocol
+            pub fn connect(pid: $crate::ipc::Pid) -> Result<Self, $crate::Error> {
+                $crate::protocol::handshake(pid, VERSION)?;
+
+                Ok(Self { pid })
+            }
+
+            $(
+                $crate::protocol!(@client $(#[$meta])* $name($($arg: $ty),*) [$($payload)?] -> $status);
+            )*
+        }
+
+        /// Implemented by the server, with one method per command
+        pub trait Server {
+            $(
+                $(#[$meta])*
+                fn $name(&mut self, from: $crate::ipc::Pid, $($arg: $ty,)* $($payload: &[u8])?) -> $status;
+            )*
+        }
+
+        /// Decodes `request`, runs the method it's for on `server` and builds the reply
+        ///
+        /// `payload` has to be whether the kernel said the request carries a payload when it was received, since only
+        /// then do `data2` and `data3` point into the response buffer instead of wherever the client wanted. The
+        /// payload is read straight out of the response buffer and released afterwards, so `request` has to be a
+        /// message this process just received
+        #[allow(unused_labels)]
+        pub unsafe fn dispatch<S: Server>(server: &mut S, request: &$crate::ipc::Message, payload: bool) -> $crate::ipc::Message {
+            let code = $crate::encoding::command(request);
+
+            let reply = if code == $crate::protocol::HANDSHAKE {
+                $crate::protocol::answer_handshake(request, VERSION)
+            } else {
+                let status = match Command::try_from(code) {
+                    $(Ok(Command::$name) => $crate::protocol!(@dispatch server request payload $name($($arg: $ty),*) [$($payload)?]),)*
+                    Err(status) => status.into(),
+                };
+
+                $crate::protocol::reply(request.pid, status)
+            };
+
+            // there's nobody to tell if the slot couldn't be released
+            if payload {
+                let _ = $crate::raw::release_rbuffer(request.data2);
+            }
+
+            reply
+        }
+    };
+
+    (@request $name:ident($($arg:ident: $ty:ty),*) [$($payload:ident)?]) => {
+        #[allow(unused_mut)]
+        pub fn $name($($arg: $ty),*) -> [u64; 4] {
+            let mut encoder = $crate::encoding::Encoder::new();
+
+            encoder.push(Command::$name as u8);
+            $(encoder.push($arg);)*
+
+            encoder.finish()
+        }
+
+        const _: () = assert!(
+            $crate::encoding::words_for(&[8, $(<$ty as $crate::encoding::Field>::BITS),*]) <= $crate::protocol!(@words [$($payload)?]),
+            "Arguments don't fit in the request",
+        );
+    };
+
+    (@words []) => { 4 };
+    (@words [$payload:ident]) => { 2 };
+
+    (@client $(#[$meta:meta])* $name:ident($($arg:ident: $ty:ty),*) [] -> $status:ty) => {
+        $(#[$meta])*
+        pub fn $name(&self, $($arg: $ty),*) -> Result<$status, $crate::Error> {
+            let [data0, data1, data2, data3] = requests::$name($($arg),*);
+            let response = unsafe { $crate::raw::call(self.pid, data0, data1, data2, data3) };
+
+            $crate::protocol::read_reply(response)
+        }
+    };
+
+    (@client $(#[$meta:meta])* $name:ident($($arg:ident: $ty:ty),*) [$payload:ident] -> $status:ty) => {
+        $(#[$meta])*
+        pub fn $name(&self, $($arg: $ty,)* $payload: &[u8]) -> Result<$status, $crate::Error> {
+            let [data0, data1, ..] = requests::$name($($arg),*);
+            let response = unsafe { $crate::raw::call_payload(self.pid, data0, data1, $payload.as_ptr(), $payload.len()) };
+
+            $crate::protocol::read_reply(response)
+        }
+    };
+
+    (@dispatch $server:ident $request:ident $has_payload:ident $name:ident($($arg:ident: $ty:ty),*) []) => {
+        'request: {
+            #[allow(unused_mut)]
+            let mut decoder = $crate::encoding::Decoder::new([$request.data0, $request.data1, $request.data2, $request.data3]);
+            let _ = decoder.pop::<u8>();
+
+            $(
+                let Ok($arg) = decoder.pop::<$ty>() else {
+                    break 'request $crate::protocol::ProtocolStatus::InvalidRequest.into();
+                };
+            )*
+
+            u8::from($server.$name($request.pid, $($arg),*))
+        }
+    };
+
+    (@dispatch $server:ident $request:ident $has_payload:ident $name:ident($($arg:ident: $ty:ty),*) [$payload:ident]) => {
+        'request: {
+            if !$has_payload {
+                break 'request $crate::protocol::ProtocolStatus::InvalidRequest.into();
+            }
+
+            #[allow(unused_mut)]
+            let mut decoder = $crate::encoding::Decoder::new([$request.data0, $request.data1, 0, 0]);
+            let _ = decoder.pop::<u8>();
+
+            $(
+                let Ok($arg) = decoder.pop::<$ty>() else {
+                    break 'request $crate::protocol::ProtocolStatus::InvalidRequest.into();
+                };
+            )*
+
+            let $payload: &[u8] = match $request.data3 {
+                0 => &[],
+                len => core::slice::from_raw_parts($request.data2 as *const u8, len as usize),
+            };
+
+            u8::from($server.$name($request.pid, $($arg,)* $payload))
+        }
+    };
+}
@@ -1 +1,2 @@
+pub mod graphics;
 pub mod input;
\ No newline at end of file
@@ -0,0 +1,12 @@
+use crate::{protocol, render::{DrawBitmapStatus, DrawStringStatus}};
+
+protocol! {
+    version 1;
+
+    /// Draws a bitmap with one bit per pixel and `width` bytes per row
+    0x10 => fn draw_bitmap(x: u16, y: u16, color: u16, width: u16, height: u16, scale: u8) with bitmap -> DrawBitmapStatus;
+    /// Draws UTF-8 text
+    0x11 => fn draw_string(x: u16, y: u16, color: u16, scale: u8) with text -> DrawStringStatus;
+    /// Writes UTF-8 text to the terminal
+    0x12 => fn print() with text -> DrawStringStatus;
+}
@@ -1,86 +1,39 @@
-use crate::{InvalidStatusCode, Status};
+use crate::{status_enum, message, protocol, encoding::{Field, DecodeError}};
 
-#[derive(Clone, Copy, Debug)]
-#[repr(u8)]
-#[allow(non_camel_case_types)]
-pub enum Command {
-    publish = 0x00,
-    subscribe = 0x10,
-}
-
-#[derive(Clone, Copy, Debug)]
-pub struct InvalidCommand;
-
-impl TryFrom<u64> for Command {
-    type Error = InvalidCommand;
-
-    fn try_from(value: u64) -> Result<Self, Self::Error> {
-        match value {
-            0x00 => Ok(Self::publish),
-            0x10 => Ok(Self::subscribe),
-            _ => Err(InvalidCommand),
-        }
-    }
-}
-
-#[derive(Clone, Copy, Debug)]
-#[repr(u8)]
-pub enum PublishStatus {
-    Success = 0,
-    IncomingKey = 1,
-    MissingPermissions = 10,
-    InvalidKey = 11,
-}
-
-impl TryFrom<u64> for PublishStatus {
-    type Error = InvalidStatusCode;
-
-    fn try_from(value: u64) -> Result<Self, Self::Error> {
-        match value {
-            0 => Ok(Self::Success),
-            1 => Ok(Self::IncomingKey),
-            10 => Ok(Self::MissingPermissions),
-            11 => Ok(Self::InvalidKey),
-            _ => Err(InvalidStatusCode),
-        }
+status_enum! {
+    pub enum PublishStatus {
+        Success = 0,
+        MissingPermissions = 10,
+        InvalidKey = 11,
     }
 }
 
-impl From<PublishStatus> for u8 {
-    fn from(value: PublishStatus) -> Self {
-        value as u8
+status_enum! {
+    pub enum SubscribeStatus {
+        Success = 0,
+        AlreadySubscribed = 10,
+        None = 255,
     }
 }
 
-impl Status for PublishStatus {}
+protocol! {
+    version 1;
 
-#[derive(Clone, Copy, Debug)]
-#[repr(u8)]
-pub enum SubscribeStatus {
-    Success = 0,
-    AlreadySubscribed = 10,
-    None = 255,
+    /// Sent by the kernel for every byte the keyboard sends
+    0x00 => fn publish(scancode: u8) -> PublishStatus;
+    /// Asks for a `KeyEvent` notification for every key that's pressed or released
+    0x10 => fn subscribe() -> SubscribeStatus;
 }
 
-impl TryFrom<u64> for SubscribeStatus {
-    type Error = InvalidStatusCode;
-    
-    fn try_from(value: u64) -> Result<Self, Self::Error> {
-        match value {
-            0 => Ok(Self::Success),
-            10 => Ok(Self::AlreadySubscribed),
-            255 => Ok(Self::None),
-            _ => Err(InvalidStatusCode),
-        }
+message! {
+    /// The notification subscribers get for each key
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct KeyEvent {
+        pub code: KeyCode,
+        pub state: KeyState,
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub struct KeyEvent {
-    pub code: KeyCode,
-    pub state: KeyState,
-}
-
 #[derive(Clone, Copy, Debug)]
 pub struct InvalidKeyCode;
 
@@ -90,17 +43,15 @@ pub enum KeyState {
     Down,
 }
 
-impl TryFrom<u64> for KeyEvent {
-    type Error = InvalidKeyCode;
+impl Field for KeyState {
+    const BITS: u32 = 1;
 
-    fn try_from(value: u64) -> Result<Self, Self::Error> {
-        let code = KeyCode::try_from(value as u8).map_err(|_| InvalidKeyCode)?;
-        let state = if value & 0x100 > 0 { KeyState::Down } else { KeyState::Up };
+    fn into_bits(self) -> u64 {
+        (self == Self::Down) as u64
+    }
 
-        Ok(Self {
-            code,
-            state
-        })
+    fn from_bits(bits: u64) -> Result<Self, DecodeError> {
+        Ok(if bits != 0 { Self::Down } else { Self::Up })
     }
 }
 
@@ -108,10 +59,24 @@ impl TryFrom<u8> for KeyCode {
     type Error = InvalidKeyCode;
 
     fn try_from(value: u8) -> Result<Self, Self::Error> {
-        match value {
-            i if i == Self::A as u8 => Ok(Self::A),
-            _ => Err(InvalidKeyCode),
+        if value > Self::RAlt2 as u8 {
+            return Err(InvalidKeyCode);
         }
+
+        // the variants go from 0 to `RAlt2` without gaps
+        Ok(unsafe { core::mem::transmute(value) })
+    }
+}
+
+impl Field for KeyCode {
+    const BITS: u32 = 8;
+
+    fn into_bits(self) -> u64 {
+        self as u64
+    }
+
+    fn from_bits(bits: u64) -> Result<Self, DecodeError> {
+        Self::try_from(bits as u8).map_err(|_| DecodeError::InvalidField)
     }
 }
 
@@ -1,125 +1,380 @@
-//! TODO: Add a `SyscallRunner` trait for user and kernel to implement to keep API consistent
+//! Every syscall is declared once in the table at the bottom of this file
+//!
+//! The table generates the `Syscall` enum, the `SyscallHandler` trait and `dispatch` function the kernel uses,
+//! and the stubs in `raw` that user programs use to make the call
 pub mod dev;
+pub mod endpoint;
 pub mod ipc;
 pub mod memshare;
 pub mod render;
+pub mod topic;
+pub mod vm;
 
-#[derive(Clone, Copy, Debug)]
-#[repr(u64)]
-#[allow(non_camel_case_types)]
-pub enum Syscall {
-    exit = 0x00,
-    config_rbuffer = 0x01,
-    fork = 0x04,
-    priv_fork = 0x05,
-    exec = 0x06,
-    send = 0x08,
-    receive = 0x09,
-    notify = 0x0a,
-    read_mailbox = 0x0b,
-    config_mailbox = 0x0c,
-    send_payload = 0x0d,
-    create_memshare = 0x10,
-    join_memshare = 0x11,
-    sleep = 0x18,
-    get_time = 0x19,
-    request_fb = 0x28,
-    request_io = 0x30,
-    inb = 0x31,
-    inw = 0x32,
-    inl = 0x33,
-    outb = 0x34,
-    outw = 0x35,
-    outl = 0x36,
-    getpid = 0x40,
-    sys_yield = 0x48,
-    // Temporary
-    // draw_bitmap = 0x100,
-    // draw_string = 0x101,
-    // print = 0x102,
-    send_serial = 0x130,
+use core::arch::asm;
+
+use crate::{Status, status_enum};
+
+use dev::{FramebufferDescriptor, RequestFbStatus, SerialStatus};
+use endpoint::{EndpointId, CreateEndpointResponse, DestroyEndpointStatus, GrantEndpointStatus, RevokeEndpointStatus, EndpointReceiveResponse};
+use ipc::{Pid, MailboxFlags, SendStatus, NotifyStatus, ConfigMailboxStatus, ReceiveResponse, ReadMailboxResponse, CallResponse, ReplyStatus, CancelSendStatus, Timeout, WaitFlags, WaitResponse, OverflowPolicy, MailboxInfo};
+use memshare::{ShareId, ShareGrant, CreateShareResponse, JoinShareResponse, AllocShareResponse, LeaveShareStatus, DestroyShareStatus};
+use topic::{TopicId, CreateTopicResponse, DestroyTopicStatus, SubscribeTopicStatus, UnsubscribeTopicStatus, PublishTopicResponse};
+use vm::{GrowHeapResponse, MapFlags, MapMemoryResponse, UnmapMemoryStatus, ProtectMemoryStatus};
+
+/// A value that can be passed to a syscall in a single register
+pub trait SyscallArg {
+    fn into_arg(self) -> u64;
+    fn from_arg(value: u64) -> Self;
 }
 
-#[derive(Clone, Copy, Debug)]
-pub struct InvalidStatusCode;
+/// A value that a syscall returns in `rax`, `rdi`, `rsi`, `rdx`, `r8` and `r9`, in that order
+pub trait SyscallOutput {
+    fn into_regs(self) -> [u64; 6];
+    fn from_regs(regs: [u64; 6]) -> Self;
+}
+
+impl SyscallArg for u64 {
+    fn into_arg(self) -> u64 {
+        self
+    }
+
+    fn from_arg(value: u64) -> Self {
+        value
+    }
+}
+
+impl SyscallArg for usize {
+    fn into_arg(self) -> u64 {
+        self as u64
+    }
+
+    fn from_arg(value: u64) -> Self {
+        value as usize
+    }
+}
+
+impl SyscallArg for bool {
+    fn into_arg(self) -> u64 {
+        self as u64
+    }
+
+    fn from_arg(value: u64) -> Self {
+        value > 0
+    }
+}
+
+impl<T> SyscallArg for *const T {
+    fn into_arg(self) -> u64 {
+        self as u64
+    }
+
+    fn from_arg(value: u64) -> Self {
+        value as Self
+    }
+}
+
+impl<T> SyscallArg for *mut T {
+    fn into_arg(self) -> u64 {
+        self as u64
+    }
+
+    fn from_arg(value: u64) -> Self {
+        value as Self
+    }
+}
+
+impl SyscallOutput for () {
+    fn into_regs(self) -> [u64; 6] {
+        [0; 6]
+    }
+
+    fn from_regs(_: [u64; 6]) -> Self {}
+}
+
+/// Executes the `syscall` instruction, returning `rax`, `rdi`, `rsi`, `rdx`, `r8` and `r9`
+/// 
+/// Arguments are passed in `rdi`, `rsi`, `rdx`, `r8`, `r9` and `r10`
+#[inline(always)]
+pub unsafe fn syscall(number: u64, args: [u64; 6]) -> [u64; 6] {
+    let mut regs = [0; 6];
+
+    asm!(
+        "syscall",
+        inlateout("rax") number => regs[0],
+        inlateout("rdi") args[0] => regs[1],
+        inlateout("rsi") args[1] => regs[2],
+        inlateout("rdx") args[2] => regs[3],
+        inlateout("r8") args[3] => regs[4],
+        inlateout("r9") args[4] => regs[5],
+        in("r10") args[5],
+        lateout("rcx") _,
+        lateout("r11") _,
+    );
+
+    regs
+}
+
+/// Pads the arguments of a syscall out to the six argument registers
+#[doc(hidden)]
+pub fn pad_args(args: &[u64]) -> [u64; 6] {
+    let mut out = [0; 6];
+    out[..args.len()].copy_from_slice(args);
+    out
+}
+
+macro_rules! define_syscalls {
+    (
+        $(
+            $(#[$meta:meta])*
+            $number:literal => fn $name:ident($($arg:ident: $arg_ty:ty),* $(,)?) -> $out:ty;
+        )*
+
+        reserved {
+            $($reserved_number:literal => $reserved:ident,)*
+        }
+    ) => {
+        #[derive(Clone, Copy, Debug)]
+        #[repr(u64)]
+        #[allow(non_camel_case_types)]
+        pub enum Syscall {
+            $($name = $number,)*
+            $($reserved = $reserved_number,)*
+        }
+
+        impl TryFrom<u64> for Syscall {
+            type Error = SyscallFromU64Error;
+
+            fn try_from(value: u64) -> Result<Self, Self::Error> {
+                match value {
+                    $($number => Ok(Self::$name),)*
+                    $($reserved_number => Ok(Self::$reserved),)*
+                    _ => Err(SyscallFromU64Error::InvalidSyscall),
+                }
+            }
+        }
+
+        /// Implemented by the kernel, with one method per syscall
+        pub trait SyscallHandler {
+            $(
+                $(#[$meta])*
+                fn $name(&mut self, $($arg: $arg_ty),*) -> $out;
+            )*
+        }
+
+        /// Decodes the arguments of `syscall` and runs it on `handler`
+        /// 
+        /// Returns the registers to hand back to the caller, or `None` if the syscall is reserved but not implemented
+        pub fn dispatch<H: SyscallHandler>(handler: &mut H, syscall: Syscall, args: [u64; 6]) -> Option<[u64; 6]> {
+            #[allow(unused_mut, unused_variables)]
+            let mut args = args.into_iter();
+
+            match syscall {
+                $(
+                    Syscall::$name => Some(handler.$name($(<$arg_ty as SyscallArg>::from_arg(args.next().unwrap())),*).into_regs()),
+                )*
+                $(Syscall::$reserved => None,)*
+            }
+        }
+
+        /// Stubs for making each syscall from user mode
+        pub mod raw {
+            use super::*;
+
+            $(
+                $(#[$meta])*
+                #[inline(always)]
+                pub unsafe fn $name($($arg: $arg_ty),*) -> $out {
+                    let args = pad_args(&[$(SyscallArg::into_arg($arg)),*]);
+
+                    <$out as SyscallOutput>::from_regs(syscall(Syscall::$name as u64, args))
+                }
+            )*
+        }
+    };
+}
 
 #[derive(Clone, Copy, Debug)]
 pub enum SyscallFromU64Error {
     InvalidSyscall,
 }
 
-impl TryFrom<u64> for Syscall {
-    type Error = SyscallFromU64Error;
-
-    fn try_from(value: u64) -> Result<Self, Self::Error> {
-        match value {
-            0x00 => Ok(Self::exit),
-            0x01 => Ok(Self::config_rbuffer),
-            0x04 => Ok(Self::fork),
-            0x05 => Ok(Self::priv_fork),
-            0x06 => Ok(Self::exec),
-            0x08 => Ok(Self::send),
-            0x09 => Ok(Self::receive),
-            0x0a => Ok(Self::notify),
-            0x0b => Ok(Self::read_mailbox),
-            0x0c => Ok(Self::config_mailbox),
-            0x0d => Ok(Self::send_payload),
-            0x10 => Ok(Self::create_memshare),
-            0x11 => Ok(Self::join_memshare),
-            0x18 => Ok(Self::sleep),
-            0x19 => Ok(Self::get_time),
-            0x28 => Ok(Self::request_fb),
-            0x30 => Ok(Self::request_io),
-            0x31 => Ok(Self::inb),
-            0x32 => Ok(Self::inw),
-            0x33 => Ok(Self::inl),
-            0x34 => Ok(Self::outb),
-            0x35 => Ok(Self::outw),
-            0x36 => Ok(Self::outl),
-            0x40 => Ok(Self::getpid),
-            0x48 => Ok(Self::sys_yield),
-            // 0x100 => Ok(Self::draw_bitmap),
-            // 0x101 => Ok(Self::draw_string),
-            // 0x102 => Ok(Self::print),
-            0x130 => Ok(Self::send_serial),
-            _ => Err(SyscallFromU64Error::InvalidSyscall),
-        }
+status_enum! {
+    pub enum ConfigRBufferStatus {
+        Success = 0,
+        TooBig = 10,
+        /// There has to be at least one slot, and slots can't be empty
+        InvalidSlots = 11,
     }
 }
 
-#[derive(Clone, Copy, Debug)]
-#[repr(u8)]
-pub enum ConfigRBufferStatus {
-    Success = 0,
-    TooBig = 10,
+status_enum! {
+    pub enum ReleaseRBufferStatus {
+        Success = 0,
+        /// The address isn't the start of a slot holding a payload
+        NotInUse = 10,
+    }
 }
 
-impl TryFrom<u64> for ConfigRBufferStatus {
-    type Error = InvalidStatusCode;
-
-    fn try_from(value: u64) -> Result<Self, Self::Error> {
-        match value {
-            0 => Ok(Self::Success),
-            10 => Ok(Self::TooBig),
-            _ => Err(InvalidStatusCode),
-        }
+status_enum! {
+    pub enum GetPidStatus {
+        Success = 0,
+        InvalidSelector = 10,
     }
 }
 
-impl From<ConfigRBufferStatus> for u8 {
-    fn from(value: ConfigRBufferStatus) -> Self {
-        value as u8
+#[derive(Clone, Copy, Debug)]
+pub struct GetPidResponse {
+    pub status: GetPidStatus,
+    pub pid: Pid,
+}
+
+impl SyscallOutput for GetPidResponse {
+    fn into_regs(self) -> [u64; 6] {
+        [self.status as u64, self.pid, 0, 0, 0, 0]
+    }
+
+    fn from_regs(regs: [u64; 6]) -> Self {
+        Self {
+            status: GetPidStatus::from_code(regs[0]),
+            pid: regs[1],
+        }
     }
 }
 
-impl Status for ConfigRBufferStatus {}
+define_syscalls! {
+    /// Ends the current process
+    0x00 => fn exit() -> ();
+    /// Reserves a response buffer at `layout::RESPONSE_BUFFER` for incoming payloads, split into `slots` slots of at
+    /// least `slot_size` bytes each
+    ///
+    /// Each payload gets a slot of its own, which is passed in `data2` and stays taken until it's released. Any payloads
+    /// still in the old buffer are released
+    0x01 => fn config_rbuffer(slot_size: u64, slots: u64) -> ConfigRBufferStatus;
+    /// Maps at least `size` more bytes onto the end of the heap at `layout::HEAP_START`
+    0x02 => fn grow_heap(size: u64) -> GrowHeapResponse;
+    /// Frees the response buffer slot starting at `addr` for another payload
+    0x03 => fn release_rbuffer(addr: u64) -> ReleaseRBufferStatus;
+    /// Sends a message to `pid`, blocking until it's received or `timeout` runs out
+    0x08 => fn send(pid: Pid, data0: u64, data1: u64, data2: u64, data3: u64, timeout: Timeout) -> SendStatus;
+    /// Blocks until a message is received from one of the processes in the whitelist, or any process if it's empty,
+    /// or until `timeout` runs out
+    0x09 => fn receive(whitelist: *const Pid, whitelist_len: usize, timeout: Timeout) -> ReceiveResponse;
+    /// Puts a message in the mailbox of `pid` without blocking
+    0x0a => fn notify(pid: Pid, data0: u64, data1: u64, data2: u64, data3: u64) -> NotifyStatus;
+    /// Takes the oldest message out of the mailbox, only looking at messages from `sender` if `filter` is set
+    0x0b => fn read_mailbox(sender: Pid, filter: bool) -> ReadMailboxResponse;
+    /// Enables or disables the mailbox, optionally replacing its whitelist or its capacity and overflow policy
+    ///
+    /// Shrinking the capacity drops the oldest notifications that don't fit anymore
+    0x0c => fn config_mailbox(flags: MailboxFlags, whitelist: *const Pid, whitelist_len: usize, capacity: usize, policy: OverflowPolicy) -> ConfigMailboxStatus;
+    /// Sends a message to `pid` with a payload copied into a free slot of its response buffer, blocking until it's
+    /// received or `timeout` runs out
+    ///
+    /// The recipient gets the address of the slot in `data2` and the length in `data3`, with `ReceiveStatus::Payload` or
+    /// `WaitStatus::ReceivedPayload` so it can tell them from words a plain message made up. While every slot is
+    /// taken, the send waits as if the recipient wasn't receiving. Page aligned payloads of at least
+    /// `ipc::ZERO_COPY_THRESHOLD` bytes have their whole pages shared copy-on-write instead of copied
+    0x0d => fn send_payload(pid: Pid, data0: u64, data1: u64, payload: *const u8, payload_len: usize, timeout: Timeout) -> SendStatus;
+    /// Sends a message to `pid` that lets it join the memory share `share` with `access`, blocking until it's received
+    ///
+    /// The recipient is added to the share's whitelist once it receives the message, which carries the share in `data2`
+    /// and the access in `data3`
+    0x0e => fn send_grant(pid: Pid, share: ShareId, access: MapFlags, data0: u64, data1: u64) -> SendStatus;
+    /// Gets the state of the mailbox, then resets its dropped notification counter if `reset_dropped` is set
+    0x0f => fn mailbox_info(reset_dropped: bool) -> MailboxInfo;
+    /// Shares the pages from `start` to `end` (inclusive) with the processes in the whitelist, with the access each one is granted
+    ///
+    /// An empty whitelist lets any process join with any access. The pages have to come from `map_memory` or the heap
+    0x10 => fn create_memshare(start: u64, end: u64, whitelist: *const ShareGrant, whitelist_len: usize) -> CreateShareResponse;
+    /// Maps a shared region into the pages from `start` to `end` (inclusive) with `access`, which can't be more than was granted
+    ///
+    /// If `start` and `end` are both 0, the kernel picks where it goes
+    0x11 => fn join_memshare(id: ShareId, start: u64, end: u64, access: MapFlags, blacklist: *const Pid, blacklist_len: usize) -> JoinShareResponse;
+    /// Unmaps a shared region from the current process, its frames are freed once every member has left
+    0x12 => fn leave_memshare(id: ShareId) -> LeaveShareStatus;
+    /// Stops anyone else from joining a shared region and leaves it, only allowed for the process that created it
+    0x13 => fn destroy_memshare(id: ShareId) -> DestroyShareStatus;
+    /// Creates a share out of `pages` fresh zeroed pages and maps it wherever there's room
+    0x14 => fn alloc_memshare(pages: u64, whitelist: *const ShareGrant, whitelist_len: usize) -> AllocShareResponse;
+    /// Maps `size` bytes of zeroed memory at `start`, or wherever there's room if `start` is 0
+    0x20 => fn map_memory(start: u64, size: u64, flags: MapFlags) -> MapMemoryResponse;
+    /// Unmaps `size` bytes starting at `start`, skipping pages that aren't mapped
+    0x21 => fn unmap_memory(start: u64, size: u64) -> UnmapMemoryStatus;
+    /// Changes the access of `size` bytes starting at `start`, which have to be memory the process owns
+    0x22 => fn protect_memory(start: u64, size: u64, flags: MapFlags) -> ProtectMemoryStatus;
+    /// Maps the framebuffer into the current process and fills in `descriptor`
+    0x28 => fn request_fb(descriptor: *mut FramebufferDescriptor) -> RequestFbStatus;
+    /// Gets the PID of the current process, `selector` must be 0
+    0x40 => fn getpid(selector: u64) -> GetPidResponse;
+    /// Gives up the rest of the current time slice
+    0x48 => fn sys_yield() -> ();
+    /// Sends a message to `pid` and blocks until it replies, returning the reply
+    0x50 => fn call(pid: Pid, data0: u64, data1: u64, data2: u64, data3: u64) -> CallResponse;
+    /// Sends a message to `pid` with a payload copied into its response buffer and blocks until it replies
+    0x51 => fn call_payload(pid: Pid, data0: u64, data1: u64, payload: *const u8, payload_len: usize) -> CallResponse;
+    /// Wakes up `pid` with a reply if it's blocked calling the current process, without blocking
+    0x52 => fn reply(pid: Pid, data0: u64, data1: u64, data2: u64, data3: u64) -> ReplyStatus;
+    /// Replies to `pid` like `reply`, then blocks until a message is received from any process
+    ///
+    /// A reply to a process that isn't waiting anymore is dropped, so a server can't be stalled by a caller that exited
+    0x53 => fn reply_and_receive(pid: Pid, data0: u64, data1: u64, data2: u64, data3: u64) -> ReceiveResponse;
+    /// Fails a send or call from `pid` that's waiting on the current process with `SendStatus::Cancelled`
+    0x54 => fn cancel_send(pid: Pid) -> CancelSendStatus;
+    /// Blocks until a synchronous message or a notification arrives, depending on `flags`, or until `timeout` runs out
+    ///
+    /// Messages have to come from a process in the whitelist, or any process if it's empty. If `flags.filter` is set,
+    /// only notifications from `notif_sender` count
+    0x55 => fn wait(flags: WaitFlags, whitelist: *const Pid, whitelist_len: usize, notif_sender: Pid, timeout: Timeout) -> WaitResponse;
+    /// Creates an endpoint owned by the current process, which only processes it grants access to can send to
+    0x56 => fn create_endpoint() -> CreateEndpointResponse;
+    /// Destroys an endpoint, failing every send or call to it that hasn't been received yet
+    0x57 => fn destroy_endpoint(endpoint: EndpointId) -> DestroyEndpointStatus;
+    /// Lets `pid` send to an endpoint, with `badge` on every message it sends there
+    ///
+    /// The owner can pick any badge and change the badge of a process that already has one. Other processes that can
+    /// send to the endpoint can only pass on their own badge, so `badge` is ignored for them, and only to processes that
+    /// can't send to it yet
+    0x58 => fn grant_endpoint(endpoint: EndpointId, pid: Pid, badge: u64) -> GrantEndpointStatus;
+    /// Stops `pid` from sending to an endpoint, only allowed for the owner
+    0x59 => fn revoke_endpoint(endpoint: EndpointId, pid: Pid) -> RevokeEndpointStatus;
+    /// Sends a message to whichever process owns an endpoint, blocking until it's received there or `timeout` runs out
+    0x5a => fn send_endpoint(endpoint: EndpointId, data0: u64, data1: u64, data2: u64, timeout: Timeout) -> SendStatus;
+    /// Sends a message to an endpoint like `send_endpoint` and blocks until the owner replies, returning the reply
+    0x5b => fn call_endpoint(endpoint: EndpointId, data0: u64, data1: u64, data2: u64) -> CallResponse;
+    /// Blocks until a message is sent to an endpoint the current process owns, or until `timeout` runs out
+    ///
+    /// Messages sent straight to the process aren't received here, and messages sent to the endpoint aren't received
+    /// by `receive`
+    0x5c => fn receive_endpoint(endpoint: EndpointId, timeout: Timeout) -> EndpointReceiveResponse;
+    /// Creates a topic owned by the current process, which is the only one that can publish to it
+    0x5d => fn create_topic() -> CreateTopicResponse;
+    /// Destroys a topic, its subscribers just stop getting events
+    0x5e => fn destroy_topic(topic: TopicId) -> DestroyTopicStatus;
+    /// Subscribes `pid` to a topic, which has to be the current process unless it owns the topic
+    ///
+    /// Processes are unsubscribed from everything when they exit
+    0x5f => fn subscribe_topic(topic: TopicId, pid: Pid) -> SubscribeTopicStatus;
+    /// Unsubscribes `pid` from a topic, which has to be the current process unless it owns the topic
+    0x60 => fn unsubscribe_topic(topic: TopicId, pid: Pid) -> UnsubscribeTopicStatus;
+    /// Puts an event in the mailbox of every subscriber of a topic the current process owns, without blocking
+    ///
+    /// Subscribers get it as a notification from the current process, following the same rules as `notify`
+    0x61 => fn publish_topic(topic: TopicId, data0: u64, data1: u64, data2: u64, data3: u64) -> PublishTopicResponse;
+    /// Prints `len` bytes of UTF-8 text to the serial port
+    0x130 => fn send_serial(text: *const u8, len: usize) -> SerialStatus;
 
-pub trait Status where 
-    Self: Sized,
-    u8: From<Self>
-{
-    fn is_err(self) -> bool {
-        let num: u8 = u8::from(self);
-        num >= 10
+    reserved {
+        0x04 => fork,
+        0x05 => priv_fork,
+        0x06 => exec,
+        0x18 => sleep,
+        0x19 => get_time,
+        0x30 => request_io,
+        0x31 => inb,
+        0x32 => inw,
+        0x33 => inl,
+        0x34 => outb,
+        0x35 => outw,
+        0x36 => outl,
     }
 }
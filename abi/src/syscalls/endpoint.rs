@@ -0,0 +1,99 @@
+use crate::{Status, SyscallOutput, status_enum, ipc::{Pid, ReceiveStatus}};
+
+status_enum! {
+    pub enum CreateEndpointStatus {
+        Success = 0,
+    }
+}
+
+status_enum! {
+    pub enum DestroyEndpointStatus {
+        Success = 0,
+        NotExists = 10,
+        /// Only the process that created an endpoint can destroy it
+        NotOwner = 11,
+    }
+}
+
+status_enum! {
+    pub enum GrantEndpointStatus {
+        Success = 0,
+        NotExists = 10,
+        /// The current process doesn't own the endpoint and either can't send to it or is trying to change the badge
+        /// of a process that already can
+        AccessDenied = 11,
+    }
+}
+
+status_enum! {
+    pub enum RevokeEndpointStatus {
+        Success = 0,
+        NotExists = 10,
+        /// Only the process that created an endpoint can revoke access to it
+        NotOwner = 11,
+        /// The process couldn't send to the endpoint anyway
+        NotGranted = 12,
+    }
+}
+
+pub type EndpointId = u64;
+
+/// A message received on an endpoint
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EndpointMessage {
+    /// The process that sent it, which is who a call gets replied to
+    pub pid: Pid,
+    /// The badge the endpoint's owner gave the sender
+    pub badge: u64,
+    pub data0: u64,
+    pub data1: u64,
+    pub data2: u64,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CreateEndpointResponse {
+    pub status: CreateEndpointStatus,
+    pub id: EndpointId,
+}
+
+impl SyscallOutput for CreateEndpointResponse {
+    fn into_regs(self) -> [u64; 6] {
+        [self.status as u64, self.id, 0, 0, 0, 0]
+    }
+
+    fn from_regs(regs: [u64; 6]) -> Self {
+        Self { status: Status::from_code(regs[0]), id: regs[1] }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct EndpointReceiveResponse {
+    pub status: ReceiveStatus,
+    pub message: Option<EndpointMessage>,
+}
+
+impl From<ReceiveStatus> for EndpointReceiveResponse {
+    fn from(value: ReceiveStatus) -> Self {
+        Self { status: value, message: None }
+    }
+}
+
+impl SyscallOutput for EndpointReceiveResponse {
+    fn into_regs(self) -> [u64; 6] {
+        let message = self.message.unwrap_or_default();
+
+        [self.status as u64, message.pid, message.badge, message.data0, message.data1, message.data2]
+    }
+
+    fn from_regs(regs: [u64; 6]) -> Self {
+        let status = ReceiveStatus::from_code(regs[0]);
+
+        if status.is_err() {
+            return status.into();
+        }
+
+        let [_, pid, badge, data0, data1, data2] = regs;
+
+        Self { status, message: Some(EndpointMessage { pid, badge, data0, data1, data2 }) }
+    }
+}
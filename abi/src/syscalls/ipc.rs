@@ -1,135 +1,237 @@
-use crate::syscalls::InvalidStatusCode;
-
-use super::Status;
+use crate::{Status, SyscallArg, SyscallOutput, status_enum};
 
 pub type Pid = u64;
 
-pub const RESPONSE_BUFFER: u64 = 0x0000_7fff_0400_0000;
-pub const RESPONSE_BUFFER_SIZE: u64 = 0x0200_0000;
-
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-#[repr(u8)]
-pub enum SendStatus {
-    Success = 0,
-    InvalidRecipient = 10,
-    Blocked = 11,
-    NoResponseBuffer = 12,
-    BufferTooSmall = 13,
-    InvalidPayload = 14,
+pub use crate::layout::{RESPONSE_BUFFER, RESPONSE_BUFFER_SIZE};
+
+/// Page aligned payloads at least this long have their pages shared with the recipient copy-on-write instead of copied
+pub const ZERO_COPY_THRESHOLD: u64 = 0x4000;
+
+status_enum! {
+    pub enum SendStatus {
+        Success = 0,
+        InvalidRecipient = 10,
+        Blocked = 11,
+        NoResponseBuffer = 12,
+        /// The payload is bigger than a slot of the recipient's response buffer
+        BufferTooSmall = 13,
+        InvalidPayload = 14,
+        /// The memory share doesn't exist or can't be joined anymore
+        InvalidShare = 15,
+        /// The sender tried to grant more access to a share than it has
+        AccessDenied = 16,
+        /// The message wasn't received before the timeout ran out
+        TimedOut = 17,
+        /// The recipient wasn't receiving and the send wasn't allowed to wait
+        WouldBlock = 18,
+        /// The recipient cancelled the send or call
+        Cancelled = 19,
+        /// The endpoint doesn't exist, or the sender hasn't been granted access to it
+        NoCapability = 20,
+        /// There weren't enough frames to put the payload in the recipient's response buffer
+        OutOfMemory = 21,
+    }
 }
 
-impl TryFrom<u64> for SendStatus {
-    type Error = InvalidStatusCode;
-
-    fn try_from(value: u64) -> Result<Self, Self::Error> {
-        match value {
-            0 => Ok(Self::Success),
-            10 => Ok(Self::InvalidRecipient),
-            11 => Ok(Self::Blocked),
-            12 => Ok(Self::NoResponseBuffer),
-            13 => Ok(Self::BufferTooSmall),
-            14 => Ok(Self::InvalidPayload),
-            _ => Err(InvalidStatusCode),
-        }
+status_enum! {
+    pub enum ReceiveStatus {
+        Success = 0,
+        /// The message carries a payload in the response buffer, the same code as `WaitStatus::ReceivedPayload`
+        Payload = 2,
+        InvalidWhitelist = 10,
+        /// No message was sent before the timeout ran out
+        TimedOut = 11,
+        /// Nothing was waiting to be received and the receive wasn't allowed to wait
+        WouldBlock = 12,
+        /// The endpoint doesn't exist or belongs to another process
+        InvalidEndpoint = 13,
     }
 }
 
-impl From<SendStatus> for u8 {
-    fn from(value: SendStatus) -> Self {
-        value as u8
+status_enum! {
+    pub enum WaitStatus {
+        /// A synchronous message was received
+        Received = 0,
+        /// A notification was taken out of the mailbox
+        Notified = 1,
+        /// A synchronous message with a payload in the response buffer was received
+        ReceivedPayload = 2,
+        InvalidWhitelist = 10,
+        /// Nothing arrived before the timeout ran out
+        TimedOut = 11,
+        /// Nothing was ready and the wait wasn't allowed to block
+        WouldBlock = 12,
+        /// Notifications were asked for, but the mailbox is disabled
+        MailboxDisabled = 13,
     }
 }
 
-impl Status for SendStatus {}
+status_enum! {
+    pub enum CancelSendStatus {
+        Success = 0,
+        /// The process isn't sending to or calling the current process
+        NotPending = 10,
+    }
+}
 
+/// How long a blocking IPC syscall waits before giving up, in timer ticks
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
-#[repr(u8)]
-pub enum ReceiveStatus {
-    Success = 0,
-    InvalidWhitelist = 10,
+pub enum Timeout {
+    /// Fails with `WouldBlock` instead of waiting at all
+    Poll,
+    /// Fails with `TimedOut` after this many ticks
+    Ticks(u64),
+    /// Waits as long as it takes
+    Forever,
 }
 
-impl TryFrom<u64> for ReceiveStatus {
-    type Error = InvalidStatusCode;
+impl SyscallArg for Timeout {
+    fn into_arg(self) -> u64 {
+        match self {
+            Self::Poll => 0,
+            Self::Ticks(ticks) => ticks.clamp(1, u64::MAX - 1),
+            Self::Forever => u64::MAX,
+        }
+    }
 
-    fn try_from(value: u64) -> Result<Self, Self::Error> {
+    fn from_arg(value: u64) -> Self {
         match value {
-            0 => Ok(Self::Success),
-            10 => Ok(Self::InvalidWhitelist),
-            _ => Err(InvalidStatusCode),
+            0 => Self::Poll,
+            u64::MAX => Self::Forever,
+            ticks => Self::Ticks(ticks),
         }
     }
 }
 
-impl From<ReceiveStatus> for u8 {
-    fn from(value: ReceiveStatus) -> Self {
-        value as u8
+status_enum! {
+    pub enum ReplyStatus {
+        Success = 0,
+        /// The process isn't waiting on a reply from the current process
+        NotWaiting = 10,
     }
 }
 
-impl Status for ReceiveStatus {}
+status_enum! {
+    pub enum NotifyStatus {
+        Success = 0,
+        InvalidRecipient = 10,
+        Disabled = 11,
+        Blocked = 12,
+        /// The mailbox is at capacity and rejects new notifications
+        Full = 13,
+    }
+}
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-#[repr(u8)]
-pub enum NotifyStatus {
-    Success = 0,
-    InvalidRecipient = 10,
-    Disabled = 11,
-    Blocked = 12,
+status_enum! {
+    pub enum ReadMailboxStatus {
+        OneMessage = 0,
+        MoreMessages = 1,
+        NoMessages = 10,
+        Disabled = 11,
+    }
 }
 
-impl TryFrom<u64> for NotifyStatus {
-    type Error = InvalidStatusCode;
+#[derive(Clone, Copy, Debug)]
+pub struct ReceiveResponse {
+    pub status: ReceiveStatus,
+    pub message: Option<Message>,
+}
 
-    fn try_from(value: u64) -> Result<Self, Self::Error> {
-        match value {
-            0 => Ok(Self::Success),
-            10 => Ok(Self::InvalidRecipient),
-            11 => Ok(Self::Disabled),
-            12 => Ok(Self::Blocked),
-            _ => Err(InvalidStatusCode),
-        }
+impl From<ReceiveStatus> for ReceiveResponse {
+    fn from(value: ReceiveStatus) -> Self {
+        Self { status: value, message: None }
+    }
+}
+
+impl SyscallOutput for ReceiveResponse {
+    fn into_regs(self) -> [u64; 6] {
+        let message = self.message.unwrap_or_default();
+
+        [self.status as u64, message.pid, message.data0, message.data1, message.data2, message.data3]
+    }
+
+    fn from_regs(regs: [u64; 6]) -> Self {
+        let status = ReceiveStatus::from_code(regs[0]);
+        let message = if status.is_err() { None } else { Some(Message::from_regs(regs)) };
+
+        Self { status, message }
     }
 }
 
-impl From<NotifyStatus> for u8 {
-    fn from(value: NotifyStatus) -> Self {
-        value as u8
+#[derive(Clone, Copy, Debug)]
+pub struct WaitResponse {
+    pub status: WaitStatus,
+    pub message: Option<Message>,
+}
+
+impl From<WaitStatus> for WaitResponse {
+    fn from(value: WaitStatus) -> Self {
+        Self { status: value, message: None }
     }
 }
 
-impl Status for NotifyStatus {}
+impl SyscallOutput for WaitResponse {
+    fn into_regs(self) -> [u64; 6] {
+        let message = self.message.unwrap_or_default();
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-#[repr(u8)]
-pub enum ReadMailboxStatus {
-    OneMessage = 0,
-    MoreMessages = 1,
-    NoMessages = 10,
-    Disabled = 11,
+        [self.status as u64, message.pid, message.data0, message.data1, message.data2, message.data3]
+    }
+
+    fn from_regs(regs: [u64; 6]) -> Self {
+        let status = WaitStatus::from_code(regs[0]);
+        let message = if status.is_err() { None } else { Some(Message::from_regs(regs)) };
+
+        Self { status, message }
+    }
 }
 
-impl TryFrom<u64> for ReadMailboxStatus {
-    type Error = InvalidStatusCode;
+#[derive(Clone, Copy, Debug)]
+pub struct CallResponse {
+    pub status: SendStatus,
+    pub message: Option<Message>,
+}
 
-    fn try_from(value: u64) -> Result<Self, Self::Error> {
-        match value {
-            0 => Ok(Self::OneMessage),
-            1 => Ok(Self::MoreMessages),
-            10 => Ok(Self::NoMessages),
-            11 => Ok(Self::Disabled),
-            _ => Err(InvalidStatusCode),
-        }
+impl SyscallOutput for CallResponse {
+    fn into_regs(self) -> [u64; 6] {
+        let message = self.message.unwrap_or_default();
+
+        [self.status as u64, message.pid, message.data0, message.data1, message.data2, message.data3]
+    }
+
+    fn from_regs(regs: [u64; 6]) -> Self {
+        let status = SendStatus::from_code(regs[0]);
+        let message = if status.is_err() { None } else { Some(Message::from_regs(regs)) };
+
+        Self { status, message }
     }
 }
 
-impl From<ReadMailboxStatus> for u8 {
+#[derive(Clone, Copy, Debug)]
+pub struct ReadMailboxResponse {
+    pub status: ReadMailboxStatus,
+    pub message: Option<Message>,
+}
+
+impl From<ReadMailboxStatus> for ReadMailboxResponse {
     fn from(value: ReadMailboxStatus) -> Self {
-        value as u8
+        Self { status: value, message: None }
     }
 }
 
-impl Status for ReadMailboxStatus {}
+impl SyscallOutput for ReadMailboxResponse {
+    fn into_regs(self) -> [u64; 6] {
+        let message = self.message.unwrap_or_default();
+
+        [self.status as u64, message.pid, message.data0, message.data1, message.data2, message.data3]
+    }
+
+    fn from_regs(regs: [u64; 6]) -> Self {
+        let status = ReadMailboxStatus::from_code(regs[0]);
+        let message = if status.is_err() { None } else { Some(Message::from_regs(regs)) };
+
+        Self { status, message }
+    }
+}
 
 #[derive(Clone, Copy, Debug, Default)]
 pub struct Message {
@@ -140,6 +242,19 @@ pub struct Message {
     pub data3: u64,
 }
 
+impl Message {
+    /// Reads a message out of the registers returned by `receive`, `call`, `wait` or `read_mailbox`, ignoring the status in `rax`
+    fn from_regs(regs: [u64; 6]) -> Self {
+        Self {
+            pid: regs[1],
+            data0: regs[2],
+            data1: regs[3],
+            data2: regs[4],
+            data3: regs[5],
+        }
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct PayloadMessage {
     pub pid: Pid,
@@ -173,37 +288,77 @@ impl From<PayloadMessage> for Message {
     }
 }
 
+status_enum! {
+    pub enum ConfigMailboxStatus {
+        Success = 0,
+        InvalidWhitelist = 10,
+        /// The capacity is 0 or more than `MAX_MAILBOX_CAPACITY`
+        InvalidCapacity = 11,
+    }
+}
+
+/// How many notifications a mailbox holds until it's configured otherwise
+pub const DEFAULT_MAILBOX_CAPACITY: usize = 64;
+
+/// The most notifications a mailbox can be configured to hold
+pub const MAX_MAILBOX_CAPACITY: usize = 1024;
+
+/// What a full mailbox does with a new notification, every notification that doesn't make it in counts as dropped
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
-#[repr(u8)]
-pub enum ConfigMailboxStatus {
-    Success = 0,
-    InvalidWhitelist = 10,
+#[repr(u64)]
+pub enum OverflowPolicy {
+    /// Turns it away, the sender gets `NotifyStatus::Full`
+    Reject = 0,
+    /// Throws out the oldest notification to make room
+    DropOldest = 1,
+    /// Replaces the newest notification from the same sender, or turns it away if there isn't one
+    Coalesce = 2,
 }
 
-impl TryFrom<u64> for ConfigMailboxStatus {
-    type Error = InvalidStatusCode;
+impl SyscallArg for OverflowPolicy {
+    fn into_arg(self) -> u64 {
+        self as u64
+    }
 
-    fn try_from(value: u64) -> Result<Self, Self::Error> {
+    /// Unknown policies are treated as `Reject`
+    fn from_arg(value: u64) -> Self {
         match value {
-            0 => Ok(Self::Success),
-            10 => Ok(Self::InvalidWhitelist),
-            _ => Err(InvalidStatusCode),
+            1 => Self::DropOldest,
+            2 => Self::Coalesce,
+            _ => Self::Reject,
         }
     }
 }
 
-impl From<ConfigMailboxStatus> for u8 {
-    fn from(value: ConfigMailboxStatus) -> Self {
-        value as u8
-    }
+/// The state of the current process's mailbox
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MailboxInfo {
+    /// Notifications waiting to be read
+    pub queued: u64,
+    pub capacity: u64,
+    /// Notifications that didn't fit since the counter was last reset
+    pub dropped: u64,
 }
 
-impl Status for ConfigMailboxStatus {}
+impl SyscallOutput for MailboxInfo {
+    fn into_regs(self) -> [u64; 6] {
+        [0, self.queued, self.capacity, self.dropped, 0, 0]
+    }
+
+    fn from_regs(regs: [u64; 6]) -> Self {
+        Self {
+            queued: regs[1],
+            capacity: regs[2],
+            dropped: regs[3],
+        }
+    }
+}
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct MailboxFlags {
     pub enable: bool,
     pub set_whitelist: bool,
+    pub set_capacity: bool,
 }
 
 impl From<u64> for MailboxFlags {
@@ -211,6 +366,7 @@ impl From<u64> for MailboxFlags {
         Self {
             enable: (value & 0x1) > 0,
             set_whitelist: (value & 0x2) > 0,
+            set_capacity: (value & 0x4) > 0,
         }
     }
 }
@@ -219,5 +375,55 @@ impl From<MailboxFlags> for u64 {
     fn from(value: MailboxFlags) -> Self {
         (if value.enable { 0x1 } else { 0x0 })
         | (if value.set_whitelist { 0x2 } else { 0x0 })
+        | (if value.set_capacity { 0x4 } else { 0x0 })
     }
-}
\ No newline at end of file
+}
+
+impl SyscallArg for MailboxFlags {
+    fn into_arg(self) -> u64 {
+        self.into()
+    }
+
+    fn from_arg(value: u64) -> Self {
+        value.into()
+    }
+}
+
+/// What a `wait` returns for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WaitFlags {
+    /// Synchronous messages from processes in the whitelist, or any process if it's empty
+    pub receive: bool,
+    /// Notifications put in the mailbox
+    pub notify: bool,
+    /// Only notifications from the given sender
+    pub filter: bool,
+}
+
+impl From<u64> for WaitFlags {
+    fn from(value: u64) -> Self {
+        Self {
+            receive: (value & 0x1) > 0,
+            notify: (value & 0x2) > 0,
+            filter: (value & 0x4) > 0,
+        }
+    }
+}
+
+impl From<WaitFlags> for u64 {
+    fn from(value: WaitFlags) -> Self {
+        (if value.receive { 0x1 } else { 0x0 })
+        | (if value.notify { 0x2 } else { 0x0 })
+        | (if value.filter { 0x4 } else { 0x0 })
+    }
+}
+
+impl SyscallArg for WaitFlags {
+    fn into_arg(self) -> u64 {
+        self.into()
+    }
+
+    fn from_arg(value: u64) -> Self {
+        value.into()
+    }
+}
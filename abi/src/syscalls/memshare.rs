@@ -1,89 +1,87 @@
-use crate::syscalls::InvalidStatusCode;
+use crate::{Status, SyscallOutput, status_enum, ipc::Pid};
 
-use super::Status;
+use super::vm::MapFlags;
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-#[repr(u8)]
-pub enum CreateShareStatus {
-    Success = 0,
-    UnalignedStart = 10,
-    UnalignedEnd = 11,
-    OutOfBounds = 13,
-}
-
-impl CreateShareStatus {
-    pub fn is_err(&self) -> bool {
-        (*self as u64) >= 10
-    }
-}
-
-impl TryFrom<u64> for CreateShareStatus {
-    type Error = InvalidStatusCode;
-    
-    fn try_from(value: u64) -> Result<Self, Self::Error> {
-        match value {
-            0 => Ok(Self::Success),
-            10 => Ok(Self::UnalignedStart),
-            11 => Ok(Self::UnalignedEnd),
-            13 => Ok(Self::OutOfBounds),
-            _ => Err(InvalidStatusCode),
-        }
+status_enum! {
+    pub enum CreateShareStatus {
+        Success = 0,
+        UnalignedStart = 10,
+        UnalignedEnd = 11,
+        OutOfBounds = 13,
+        /// Part of the range isn't memory the process mapped with `map_memory` or the heap
+        NotOwned = 14,
+        /// There weren't enough frames to map the range or copy the parts of it that were copy-on-write
+        OutOfMemory = 15,
     }
 }
 
-impl From<CreateShareStatus> for u8 {
-    fn from(value: CreateShareStatus) -> Self {
-        value as u8
+status_enum! {
+    pub enum JoinShareStatus {
+        Success = 0,
+        UnalignedStart = 10,
+        UnalignedEnd = 11,
+        BlacklistClash = 12,
+        OutOfBounds = 13,
+        TooSmall = 14,
+        TooLarge = 15,
+        NotExists = 16,
+        NotAllowed = 17,
+        AlreadyMapped = 18,
+        /// The process asked for more access than the creator gave it
+        AccessDenied = 19,
+        /// The kernel was asked to pick an address, but there's no room left
+        NoSpace = 20,
     }
 }
 
-impl Status for CreateShareStatus {}
-
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-#[repr(u8)]
-pub enum JoinShareStatus {
-    Success = 0,
-    UnalignedStart = 10,
-    UnalignedEnd = 11,
-    BlacklistClash = 12,
-    OutOfBounds = 13,
-    TooSmall = 14,
-    TooLarge = 15,
-    NotExists = 16,
-    NotAllowed = 17,
-    AlreadyMapped = 18,
-}
-
-impl TryFrom<u64> for JoinShareStatus {
-    type Error = InvalidStatusCode;
-    
-    fn try_from(value: u64) -> Result<Self, Self::Error> {
-        match value {
-            0 => Ok(Self::Success),
-            10 => Ok(Self::UnalignedStart),
-            11 => Ok(Self::UnalignedEnd),
-            12 => Ok(Self::BlacklistClash),
-            13 => Ok(Self::OutOfBounds),
-            14 => Ok(Self::TooSmall),
-            15 => Ok(Self::TooLarge),
-            16 => Ok(Self::NotExists),
-            17 => Ok(Self::NotAllowed),
-            18 => Ok(Self::AlreadyMapped),
-            _ => Err(InvalidStatusCode),
-        }
+status_enum! {
+    pub enum AllocShareStatus {
+        Success = 0,
+        InvalidSize = 10,
+        InvalidWhitelist = 11,
+        NoSpace = 12,
+        OutOfMemory = 13,
     }
 }
 
-impl From<JoinShareStatus> for u8 {
-    fn from(value: JoinShareStatus) -> Self {
-        value as u8
+status_enum! {
+    pub enum LeaveShareStatus {
+        Success = 0,
+        NotExists = 10,
+        NotMember = 11,
     }
 }
 
-impl Status for JoinShareStatus {}
+status_enum! {
+    pub enum DestroyShareStatus {
+        Success = 0,
+        NotExists = 10,
+        /// Only the process that created a share can destroy it
+        NotOwner = 11,
+    }
+}
 
 pub type ShareId = u64;
 
+/// The access the creator of a share gives to one process
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ShareGrant {
+    pub pid: Pid,
+    /// `MapFlags` as a raw value, since grants get copied straight out of user memory
+    pub flags: u64,
+}
+
+impl ShareGrant {
+    pub fn new(pid: Pid, flags: MapFlags) -> Self {
+        Self { pid, flags: flags.into() }
+    }
+
+    pub fn flags(&self) -> MapFlags {
+        self.flags.into()
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct CreateShareResponse {
     pub status: CreateShareStatus,
@@ -94,4 +92,72 @@ impl From<CreateShareStatus> for CreateShareResponse {
     fn from(value: CreateShareStatus) -> Self {
         CreateShareResponse { status: value, id: None }
     }
-}
\ No newline at end of file
+}
+
+impl SyscallOutput for CreateShareResponse {
+    fn into_regs(self) -> [u64; 6] {
+        [self.status as u64, self.id.unwrap_or(0), 0, 0, 0, 0]
+    }
+
+    fn from_regs(regs: [u64; 6]) -> Self {
+        let status = CreateShareStatus::from_code(regs[0]);
+        let id = if status.is_err() { None } else { Some(regs[1]) };
+
+        Self { status, id }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct JoinShareResponse {
+    pub status: JoinShareStatus,
+    /// Where the share was mapped
+    pub start: u64,
+    /// How many bytes were mapped
+    pub size: u64,
+}
+
+impl From<JoinShareStatus> for JoinShareResponse {
+    fn from(value: JoinShareStatus) -> Self {
+        JoinShareResponse { status: value, start: 0, size: 0 }
+    }
+}
+
+impl SyscallOutput for JoinShareResponse {
+    fn into_regs(self) -> [u64; 6] {
+        [self.status as u64, self.start, self.size, 0, 0, 0]
+    }
+
+    fn from_regs(regs: [u64; 6]) -> Self {
+        Self { status: Status::from_code(regs[0]), start: regs[1], size: regs[2] }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct AllocShareResponse {
+    pub status: AllocShareStatus,
+    pub id: Option<ShareId>,
+    /// Where the share was mapped in the creator
+    pub start: u64,
+}
+
+impl From<AllocShareStatus> for AllocShareResponse {
+    fn from(value: AllocShareStatus) -> Self {
+        AllocShareResponse { status: value, id: None, start: 0 }
+    }
+}
+
+impl SyscallOutput for AllocShareResponse {
+    fn into_regs(self) -> [u64; 6] {
+        [self.status as u64, self.id.unwrap_or(0), self.start, 0, 0, 0]
+    }
+
+    fn from_regs(regs: [u64; 6]) -> Self {
+        let status = AllocShareStatus::from_code(regs[0]);
+
+        if status.is_err() {
+            return status.into();
+        }
+
+        Self { status, id: Some(regs[1]), start: regs[2] }
+    }
+}
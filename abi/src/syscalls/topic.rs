@@ -0,0 +1,86 @@
+use crate::{Status, SyscallOutput, status_enum};
+
+status_enum! {
+    pub enum CreateTopicStatus {
+        Success = 0,
+    }
+}
+
+status_enum! {
+    pub enum DestroyTopicStatus {
+        Success = 0,
+        NotExists = 10,
+        /// Only the process that created a topic can destroy it
+        NotOwner = 11,
+    }
+}
+
+status_enum! {
+    pub enum SubscribeTopicStatus {
+        Success = 0,
+        NotExists = 10,
+        /// Only the owner can subscribe processes other than the current one
+        AccessDenied = 11,
+        AlreadySubscribed = 12,
+    }
+}
+
+status_enum! {
+    pub enum UnsubscribeTopicStatus {
+        Success = 0,
+        NotExists = 10,
+        /// Only the owner can unsubscribe processes other than the current one
+        AccessDenied = 11,
+        NotSubscribed = 12,
+    }
+}
+
+status_enum! {
+    pub enum PublishTopicStatus {
+        Success = 0,
+        NotExists = 10,
+        /// Only the process that created a topic can publish to it
+        NotOwner = 11,
+    }
+}
+
+pub type TopicId = u64;
+
+#[derive(Clone, Copy, Debug)]
+pub struct CreateTopicResponse {
+    pub status: CreateTopicStatus,
+    pub id: TopicId,
+}
+
+impl SyscallOutput for CreateTopicResponse {
+    fn into_regs(self) -> [u64; 6] {
+        [self.status as u64, self.id, 0, 0, 0, 0]
+    }
+
+    fn from_regs(regs: [u64; 6]) -> Self {
+        Self { status: Status::from_code(regs[0]), id: regs[1] }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PublishTopicResponse {
+    pub status: PublishTopicStatus,
+    /// How many subscribers got the event, the rest had full, disabled or blocking mailboxes
+    pub delivered: u64,
+}
+
+impl From<PublishTopicStatus> for PublishTopicResponse {
+    fn from(value: PublishTopicStatus) -> Self {
+        Self { status: value, delivered: 0 }
+    }
+}
+
+impl SyscallOutput for PublishTopicResponse {
+    fn into_regs(self) -> [u64; 6] {
+        [self.status as u64, self.delivered, 0, 0, 0, 0]
+    }
+
+    fn from_regs(regs: [u64; 6]) -> Self {
+        Self { status: Status::from_code(regs[0]), delivered: regs[1] }
+    }
+}
@@ -0,0 +1,144 @@
+use crate::{Status, SyscallArg, SyscallOutput, status_enum};
+
+pub use crate::layout::{HEAP_START, HEAP_MAX, MAP_START, MAP_END};
+
+status_enum! {
+    pub enum GrowHeapStatus {
+        Success = 0,
+        TooLarge = 10,
+        OutOfMemory = 11,
+        AlreadyMapped = 12,
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct GrowHeapResponse {
+    pub status: GrowHeapStatus,
+    /// Start of the newly mapped pages, which is the old end of the heap
+    pub start: u64,
+    /// Number of bytes mapped, rounded up to whole pages
+    pub size: u64,
+}
+
+impl From<GrowHeapStatus> for GrowHeapResponse {
+    fn from(value: GrowHeapStatus) -> Self {
+        GrowHeapResponse { status: value, start: 0, size: 0 }
+    }
+}
+
+impl SyscallOutput for GrowHeapResponse {
+    fn into_regs(self) -> [u64; 6] {
+        [self.status as u64, self.start, self.size, 0, 0, 0]
+    }
+
+    fn from_regs(regs: [u64; 6]) -> Self {
+        let status = GrowHeapStatus::from_code(regs[0]);
+
+        if status.is_err() {
+            return status.into();
+        }
+
+        Self { status, start: regs[1], size: regs[2] }
+    }
+}
+
+/// Access to pages mapped with `map_memory` or a memory share, they can always be read
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MapFlags {
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl MapFlags {
+    /// Checks if this allows everything `other` does
+    pub fn contains(self, other: MapFlags) -> bool {
+        (self.write || !other.write) && (self.execute || !other.execute)
+    }
+}
+
+impl From<u64> for MapFlags {
+    fn from(value: u64) -> Self {
+        Self {
+            write: (value & 0x1) > 0,
+            execute: (value & 0x2) > 0,
+        }
+    }
+}
+
+impl From<MapFlags> for u64 {
+    fn from(value: MapFlags) -> Self {
+        (if value.write { 0x1 } else { 0x0 })
+        | (if value.execute { 0x2 } else { 0x0 })
+    }
+}
+
+impl SyscallArg for MapFlags {
+    fn into_arg(self) -> u64 {
+        self.into()
+    }
+
+    fn from_arg(value: u64) -> Self {
+        value.into()
+    }
+}
+
+status_enum! {
+    pub enum MapMemoryStatus {
+        Success = 0,
+        Unaligned = 10,
+        OutOfBounds = 11,
+        InvalidSize = 12,
+        AlreadyMapped = 13,
+        OutOfMemory = 14,
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MapMemoryResponse {
+    pub status: MapMemoryStatus,
+    /// Where the pages were mapped
+    pub start: u64,
+}
+
+impl From<MapMemoryStatus> for MapMemoryResponse {
+    fn from(value: MapMemoryStatus) -> Self {
+        MapMemoryResponse { status: value, start: 0 }
+    }
+}
+
+impl SyscallOutput for MapMemoryResponse {
+    fn into_regs(self) -> [u64; 6] {
+        [self.status as u64, self.start, 0, 0, 0, 0]
+    }
+
+    fn from_regs(regs: [u64; 6]) -> Self {
+        let status = MapMemoryStatus::from_code(regs[0]);
+
+        if status.is_err() {
+            return status.into();
+        }
+
+        Self { status, start: regs[1] }
+    }
+}
+
+status_enum! {
+    pub enum UnmapMemoryStatus {
+        Success = 0,
+        Unaligned = 10,
+        OutOfBounds = 11,
+        InvalidSize = 12,
+    }
+}
+
+status_enum! {
+    pub enum ProtectMemoryStatus {
+        Success = 0,
+        Unaligned = 10,
+        OutOfBounds = 11,
+        InvalidSize = 12,
+        NotMapped = 13,
+        /// Part of the range is a memory share or a device, whose access was fixed when it was mapped
+        NotOwned = 14,
+    }
+}
@@ -1,137 +1,66 @@
-use std::{ipc::{Message, PayloadMessage}, graphics::{DrawBitmapStatus, DrawStringStatus}, serial_println};
+use std::{ipc::Pid, graphics::{DrawBitmapStatus, DrawStringStatus, Server}};
 
-use alloc::{slice, string::String, format};
+use alloc::string::String;
 
 use crate::{drawing, font::{self, Font}, tty::Tty};
 
-pub fn draw_bitmap(request: PayloadMessage) -> Message {
-    let PayloadMessage { pid, data0, data1, payload, payload_len } = request;
-
-    let data0_bytes = data0.to_le_bytes();
+/// Everything the graphics server's commands draw with
+pub struct Graphics<'a> {
+    pub font: &'a Font,
+    pub tty: Tty<'a>,
+}
 
-    let x = data0_bytes[5] as u16 | ((data0_bytes[6] as u16) << 8);
-    let y = data0_bytes[3] as u16 | ((data0_bytes[4] as u16) << 8);
-    let color = data0_bytes[1] as u16 | ((data0_bytes[2] as u16) << 8);
+impl Server for Graphics<'_> {
+    fn draw_bitmap(&mut self, _from: Pid, x: u16, y: u16, color: u16, width: u16, height: u16, scale: u8, bitmap: &[u8]) -> DrawBitmapStatus {
+        if width as usize * height as usize != bitmap.len() {
+            return DrawBitmapStatus::InvalidLength;
+        }
 
-    let data1_bytes = data1.to_le_bytes();
+        let x_max = drawing::FB.width;
+        let y_max = drawing::FB.height;
 
-    let width = data1_bytes[6] as u16 | ((data1_bytes[7] as u16) << 8);
-    let height = data1_bytes[4] as u16 | ((data1_bytes[5] as u16) << 8);
-    let scale = data1_bytes[0] as u8;
+        // Bounds checking
+        if x as u64 + width as u64 * 8 * scale as u64 >= x_max {
+            return DrawBitmapStatus::TooWide;
+        } else if y as u64 + height as u64 * scale as u64 >= y_max {
+            return DrawBitmapStatus::TooTall;
+        }
 
+        drawing::draw_bitmap(bitmap, x as usize, y as usize, color, width as usize, height as usize, scale as usize);
 
-    if width as u64 * height as u64 != payload_len {
-        return Message {
-            pid,
-            data0: DrawBitmapStatus::InvalidLength as u64,
-            ..Default::default()
-        };
+        DrawBitmapStatus::Success
     }
 
-    let bitmap_ptr = payload as *const u8;
-    let bitmap = unsafe { slice::from_raw_parts(bitmap_ptr, payload_len as usize) };
-
-    let x_max = drawing::FB.width;
-    let y_max = drawing::FB.height;
-
-    // Bounds checking
-    if x as u64 + width as u64 * 8 * scale as u64 >= x_max {
-        return Message {
-            pid,
-            data0: DrawBitmapStatus::TooWide as u64,
-            ..Default::default()
-        };
-    } else if y as u64 + height as u64 * scale as u64 >= y_max {
-        return Message {
-            pid,
-            data0: DrawBitmapStatus::TooTall as u64,
-            ..Default::default()
+    fn draw_string(&mut self, _from: Pid, x: u16, y: u16, color: u16, scale: u8, text: &[u8]) -> DrawStringStatus {
+        let Ok(text) = String::from_utf8(text.into()) else {
+            return DrawStringStatus::InvalidUtf8;
         };
-    }
 
-    drawing::draw_bitmap(bitmap, x as usize, y as usize, color, width as usize, height as usize, scale as usize);
+        let max_width = drawing::FB.width;
+        let max_height = drawing::FB.height;
 
-    Message {
-        pid,
-        data0: DrawBitmapStatus::Success as u64,
-        ..Default::default()
-    }
-}
+        if x as u64 + text.len() as u64 * scale as u64 > max_width {
+            return DrawStringStatus::TooWide;
+        } else if y as u64 + 16 * scale as u64 > max_height {
+            return DrawStringStatus::TooTall;
+        }
 
-pub fn draw_string(request: PayloadMessage, font: &Font) -> Message {
-    let PayloadMessage { pid, data0, data1: _, payload, payload_len } = request;
-
-    let data0_bytes = data0.to_le_bytes();
-    let x = data0_bytes[5] as u16 | ((data0_bytes[6] as u16) << 8);
-    let y = data0_bytes[3] as u16 | ((data0_bytes[4] as u16) << 8);
-    let color = data0_bytes[1] as u16 | ((data0_bytes[2] as u16) << 8);
-    let scale = data0_bytes[0];
-    
-    let payload_ptr = payload as *const u8;
-    let payload_bytes = unsafe { slice::from_raw_parts(payload_ptr, payload_len as usize) };
-    let Ok(text) = String::from_utf8(payload_bytes.into()) else {
-        return Message {
-            pid,
-            data0: DrawStringStatus::InvalidUtf8 as u64,
-            ..Default::default()
-        };
-    };
+        for (i, c) in text.chars().enumerate() {
+            let bitmap = self.font.get_char(c).unwrap_or(&font::FALLBACK_CHAR);
 
-    let max_width = drawing::FB.width;
-    let max_height = drawing::FB.height;
+            drawing::draw_bitmap(bitmap, x as usize + i * 8 * scale as usize, y as usize, color, 1, 16, scale as usize);
+        }
 
-    if x as u64 + text.len() as u64 * scale as u64 > max_width {
-        return Message {
-            pid,
-            data0: DrawStringStatus::TooWide as u64,
-            ..Default::default()
-        };
-    } else if y as u64 + 16 * scale as u64 > max_height {
-        return Message {
-            pid,
-            data0: DrawStringStatus::TooTall as u64,
-            ..Default::default()
-        };
+        DrawStringStatus::Success
     }
 
-    for (i, c) in text.chars().enumerate() {
-        let bitmap = font.get_char(c).unwrap_or(&font::FALLBACK_CHAR);
-
-        drawing::draw_bitmap(bitmap, x as usize + i * 8 * scale as usize, y as usize, color, 1, 16, scale as usize);
-    }
-
-    Message {
-        pid,
-        data0: DrawStringStatus::Success as u64,
-        ..Default::default()
-    }
-}
-
-pub fn print(request: PayloadMessage, tty: &mut Tty) -> Message {
-    // serial_println!("[GRAPHICS] Trying to print");
-
-    let PayloadMessage { pid, data0: _, data1: _, payload, payload_len } = request;
-
-    let payload_ptr = payload as *const u8;
-
-    // serial_println!("[GRAPHICS] Assembling string");
-    let payload_bytes = unsafe { slice::from_raw_parts(payload_ptr, payload_len as usize) };
-    // serial_println!("[GRAPHICS] Bytes assembled");
-
-    let Ok(text) = String::from_utf8(payload_bytes.into()) else {
-        return Message {
-            pid,
-            data0: DrawStringStatus::InvalidUtf8 as u64,
-            ..Default::default()
+    fn print(&mut self, _from: Pid, text: &[u8]) -> DrawStringStatus {
+        let Ok(text) = String::from_utf8(text.into()) else {
+            return DrawStringStatus::InvalidUtf8;
         };
-    };
 
-    tty.write_str(&text);
-    // serial_println!("[GRAPHICS] {}", text);
+        self.tty.write_str(&text);
 
-    Message { 
-        pid,
-        data0: DrawStringStatus::Success as u64,
-        ..Default::default()
+        DrawStringStatus::Success
     }
-}
\ No newline at end of file
+}
@@ -9,13 +9,10 @@ use crate::font::{self, Font};
 
 lazy_static! {
     pub static ref FB: FramebufferDescriptor = {
-        let (status, fb_descriptor) = request_fb();
-
-        if status as u64 >= 10 {
-            panic!("[GRAPHICS] Request for framebuffer was denied: {:?}", status);
+        match request_fb() {
+            Ok(descriptor) => descriptor,
+            Err(e) => panic!("[GRAPHICS] Request for framebuffer was denied: {}", e),
         }
-
-        fb_descriptor.unwrap()
     };
 
     static ref DOUBLE_BUFFER: Mutex<Vec<u8>> = {
@@ -12,13 +12,13 @@ pub mod font;
 pub mod tty;
 
 use core::fmt::{Arguments, Write};
-use std::{config_rbuffer, ipc::{notify, receive}, serial_println, sys_yield};
-use std::graphics::Command;
+use std::{config_rbuffer, ipc::{Message, next_request}, serial_println, sys_yield};
+use std::graphics::dispatch;
 
 use alloc::{borrow::ToOwned, fmt, string, vec};
 use drawing::FB;
 
-use crate::font::unpack_psf;
+use crate::{commands::Graphics, font::unpack_psf};
 
 const TTY_COLOR: u16 = 0xDDDD;
 const TTY_SCALE: usize = 1;
@@ -27,28 +27,26 @@ const TTY_SCALE: usize = 1;
 pub unsafe extern "C" fn _start() {
     serial_println!("[GRAPHICS] Started");
 
-    config_rbuffer(4096);
+    // every request is answered before the next one is received, so one payload at a time is enough
+    // big enough for a full screen bitmap, which gets shared instead of copied if the client page aligns it
+    config_rbuffer(0x10_0000, 1).unwrap();
 
     let psf = {
         let psf = include_bytes!("./font/cp850-8x16.psfu");
         unpack_psf(psf)
     };
 
-    let mut tty = tty::Tty::new(TTY_COLOR, TTY_SCALE, &FB, &psf);
+    let tty = tty::Tty::new(TTY_COLOR, TTY_SCALE, &FB, &psf);
+    let mut server = Graphics { font: &psf, tty };
 
-    loop {
-        let request = receive(&[]);
-        let opcode = (request.data0 >> 56) & 0xFF;
-        let Ok(command): Result<Command, _> = opcode.try_into() else {
-            panic!("[GRAPHICS] Invalid command: {:#04X}", opcode);
-        };
+    let mut response: Option<Message> = None;
 
-        let response = match command  {
-            Command::draw_bitmap => commands::draw_bitmap(request.into()),
-            Command::draw_string => commands::draw_string(request.into(), &psf),
-            Command::print => commands::print(request.into(), &mut tty),
+    loop {
+        // answer the last request while waiting for the next one
+        let Ok((request, payload)) = next_request(response.take()) else {
+            continue;
         };
 
-        notify(response);
+        response = Some(dispatch(&mut server, &request, payload));
     }
 }
\ No newline at end of file
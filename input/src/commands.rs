@@ -1,69 +1,49 @@
-use std::{ipc::{Message, Pid, notify}, print};
+use std::{ipc::Pid, topic::{TopicId, subscribe_topic, publish_topic}, encoding::MessageData, print};
 
-use alloc::vec::Vec;
-use std::input::{PublishStatus, SubscribeStatus};
-use pc_keyboard::{KeyboardLayout, ScancodeSet, Keyboard, KeyState};
+use std::input::{PublishStatus, SubscribeStatus, KeyCode, KeyEvent, KeyState, Server};
+use pc_keyboard::{KeyboardLayout, ScancodeSet, Keyboard};
 
 use crate::handling::decode;
 
-pub fn publish<T: KeyboardLayout, S: ScancodeSet>(request: Message, keyboard: &mut Keyboard<T, S>, subscribers: &Vec<Pid>) -> Message {
-    let Message { pid, data0, data1: _, data2: _, data3: _ } = request;
+/// The keyboard the kernel publishes bytes from, and the topic its keys are passed on to
+pub struct Input<T: KeyboardLayout, S: ScancodeSet> {
+    pub keyboard: Keyboard<T, S>,
+    pub topic: TopicId,
+}
 
-    if pid != 0 {
-        return Message {
-            pid,
-            data0: PublishStatus::MissingPermissions as u64,
-            ..Default::default()
-        };
-    }
+impl<T: KeyboardLayout, S: ScancodeSet> Server for Input<T, S> {
+    fn publish(&mut self, from: Pid, scancode: u8) -> PublishStatus {
+        if from != 0 {
+            return PublishStatus::MissingPermissions;
+        }
 
-    let scancode = ((data0 >> 48) & 0xFF) as u8;
+        let Some(key) = decode(scancode, &mut self.keyboard) else {
+            return PublishStatus::InvalidKey;
+        };
 
-    let Some(key) = decode(scancode, keyboard) else {
-        return Message {
-            pid,
-            data0: PublishStatus::InvalidKey as u64,
-            ..Default::default()
+        // both key code enums come from pc_keyboard, so they line up
+        let Ok(code) = KeyCode::try_from(key.code as u8) else {
+            return PublishStatus::InvalidKey;
         };
-    };
 
-    let state_bit = if key.state == KeyState::Down { 0x100 } else { 0x100 };
+        let state = match key.state {
+            pc_keyboard::KeyState::Up => KeyState::Up,
+            _ => KeyState::Down,
+        };
 
-    let data1 = (key.code as u8 as u64) | state_bit;
+        print!("{:?}", key.code);
 
-    print!("{:?}", key.code);
+        // the kernel drops subscribers when they exit, and ones with full mailboxes just miss the key
+        let _ = publish_topic(self.topic, KeyEvent { code, state }.encode());
 
-    for s in subscribers.iter() {
-        notify(Message {
-            pid: *s,
-            data0: PublishStatus::IncomingKey as u64 | data1,
-            ..Default::default()
-        });
+        PublishStatus::Success
     }
 
-    Message {
-        pid,
-        data0: PublishStatus::Success as u64,
-        ..Default::default()
-    }
-}
-
-pub fn subscribe(request: Message, subscribers: &mut Vec<Pid>) -> Message {
-    let Message { pid, data0: _, data1: _, data2: _, data3: _ } = request;
-
-    if subscribers.contains(&pid) {
-        Message {
-            pid,
-            data0: SubscribeStatus::AlreadySubscribed as u64,
-            ..Default::default()
-        }
-    } else {
-        subscribers.push(pid);
-
-        Message {
-            pid,
-            data0: SubscribeStatus::Success as u64,
-            ..Default::default()
+    fn subscribe(&mut self, from: Pid) -> SubscribeStatus {
+        match subscribe_topic(self.topic, from) {
+            Ok(()) => SubscribeStatus::Success,
+            // the topic is this server's own, so being subscribed already is the only way it can fail
+            Err(_) => SubscribeStatus::AlreadySubscribed,
         }
     }
-}
\ No newline at end of file
+}
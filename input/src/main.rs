@@ -6,45 +6,47 @@ extern crate alloc;
 mod commands;
 mod handling;
 
-use std::{ipc::{Pid, notify, set_mailbox_enabled}, println, await_notif_from, await_notif, Status, getpid, print};
+use std::{ipc::{WaitStatus, reply, set_mailbox_enabled, take_dropped_notifs, wait}, topic::create_topic, println, serial_println, Status, getpid, print};
 
-use alloc::vec::Vec;
-use std::input::Command;
+use std::input::dispatch;
 use pc_keyboard::{Keyboard, ScancodeSet1, layouts::Us104Key};
 
+use crate::commands::Input;
+
 #[no_mangle]
 pub unsafe extern "C" fn _start() {
-    let mut subscribers: Vec<Pid> = Vec::new();
-    let mut keyboard = Keyboard::new(ScancodeSet1::new(), Us104Key, pc_keyboard::HandleControl::Ignore);
+    let keyboard = Keyboard::new(ScancodeSet1::new(), Us104Key, pc_keyboard::HandleControl::Ignore);
+    let mut server = Input { keyboard, topic: create_topic() };
 
-    set_mailbox_enabled(true);
+    set_mailbox_enabled(true).unwrap();
     // println!("gup");
 
     let mut counter = 0;
 
     loop {
-        let (status, request) = await_notif_from(0, 0).unwrap();
-
-        if status.is_err() {
+        // keys are published by the kernel as notifications, everything else is a call
+        let Ok((kind, request)) = wait(&[], Some(0)) else {
             // println!("[{}] Error: {:?}", getpid(), status);
             continue;
-        }
+        };
 
-        let request = request.unwrap();
+        // keys that came in while the mailbox was full are gone, but at least say so
+        let dropped = take_dropped_notifs();
 
-        let opcode = (request.data0 >> 56) & 0xFF;
-        let Ok(command): Result<Command, _> = opcode.try_into() else {
-            panic!("[INPUT] Invalid command: {:#04X}", opcode);
-        };
+        if dropped > 0 {
+            serial_println!("[INPUT] Dropped {} keys", dropped);
+        }
 
         print!("{:03} ", counter);
         counter += 1;
 
-        let response = match command {
-            Command::publish => commands::publish(request, &mut keyboard, &subscribers),
-            Command::subscribe => commands::subscribe(request, &mut subscribers),
-        };
+        let response = dispatch(&mut server, &request, kind == WaitStatus::ReceivedPayload);
 
-        notify(response);
+        // the kernel doesn't wait for a reply
+        if kind != WaitStatus::Notified {
+            if let Err(e) = reply(response) {
+                serial_println!("[INPUT] Couldn't reply to {}: {}", response.pid, e);
+            }
+        }
     }
 }
\ No newline at end of file
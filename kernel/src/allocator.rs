@@ -1,8 +1,9 @@
-use core::{alloc::GlobalAlloc, ptr};
+use core::{alloc::{GlobalAlloc, Layout}, mem::size_of, ptr};
 
 use spin::Mutex;
+use x86_64::{VirtAddr, structures::paging::{Page, PageTableFlags, Mapper, FrameAllocator, FrameDeallocator, Size4KiB}};
 
-use crate::serial_println;
+use crate::{memory, serial_println};
 
 extern "C" {
     fn _initial_kernel_heap_start();
@@ -13,6 +14,21 @@ extern "C" {
 const HEAP_START: *mut u8 = _initial_kernel_heap_start as _;
 const HEAP_SIZE: *const () = _initial_kernel_heap_size as _;
 
+/// Once the initial heap runs out, more pages get mapped starting here
+///
+/// This is in the upper half, so the mappings are shared by every address space
+const GROWTH_START: u64 = 0xFFFF_C000_0000_0000;
+const GROWTH_MAX: u64 = 0x0000_0040_0000_0000;
+/// The heap grows by at least this much at a time
+const GROWTH_STEP: usize = 64 * 1024;
+/// How many free bytes the heap keeps mapped ahead of time
+///
+/// The heap can't grow while the frame allocator is locked, so code holding it allocates out of this
+const RESERVE: usize = 64 * 1024;
+
+/// Every block is a multiple of this size, so a free block always fits in any gap
+const BLOCK_ALIGN: usize = size_of::<FreeBlock>();
+
 struct Allocator(Mutex<Option<Heap>>);
 
 #[global_allocator]
@@ -20,69 +36,61 @@ static ALLOCATOR: Allocator = Allocator(Mutex::new(None));
 
 /// Align the given address `addr` upwards to alignment `align`.
 fn align_up(addr: usize, align: usize) -> usize {
-    let remainder = addr % align;
-    addr + (align - remainder)
+    (addr + align - 1) & !(align - 1)
 }
 
-unsafe impl GlobalAlloc for Allocator {
-    unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
-        let mut bump = self.0.lock();
-        let bump = bump.as_mut().expect("Heap allocator should be initialized");
-
-        let alloc_start = align_up(bump.next as usize, layout.align());
-        let alloc_end = match alloc_start.checked_add(layout.size()) {
-            Some(end) => end,
-            None => return ptr::null_mut(),
-        };
+/// Rounds a layout up to the size and alignment every block in the heap has
+fn block_layout(layout: Layout) -> (usize, usize) {
+    let size = align_up(layout.size().max(1), BLOCK_ALIGN);
+    let align = layout.align().max(BLOCK_ALIGN);
 
-        if alloc_end > bump.heap_end {
-            ptr::null_mut() // out of memory
-        } else {
-            bump.next = alloc_end as _;
-            bump.allocations += 1;
-            
-            alloc_start as *mut u8
-        }
-    }
-
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: core::alloc::Layout) {
-        let mut bump = self.0.lock();
-        let bump = bump.as_mut().expect("Heap allocator should be initialized");
+    (size, align)
+}
 
-        bump.allocations -= 1;
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.0.lock();
+        let heap = heap.as_mut().expect("Heap allocator should be initialized");
 
-        if bump.allocations == 0 {
-            bump.next = bump.heap_start;
-        }
-    }
-}
+        let (size, align) = block_layout(layout);
 
-pub fn init_heap() {
-    // let heap_start = VirtAddr::new(HEAP_START as u64);
-    // let heap_end = heap_start + HEAP_SIZE as usize - 1u64;
-    // let heap_start_page = Page::containing_address(heap_start);
-    // let heap_end_page = Page::containing_address(heap_end);
+        let ptr = match heap.take(size, align) {
+            Some(ptr) => ptr,
+            None => {
+                // aligning could waste up to `align` bytes at the start of the new region
+                if !heap.grow(size + align) {
+                    return ptr::null_mut(); // out of memory
+                }
 
-    // let page_range = Page::range_inclusive(heap_start_page, heap_end_page);
+                let Some(ptr) = heap.take(size, align) else {
+                    return ptr::null_mut();
+                };
 
-    // serial_println!("Initializing allocator...");
+                ptr
+            }
+        };
 
-    // for (i, page) in page_range.enumerate() {
-    //     let frame = frame_allocator
-    //         .allocate_frame()
-    //         .ok_or(MapToError::FrameAllocationFailed)?;
+        // top the reserve back up while the frame allocator is free, it's fine if it isn't
+        if heap.size - heap.live < RESERVE {
+            heap.grow(RESERVE);
+        }
 
-    //     let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::GLOBAL;
+        ptr
+    }
 
-    //     unsafe {
-    //         mapper.map_to(page, frame, flags, frame_allocator)?.flush();
-    //     }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut heap = self.0.lock();
+        let heap = heap.as_mut().expect("Heap allocator should be initialized");
 
-    //     serial_println!("{}", i);
-    // }
+        let (size, _) = block_layout(layout);
 
-    // serial_println!("   Heap allocated");
+        heap.insert(ptr as usize, size);
+        heap.live -= size;
+        heap.allocations -= 1;
+    }
+}
 
+pub fn init_heap() {
     unsafe {
         *ALLOCATOR.0.lock() = Some(Heap::new(HEAP_START, HEAP_SIZE as usize));
     }
@@ -90,28 +98,185 @@ pub fn init_heap() {
     serial_println!("Allocator initialized");
 }
 
+/// Returns the current usage of the kernel heap
+pub fn stats() -> HeapStats {
+    let heap = ALLOCATOR.0.lock();
+    let heap = heap.as_ref().expect("Heap allocator should be initialized");
+
+    HeapStats {
+        size: heap.size,
+        live: heap.live,
+        peak: heap.peak,
+        allocations: heap.allocations,
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct HeapStats {
+    /// Total bytes the heap covers, including growth
+    pub size: usize,
+    /// Bytes currently allocated
+    pub live: usize,
+    /// The most bytes that have been allocated at once
+    pub peak: usize,
+    pub allocations: usize,
+}
+
+/// Header written at the start of every free block
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+/// A linked list allocator, with free blocks kept sorted by address so neighbours can be merged
 pub struct Heap {
-    pub heap_start: *mut u8,
-    pub heap_end: usize,
-    pub next: *mut u8,
-    pub allocations: usize,   
+    /// First free block
+    head: *mut FreeBlock,
+    /// End of the mapped part of the growth region
+    growth_end: u64,
+    pub size: usize,
+    pub live: usize,
+    pub peak: usize,
+    pub allocations: usize,
 }
 
 unsafe impl Send for Heap {}
 
 impl Heap {
-    /// Initializes the bump allocator with the given heap bounds.
-    /// 
+    /// Initializes the allocator with the given heap bounds.
+    ///
     /// # Safety
-    /// 
+    ///
     /// The caller must ensure that the given memory range is unused.
     /// Also, this method must be called only once.
     pub unsafe fn new(heap_start: *mut u8, heap_size: usize) -> Self {
-        Self {
-            heap_start,
-            heap_end: heap_start.offset(heap_size as isize) as _,
-            next: heap_start,
+        let mut heap = Self {
+            head: ptr::null_mut(),
+            growth_end: GROWTH_START,
+            size: 0,
+            live: 0,
+            peak: 0,
             allocations: 0,
+        };
+
+        let start = align_up(heap_start as usize, BLOCK_ALIGN);
+        let end = (heap_start as usize + heap_size) & !(BLOCK_ALIGN - 1);
+
+        heap.insert(start, end - start);
+        heap.size = end - start;
+
+        heap
+    }
+
+    /// Removes `size` bytes aligned to `align` from the first free block they fit in
+    unsafe fn take(&mut self, size: usize, align: usize) -> Option<*mut u8> {
+        let mut prev: *mut *mut FreeBlock = &mut self.head;
+
+        while !(*prev).is_null() {
+            let block = *prev;
+            let block_start = block as usize;
+            let block_end = block_start + (*block).size;
+
+            let alloc_start = align_up(block_start, align);
+            let alloc_end = alloc_start + size;
+
+            if alloc_end > block_end {
+                prev = &mut (*block).next;
+                continue;
+            }
+
+            *prev = (*block).next;
+
+            // whatever's left on either side is a multiple of BLOCK_ALIGN, so it can go back in the list
+            if alloc_start > block_start {
+                self.insert(block_start, alloc_start - block_start);
+            }
+
+            if block_end > alloc_end {
+                self.insert(alloc_end, block_end - alloc_end);
+            }
+
+            self.live += size;
+            self.peak = self.peak.max(self.live);
+            self.allocations += 1;
+
+            return Some(alloc_start as *mut u8);
+        }
+
+        None
+    }
+
+    /// Adds a free block to the list, merging it with the blocks next to it
+    unsafe fn insert(&mut self, start: usize, size: usize) {
+        let mut prev: *mut FreeBlock = ptr::null_mut();
+        let mut next = self.head;
+
+        while !next.is_null() && (next as usize) < start {
+            prev = next;
+            next = (*next).next;
+        }
+
+        let block = start as *mut FreeBlock;
+        block.write(FreeBlock { size, next });
+
+        if !next.is_null() && start + size == next as usize {
+            (*block).size += (*next).size;
+            (*block).next = (*next).next;
+        }
+
+        if prev.is_null() {
+            self.head = block;
+        } else if prev as usize + (*prev).size == start {
+            (*prev).size += (*block).size;
+            (*prev).next = (*block).next;
+        } else {
+            (*prev).next = block;
         }
     }
-}
\ No newline at end of file
+
+    /// Maps at least `min_size` more bytes onto the end of the growth region
+    ///
+    /// Fails instead of waiting if the frame allocator is already locked, since whoever holds it might be the one allocating
+    unsafe fn grow(&mut self, min_size: usize) -> bool {
+        let size = align_up(min_size.max(GROWTH_STEP), 4096);
+
+        if self.growth_end + size as u64 > GROWTH_START + GROWTH_MAX {
+            return false;
+        }
+
+        let Some(mut frame_allocator) = memory::PHYS_ALLOCATOR.try_lock() else {
+            return false;
+        };
+
+        let Some(frame_allocator) = frame_allocator.0.as_mut() else {
+            return false;
+        };
+
+        let mut mapper = memory::get_mapper();
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::GLOBAL;
+
+        let start = VirtAddr::new(self.growth_end);
+        let start_page: Page<Size4KiB> = Page::containing_address(start);
+        let end_page: Page<Size4KiB> = Page::containing_address(start + (size - 1));
+
+        for page in Page::range_inclusive(start_page, end_page) {
+            let Some(frame) = frame_allocator.allocate_frame() else {
+                return false;
+            };
+
+            let Ok(flush) = mapper.map_to(page, frame, flags, frame_allocator) else {
+                frame_allocator.deallocate_frame(frame);
+                return false;
+            };
+
+            flush.flush();
+
+            // keep whatever got mapped, even if a later page fails
+            self.insert(page.start_address().as_u64() as usize, 4096);
+            self.growth_end += 4096;
+            self.size += 4096;
+        }
+
+        true
+    }
+}
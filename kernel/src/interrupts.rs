@@ -1,6 +1,6 @@
-use core::{default, arch::asm};
+use core::{default, arch::asm, sync::atomic::{AtomicU64, Ordering}};
 
-use abi::{ipc::Message, input};
+use abi::{input, ipc::Message};
 use lazy_static::lazy_static;
 use pic8259::ChainedPics;
 use x86_64::{
@@ -12,7 +12,7 @@ use x86_64::{
     instructions::{port::Port, interrupts::without_interrupts}, registers::control::Cr3,
 };
 
-use crate::{serial_print, serial_println, memory::{self, HARDWARE_IST_INDEX}, serial::SERIAL1, ipc::notify, process::SCHEDULER};
+use crate::{serial_print, serial_println, memory::{self, HARDWARE_IST_INDEX}, serial::SERIAL1, ipc::notify, process::{SCHEDULER, vma}};
 
 /// Offset used for PIC 1
 pub const PIC_1_OFFSET: u8 = 0x20;
@@ -20,6 +20,14 @@ pub const PIC_1_OFFSET: u8 = 0x20;
 /// Offset used for PIC 2
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 
+/// Number of timer interrupts since boot
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Gets the number of timer interrupts since boot
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
 /// Both PICs
 pub static PICS: spin::Mutex<ChainedPics> = spin::Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
@@ -98,6 +106,20 @@ extern "x86-interrupt" fn page_fault_handler(stack_frame: InterruptStackFrame, e
     let addr = Cr2::read();
     let cr3 = Cr3::read();
 
+    // writing to a copy-on-write page and touching a reserved page are the only faults that aren't bugs
+    if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE)
+        && unsafe { memory::cow::handle_write_fault(addr) }
+    {
+        return;
+    }
+
+    if !error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+        && addr.as_u64() < memory::user::USER_END
+        && unsafe { vma::populate_current(addr) }
+    {
+        return;
+    }
+
     serial_println!("page fault for addr {:#018X} ({:?}) [{:#018X?}]", addr, error_code, cr3.0);
 
     // serial_println!("pml4 @ {:#018X}", cr3.start_address());
@@ -149,6 +171,8 @@ extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFram
 #[no_mangle]
 extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
     serial_print!(".");
+
+    TICKS.fetch_add(1, Ordering::Relaxed);
     
     // let mut scheduler = SCHEDULER.write();
     // let data0 = (input::Command::publish as u64) << 56;
@@ -176,18 +200,15 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
 
     serial_print!("Key");
 
-    let data0 = ((input::Command::publish as u64) << 56) | scancode as u64;
+    let [data0, data1, data2, data3] = input::requests::publish(scancode);
+    let message = Message { pid: 3, data0, data1, data2, data3 };
 
     serial_println!("[KERNEL] Notifying input server");
 
     without_interrupts(|| {
         let mut scheduler = SCHEDULER.write();
 
-        notify(0, Message {
-            pid: 3,
-            data0,
-            ..Default::default()
-        }, &mut scheduler);
+        notify(0, message, &mut scheduler);
     
         let mail = &scheduler.queue.iter().find(|p| p.pid == 3).unwrap().message_handler.mailbox.notifs;    
         serial_println!("[KERNEL] Notified input server: {:?}", mail);
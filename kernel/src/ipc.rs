@@ -1,25 +1,54 @@
+pub mod endpoint;
 pub mod memshare;
-
-use abi::ipc::{Message, PayloadMessage, SendStatus, NotifyStatus, RESPONSE_BUFFER, ReadMailboxStatus};
+pub mod topic;
+
+use abi::{
+    ipc::{Message, PayloadMessage, SendStatus, NotifyStatus, ReadMailboxStatus, ReadMailboxResponse, ZERO_COPY_THRESHOLD, ReplyStatus, ReceiveStatus, CancelSendStatus, Timeout, WaitFlags, WaitStatus, OverflowPolicy, MailboxInfo, DEFAULT_MAILBOX_CAPACITY},
+    endpoint::{EndpointId, EndpointMessage},
+    memshare::ShareId,
+    vm::MapFlags,
+};
 use alloc::{vec::Vec, slice, borrow::ToOwned, collections::VecDeque};
-use x86_64::{registers::control::{Cr3, Cr3Flags}, instructions::interrupts::without_interrupts};
+use x86_64::{VirtAddr, registers::control::{Cr3, Cr3Flags}};
 
-use crate::{process::{Pid, ReturnRegs, SCHEDULER, ExecState, Scheduler, Process}, serial_println, println, syscall::build_user_vec};
+use crate::{process::{Pid, ReturnRegs, ExecState, Scheduler, Process}, serial_println, println, memory::{cow, user::{check_user_range, copy_from_space}}, interrupts::ticks};
 
+pub use endpoint::*;
 pub use memshare::*;
+pub use topic::*;
 
 #[derive(Clone, Debug)]
 pub struct MessageHandler {
     pub state: MessageHandlerState,
     pub mailbox: Mailbox,
+    /// The tick a send or receive gives up at, set whenever one starts waiting
+    pub deadline: Option<u64>,
 }
 
 #[derive(Clone, Debug)]
 pub enum MessageHandlerState {
     Idle,
-    Sending(Message),
-    SendingPayload(PayloadMessage),
+    Sending(Message, AfterSend),
+    SendingPayload(PayloadMessage, AfterSend),
+    /// Sending to an endpoint, the sender and badge are only filled in once the message is received
+    SendingEndpoint(EndpointId, EndpointMessage, AfterSend),
     Receiving(Vec<Pid>),
+    ReceivingEndpoint(EndpointId),
+    /// Blocked in `wait` with a whitelist for messages, what it's waiting for and the notification sender to filter on
+    Listening(Vec<Pid>, WaitFlags, Pid),
+    /// Made a call to the process with this PID and is blocked until it replies
+    AwaitingReply(Pid),
+}
+
+/// What happens to the sender once its message has been received
+#[derive(Clone, Copy, Debug)]
+pub enum AfterSend {
+    /// The send returns successfully
+    Return,
+    /// The recipient is granted access to a memory share, then the send returns successfully
+    Grant(ShareId, MapFlags),
+    /// The sender keeps blocking until the recipient replies
+    AwaitReply,
 }
 
 #[derive(Clone, Debug)]
@@ -27,6 +56,11 @@ pub struct Mailbox {
     pub notifs: VecDeque<Message>,
     pub whitelist: Vec<Pid>,
     pub enabled: bool,
+    /// The most notifications `notifs` holds
+    pub capacity: usize,
+    pub policy: OverflowPolicy,
+    /// Notifications that didn't fit since the owner last reset this
+    pub dropped: u64,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -44,6 +78,55 @@ impl Mailbox {
             notifs: VecDeque::new(),
             whitelist: Vec::new(),
             enabled: false,
+            capacity: DEFAULT_MAILBOX_CAPACITY,
+            policy: OverflowPolicy::Reject,
+            dropped: 0,
+        }
+    }
+
+    /// Adds a notification, making room for it according to the overflow policy if the mailbox is full
+    pub fn push(&mut self, message: Message) -> NotifyStatus {
+        if self.notifs.len() < self.capacity {
+            self.notifs.push_back(message);
+            return NotifyStatus::Success;
+        }
+
+        self.dropped += 1;
+
+        match self.policy {
+            OverflowPolicy::Reject => NotifyStatus::Full,
+            OverflowPolicy::DropOldest => {
+                self.notifs.pop_front();
+                self.notifs.push_back(message);
+                NotifyStatus::Success
+            },
+            OverflowPolicy::Coalesce => {
+                let Some(newest) = self.notifs.iter_mut().rev().find(|n| n.pid == message.pid) else {
+                    return NotifyStatus::Full;
+                };
+
+                *newest = message;
+                NotifyStatus::Success
+            },
+        }
+    }
+
+    /// Changes the capacity and overflow policy, dropping the oldest notifications that don't fit anymore
+    pub fn resize(&mut self, capacity: usize, policy: OverflowPolicy) {
+        while self.notifs.len() > capacity {
+            self.notifs.pop_front();
+            self.dropped += 1;
+        }
+
+        self.capacity = capacity;
+        self.policy = policy;
+    }
+
+    pub fn info(&self) -> MailboxInfo {
+        MailboxInfo {
+            queued: self.notifs.len() as u64,
+            capacity: self.capacity as u64,
+            dropped: self.dropped,
         }
     }
 }
@@ -52,12 +135,70 @@ impl MessageHandler {
         Self {
             state: MessageHandlerState::Idle,
             mailbox: Mailbox::new(),
+            deadline: None,
         }
     }
 
-    pub fn receive_message(&mut self, from: Pid, data0: u64, data1: u64, data2: u64, data3: u64) -> MessageState {
+    /// Gets the tick the current send or receive gives up at, if it has one
+    pub fn pending_deadline(&self) -> Option<u64> {
+        match self.state {
+            MessageHandlerState::Sending(..)
+            | MessageHandlerState::SendingPayload(..)
+            | MessageHandlerState::SendingEndpoint(..)
+            | MessageHandlerState::Receiving(_)
+            | MessageHandlerState::ReceivingEndpoint(_)
+            | MessageHandlerState::Listening(..) => self.deadline,
+            _ => None,
+        }
+    }
+
+    /// Checks if the process is waiting for a notification, which an interrupt handler can send
+    pub fn wants_any_notif(&self) -> bool {
+        matches!(self.state, MessageHandlerState::Listening(_, flags, _) if flags.notify)
+    }
+
+    /// Gets the whitelist for synchronous messages if the process is waiting for one
+    pub fn receive_whitelist(&self) -> Option<&Vec<Pid>> {
+        match &self.state {
+            MessageHandlerState::Receiving(whitelist) => Some(whitelist),
+            MessageHandlerState::Listening(whitelist, flags, _) if flags.receive => Some(whitelist),
+            _ => None,
+        }
+    }
+
+    /// Checks if the process is waiting for a notification from `from`
+    pub fn wants_notif(&self, from: Pid) -> bool {
         match &self.state {
-            MessageHandlerState::Receiving(whitelist) => {
+            MessageHandlerState::Listening(_, flags, sender) => flags.notify && (!flags.filter || *sender == from),
+            _ => false,
+        }
+    }
+
+    /// Checks if the process is waiting for any kind of synchronous message
+    pub fn is_receiving(&self) -> bool {
+        self.receive_whitelist().is_some() || matches!(self.state, MessageHandlerState::ReceivingEndpoint(_))
+    }
+
+    /// Checks if `sender` is waiting to send a message this process, with PID `pid`, would take right now
+    fn takes_from(&self, pid: Pid, sender: &Process) -> bool {
+        let to_pid = match &sender.message_handler.state {
+            MessageHandlerState::Sending(message, _) => message.pid,
+            MessageHandlerState::SendingPayload(message, _) => message.pid,
+            MessageHandlerState::SendingEndpoint(endpoint, ..) => {
+                return matches!(self.state, MessageHandlerState::ReceivingEndpoint(receiving) if receiving == *endpoint);
+            },
+            _ => return false,
+        };
+
+        match self.receive_whitelist() {
+            Some(whitelist) => to_pid == pid && (whitelist.len() == 0 || whitelist.contains(&sender.pid)),
+            None => false,
+        }
+    }
+
+    pub fn receive_message(&mut self, from: Pid, data0: u64, data1: u64, data2: u64, data3: u64) -> MessageState {
+        match self.receive_whitelist() {
+            Some(whitelist) => {
                 if whitelist.len() > 0 && !whitelist.contains(&from) {
                     MessageState::Blocked
                 } else {
@@ -75,21 +216,58 @@ impl MessageHandler {
         }
     }
 
-    pub fn await_message(&mut self, whitelist: Vec<Pid>) {
+    /// Takes a message sent to `endpoint` if the process is receiving on it
+    pub fn receive_endpoint_message(&mut self, endpoint: EndpointId, message: EndpointMessage) -> MessageState {
+        match self.state {
+            MessageHandlerState::ReceivingEndpoint(receiving) if receiving == endpoint => {
+                MessageState::Receivable(ReturnRegs {
+                    rax: 0,
+                    rdi: message.pid,
+                    rsi: message.badge,
+                    rdx: message.data0,
+                    r8: message.data1,
+                    r9: message.data2,
+                })
+            },
+            _ => MessageState::Waiting,
+        }
+    }
+
+    pub fn await_message(&mut self, whitelist: Vec<Pid>, deadline: Option<u64>) {
         self.state = MessageHandlerState::Receiving(whitelist);
+        self.deadline = deadline;
+    }
+
+    pub fn await_any(&mut self, whitelist: Vec<Pid>, flags: WaitFlags, notif_sender: Pid, deadline: Option<u64>) {
+        self.state = MessageHandlerState::Listening(whitelist, flags, notif_sender);
+        self.deadline = deadline;
+    }
+
+    pub fn await_endpoint(&mut self, endpoint: EndpointId, deadline: Option<u64>) {
+        self.state = MessageHandlerState::ReceivingEndpoint(endpoint);
+        self.deadline = deadline;
+    }
+}
+
+/// Turns a timeout into the tick it runs out at
+pub fn deadline(timeout: Timeout) -> Option<u64> {
+    match timeout {
+        Timeout::Ticks(ticks) => Some(self::ticks().saturating_add(ticks)),
+        _ => None,
     }
 }
 
 /// Sends a message from the process with PID `sender_pid` to the process with PID `message.pid`
 /// 
-/// Returns `None` if the recipient doesn't exist
-pub fn send_message(sender_pid: Pid, message: Message, scheduler: &mut Scheduler)  -> Option<MessageState> {
+/// If the recipient isn't receiving yet, the sender is left waiting and the send is retried by `refresh_ipc`.
+/// Once it's received, the recipient is moved to the front of the queue and `after` is applied to the sender
+pub fn send_message(sender_pid: Pid, message: Message, after: AfterSend, scheduler: &mut Scheduler) -> Result<MessageState, SendStatus> {
     let Message { pid, data0, data1, data2, data3 } = message;
 
     let processes = &mut scheduler.queue;
 
     let Some(recipient_index) = processes.iter().position(|p| p.pid == pid) else {
-        return None;
+        return Err(SendStatus::InvalidRecipient);
     };
 
     let recipient = &mut processes[recipient_index];
@@ -104,34 +282,225 @@ pub fn send_message(sender_pid: Pid, message: Message, scheduler: &mut Scheduler
 
             processes.swap(recipient_index, sender_index);
 
-            let sender = &mut processes[sender_index];
-            sender.exec_state = ExecState::Running;
-            sender.message_handler.state = MessageHandlerState::Idle;
+            finish_send(&mut processes[recipient_index], pid, after);
 
-            Some(MessageState::Received)
+            Ok(MessageState::Received)
         },
-        e => {
+        MessageState::Waiting => {
             let sender = processes.iter_mut().find(|p| p.pid == sender_pid).unwrap();
             
             sender.exec_state = ExecState::WaitingIpc;
-            sender.message_handler.state = MessageHandlerState::Sending(message);
-            Some(e)
+            sender.message_handler.state = MessageHandlerState::Sending(message, after);
+
+            Ok(MessageState::Waiting)
+        },
+        e => Ok(e),
+    }
+}
+
+/// Sends a message from the process with PID `sender_pid` to the owner of `endpoint`
+///
+/// Follows the same rules as `send_message`, except the recipient has to be receiving on the endpoint. The sender's
+/// access is checked again every time the send is retried, so revoking it or destroying the endpoint fails the send
+pub fn send_endpoint_message(sender_pid: Pid, endpoint: EndpointId, message: EndpointMessage, after: AfterSend, scheduler: &mut Scheduler) -> Result<MessageState, SendStatus> {
+    let (pid, badge) = ENDPOINTS.lock().resolve(endpoint, sender_pid)?;
+
+    let processes = &mut scheduler.queue;
+
+    let Some(recipient_index) = processes.iter().position(|p| p.pid == pid) else {
+        return Err(SendStatus::InvalidRecipient);
+    };
+
+    let recipient = &mut processes[recipient_index];
+
+    match recipient.message_handler.receive_endpoint_message(endpoint, EndpointMessage { pid: sender_pid, badge, ..message }) {
+        MessageState::Receivable(regs) => {
+            recipient.reg_state = regs;
+            recipient.exec_state = ExecState::Running;
+            recipient.message_handler.state = MessageHandlerState::Idle;
+
+            let sender_index = processes.iter().position(|p| p.pid == sender_pid).unwrap();
+
+            processes.swap(recipient_index, sender_index);
+
+            finish_send(&mut processes[recipient_index], pid, after);
+
+            Ok(MessageState::Received)
+        },
+        MessageState::Waiting => {
+            let sender = processes.iter_mut().find(|p| p.pid == sender_pid).unwrap();
+
+            sender.exec_state = ExecState::WaitingIpc;
+            sender.message_handler.state = MessageHandlerState::SendingEndpoint(endpoint, message, after);
+
+            Ok(MessageState::Waiting)
+        },
+        e => Ok(e),
+    }
+}
+
+/// Updates a sender whose message was just received by `recipient_pid`
+fn finish_send(sender: &mut Process, recipient_pid: Pid, after: AfterSend) {
+    if let AfterSend::AwaitReply = after {
+        sender.exec_state = ExecState::WaitingIpc;
+        sender.message_handler.state = MessageHandlerState::AwaitingReply(recipient_pid);
+        return;
+    }
+
+    if let AfterSend::Grant(share, access) = after {
+        MEMORY_SHARE.lock().grant(share, recipient_pid, access);
+    }
+
+    sender.exec_state = ExecState::Running;
+    sender.message_handler.state = MessageHandlerState::Idle;
+    sender.reg_state = ReturnRegs {
+        rax: SendStatus::Success as u64,
+        ..Default::default()
+    };
+}
+
+/// Wakes up the process with PID `pid` with the reply `message` from `from`, which it has to be waiting on
+pub fn reply(from: Pid, message: Message, scheduler: &mut Scheduler) -> ReplyStatus {
+    let Message { pid, data0, data1, data2, data3 } = message;
+
+    let Some(caller) = scheduler.queue.iter_mut().find(|p| p.pid == pid) else {
+        return ReplyStatus::NotWaiting;
+    };
+
+    let MessageHandlerState::AwaitingReply(callee) = caller.message_handler.state else {
+        return ReplyStatus::NotWaiting;
+    };
+
+    if callee != from {
+        return ReplyStatus::NotWaiting;
+    }
+
+    caller.reg_state = ReturnRegs {
+        rax: SendStatus::Success as u64,
+        rdi: from,
+        rsi: data0,
+        rdx: data1,
+        r8: data2,
+        r9: data3,
+    };
+    caller.exec_state = ExecState::Running;
+    caller.message_handler.state = MessageHandlerState::Idle;
+
+    ReplyStatus::Success
+}
+
+/// Fails every call that's waiting on a reply from `pid`, since it's exiting and won't ever send one
+pub fn abandon_calls(pid: Pid, scheduler: &mut Scheduler) {
+    for process in scheduler.queue.iter_mut() {
+        if let MessageHandlerState::AwaitingReply(callee) = process.message_handler.state {
+            if callee == pid {
+                fail_send(process, SendStatus::InvalidRecipient);
+            }
         }
     }
 }
 
-pub fn receive_message(recipient: Pid, whitelist: Vec<Pid>) {
-    without_interrupts(|| {
-        let mut scheduler = SCHEDULER.write();
-        let processes = &mut scheduler.queue;
-    
-        let Some(process) = processes.iter_mut().find(|p| p.pid == recipient) else {
-            return;
-        };
-    
-        process.message_handler.await_message(whitelist);
-        process.exec_state = ExecState::WaitingIpc;
-    });
+/// Stops a process from sending, returning `status` from the send
+fn fail_send(sender: &mut Process, status: SendStatus) {
+    stop_waiting(sender);
+    sender.reg_state = ReturnRegs {
+        rax: status as u64,
+        ..Default::default()
+    };
+}
+
+/// Starts waiting for a message from a process in `whitelist`, or any process if it's empty
+pub fn receive_message(recipient: &mut Process, whitelist: Vec<Pid>, deadline: Option<u64>) {
+    recipient.message_handler.await_message(whitelist, deadline);
+    recipient.exec_state = ExecState::WaitingIpc;
+}
+
+/// Starts waiting for a message sent to `endpoint`, which the process has to own
+pub fn receive_endpoint(recipient: &mut Process, endpoint: EndpointId, deadline: Option<u64>) {
+    recipient.message_handler.await_endpoint(endpoint, deadline);
+    recipient.exec_state = ExecState::WaitingIpc;
+}
+
+/// Starts waiting for a synchronous message or a notification, depending on `flags`
+pub fn listen(recipient: &mut Process, whitelist: Vec<Pid>, flags: WaitFlags, notif_sender: Pid, deadline: Option<u64>) {
+    recipient.message_handler.await_any(whitelist, flags, notif_sender, deadline);
+    recipient.exec_state = ExecState::WaitingIpc;
+}
+
+/// Delivers a message to the process with PID `pid` from a process that was already waiting to send to it
+///
+/// The recipient has to be receiving and at the front of the queue, where it stays. Returns the message if one got through
+///
+/// Also returns whether the message carries a payload
+pub fn pull_message(pid: Pid, scheduler: &mut Scheduler) -> Option<(Message, bool)> {
+    let ReturnRegs { rax, rdi, rsi, rdx, r8, r9 } = pull(pid, scheduler)?;
+
+    Some((Message { pid: rdi, data0: rsi, data1: rdx, data2: r8, data3: r9 }, rax == ReceiveStatus::Payload as u64))
+}
+
+/// Delivers a message sent to the endpoint the process with PID `pid` is receiving on, like `pull_message`
+pub fn pull_endpoint_message(pid: Pid, scheduler: &mut Scheduler) -> Option<EndpointMessage> {
+    let ReturnRegs { rdi, rsi, rdx, r8, r9, .. } = pull(pid, scheduler)?;
+
+    Some(EndpointMessage { pid: rdi, badge: rsi, data0: rdx, data1: r8, data2: r9 })
+}
+
+/// Retries the sends waiting on the process with PID `pid` until one gets through, returning the registers it left
+fn pull(pid: Pid, scheduler: &mut Scheduler) -> Option<ReturnRegs> {
+    let recipient = &scheduler.queue[0].message_handler;
+
+    let senders: Vec<Pid> = scheduler.queue.iter()
+        .filter(|p| recipient.takes_from(pid, p))
+        .map(|p| p.pid)
+        .collect();
+
+    for sender in senders {
+        refresh_ipc(sender, scheduler);
+
+        let recipient_index = scheduler.queue.iter().position(|p| p.pid == pid).unwrap();
+        let recipient = &scheduler.queue[recipient_index];
+
+        if recipient.message_handler.is_receiving() {
+            continue;
+        }
+
+        let regs = recipient.reg_state;
+
+        // delivering a message swaps the sender to the front
+        scheduler.queue.swap(0, recipient_index);
+
+        return Some(regs);
+    }
+
+    None
+}
+
+/// Stops a process from waiting on a send or receive without anything happening
+pub fn stop_waiting(process: &mut Process) {
+    process.exec_state = ExecState::Running;
+    process.message_handler.state = MessageHandlerState::Idle;
+}
+
+/// Fails a send or call from the process with PID `sender` that's waiting on `recipient`
+pub fn cancel_send(recipient: Pid, sender: Pid, scheduler: &mut Scheduler) -> CancelSendStatus {
+    let Some(process) = scheduler.queue.iter_mut().find(|p| p.pid == sender) else {
+        return CancelSendStatus::NotPending;
+    };
+
+    let pending = match &process.message_handler.state {
+        MessageHandlerState::Sending(message, _) => message.pid == recipient,
+        MessageHandlerState::SendingPayload(message, _) => message.pid == recipient,
+        MessageHandlerState::SendingEndpoint(endpoint, ..) => ENDPOINTS.lock().owner(*endpoint) == Some(recipient),
+        MessageHandlerState::AwaitingReply(callee) => *callee == recipient,
+        _ => false,
+    };
+
+    if !pending {
+        return CancelSendStatus::NotPending;
+    }
+
+    fail_send(process, SendStatus::Cancelled);
+    CancelSendStatus::Success
 }
 
 /// Sends a notification to the target process.
@@ -159,33 +528,38 @@ pub fn notify(sender_pid: Pid, message: Message, scheduler: &mut Scheduler) -> N
         ..message
     };
 
-    mailbox.notifs.push_back(message);
-    NotifyStatus::Success
+    // anything it would've wanted was taken out of the mailbox when it started waiting, so this can skip the mailbox
+    if recipient.message_handler.wants_notif(sender_pid) {
+        stop_waiting(recipient);
+        recipient.reg_state = ReturnRegs {
+            rax: WaitStatus::Notified as u64,
+            rdi: sender_pid,
+            rsi: data0,
+            rdx: data1,
+            r8: data2,
+            r9: data3,
+        };
+
+        return NotifyStatus::Success;
+    }
+
+    recipient.message_handler.mailbox.push(message)
 }
 
-pub fn read_mailbox(recipient: &mut Process, sender_pid: Pid, filter: bool) -> ReturnRegs {
+pub fn read_mailbox(recipient: &mut Process, sender_pid: Pid, filter: bool) -> ReadMailboxResponse {
     if !recipient.message_handler.mailbox.enabled {
-        return ReturnRegs {
-            rax: ReadMailboxStatus::Disabled as u64,
-            ..Default::default()
-        }
+        return ReadMailboxStatus::Disabled.into();
     }
 
     let notifs = &mut recipient.message_handler.mailbox.notifs;
     
     if notifs.len() == 0 {
-        return ReturnRegs {
-            rax: ReadMailboxStatus::NoMessages as u64,
-            ..Default::default()
-        };
+        return ReadMailboxStatus::NoMessages.into();
     }
 
     let message = if filter {
         let Some(idx) = notifs.iter().position(|p| p.pid == sender_pid) else {
-            return ReturnRegs {
-                rax: ReadMailboxStatus::NoMessages as u64,
-                ..Default::default()
-            };
+            return ReadMailboxStatus::NoMessages.into();
         };
 
         notifs.remove(idx)
@@ -193,80 +567,131 @@ pub fn read_mailbox(recipient: &mut Process, sender_pid: Pid, filter: bool) -> R
         notifs.pop_front()
     }.unwrap();
 
-    let rax = if notifs.len() > 0 { ReadMailboxStatus::MoreMessages } else { ReadMailboxStatus::OneMessage } as u64;
+    let status = if notifs.len() > 0 { ReadMailboxStatus::MoreMessages } else { ReadMailboxStatus::OneMessage };
 
-    ReturnRegs {
-        rax,
-        rdi: message.pid,
-        rsi: message.data0,
-        rdx: message.data1,
-        r8: message.data2,
-        r9: message.data3,
+    ReadMailboxResponse {
+        status,
+        message: Some(message),
     }
 }
 
-pub unsafe fn send_payload(sender_pid: Pid, message: PayloadMessage, scheduler: &mut Scheduler) -> Result<MessageState, SendStatus> {
+/// Sends a message from the process with PID `sender_pid` with its payload copied or, if it's big and page aligned, shared
+/// copy-on-write into a slot of the recipient's response buffer
+///
+/// Follows the same rules as `send_message`. The sender's address space has to be the active one
+pub unsafe fn send_payload(sender_pid: Pid, message: PayloadMessage, after: AfterSend, scheduler: &mut Scheduler) -> Result<MessageState, SendStatus> {
     let PayloadMessage { pid, data0, data1, payload, payload_len } = message;
 
     let processes = &mut scheduler.queue;
 
+    let mut remap_len = 0;
+
+    // the scheduler is locked, so the payload can't be populated when it's copied
+    if let Some(sender) = processes.iter().find(|p| p.pid == sender_pid) {
+        let end = payload.saturating_add(payload_len);
+
+        sender.vmas.populate(payload, end);
+
+        // whole pages of big payloads get shared instead of copied, as long as the frames are the sender's to share
+        if payload % 4096 == 0 && payload_len >= ZERO_COPY_THRESHOLD && sender.vmas.owns(payload, end) {
+            remap_len = payload_len & !4095;
+        }
+    }
+
     let Some(recipient_index) = processes.iter().position(|p| p.pid == pid) else {
         return Err(SendStatus::InvalidRecipient);
     };
 
     let recipient = &mut processes[recipient_index];
 
-    if recipient.response_buffer.is_none() {
+    let Some(buffer) = recipient.response_buffer.as_mut() else {
         return Err(SendStatus::NoResponseBuffer);
-    } else if recipient.response_buffer.as_ref().unwrap().size <  message.payload_len {
+    };
+
+    if buffer.slot_size < payload_len {
         return Err(SendStatus::BufferTooSmall);
     }
 
-    match recipient.message_handler.receive_message(sender_pid, data0, data1, RESPONSE_BUFFER, payload_len) {
-        MessageState::Receivable(regs) => {
-            recipient.reg_state = regs;
-            recipient.exec_state = ExecState::Running;
-            recipient.message_handler.state = MessageHandlerState::Idle;
+    // check the payload while we're still in the sender's address space
+    if check_user_range(payload, payload_len, false).is_err() {
+        return Err(SendStatus::InvalidPayload);
+    }
 
+    // with every slot taken, the sender waits for one to be released just like it waits for the recipient to receive
+    let state = if buffer.is_full() {
+        MessageState::Waiting
+    } else {
+        let slot = buffer.claim().unwrap();
+        let mut state = recipient.message_handler.receive_message(sender_pid, data0, data1, slot, payload_len);
 
-            let Ok(payload_slice): Result<Vec<u8>, _> = build_user_vec(payload, payload_len as usize) else {
-                return Err(SendStatus::InvalidPayload);
-            };
+        // flagged so the recipient knows `data2` and `data3` came from the kernel, not the sender
+        if let MessageState::Receivable(regs) = &mut state {
+            regs.rax = ReceiveStatus::Payload as u64;
+        }
 
-            unsafe { Cr3::write(recipient.cr3, Cr3Flags::empty()) };
+        if !matches!(state, MessageState::Receivable(_)) {
+            recipient.response_buffer.as_mut().unwrap().release(slot);
+        }
 
-            let mut payload_ptr = RESPONSE_BUFFER as *mut u8;
-            
-            for byte in payload_slice {
-                unsafe {
-                    payload_ptr.write(byte);
-                    payload_ptr = payload_ptr.offset(1);
+        state
+    };
+
+    match state {
+        MessageState::Receivable(regs) => {
+            let slot = regs.r8;
+            let (sender_cr3, _) = Cr3::read();
+
+            // running out of memory fails the send, the recipient keeps waiting with the slot still free
+            if remap_len > 0 {
+                let flags = recipient.vmas.find(slot).unwrap().flags;
+
+                if cow::remap_area(VirtAddr::new(payload), VirtAddr::new(slot), remap_len / 4096, recipient.cr3, flags).is_err() {
+                    recipient.response_buffer.as_mut().unwrap().release(slot);
+                    return Err(SendStatus::OutOfMemory);
                 }
             }
-            
 
-            let sender_index = processes.iter().position(|p| p.pid == sender_pid).unwrap();
+            unsafe { Cr3::write(recipient.cr3, Cr3Flags::empty()) };
 
+            let (copy_start, copy_len) = (slot + remap_len, payload_len - remap_len);
 
-            let sender = &mut processes[recipient_index];
+            // the page fault handler can't populate the buffer while the scheduler is locked, and pages left shared by
+            // an earlier payload have to be copied before the kernel writes to them
+            if !recipient.vmas.populate(copy_start, copy_start + copy_len) || check_user_range(copy_start, copy_len, true).is_err() {
+                unsafe { Cr3::write(sender_cr3, Cr3Flags::empty()) };
+                cow::unmap_area(VirtAddr::new(slot), remap_len / 4096, recipient.cr3);
 
-            sender.exec_state = ExecState::Running;
-            sender.message_handler.state = MessageHandlerState::Idle;
+                recipient.response_buffer.as_mut().unwrap().release(slot);
+                return Err(SendStatus::OutOfMemory);
+            }
+
+            unsafe {
+                copy_from_space(sender_cr3, payload + remap_len, copy_start, copy_len);
+                Cr3::write(sender_cr3, Cr3Flags::empty());
+            }
+
+            recipient.reg_state = regs;
+            recipient.exec_state = ExecState::Running;
+            recipient.message_handler.state = MessageHandlerState::Idle;
 
-            unsafe { Cr3::write(sender.cr3, Cr3Flags::empty()) };
+            let sender_index = processes.iter().position(|p| p.pid == sender_pid).unwrap();
             
-            serial_println!("[IPC] Swapping {} (PID {}) and {} (PID {})", sender_index, sender_pid, recipient_index, pid);
             processes.swap(recipient_index, sender_index);
-            serial_println!("[IPC] Swapped");
+
+            finish_send(&mut processes[recipient_index], pid, after);
             
             Ok(MessageState::Received)
         },
-        e => {
-            serial_println!("Send failed: {:?}", e);
+        MessageState::Waiting => {
             let sender = processes.iter_mut().find(|p| p.pid == sender_pid).unwrap();
             
             sender.exec_state = ExecState::WaitingIpc;
-            sender.message_handler.state = MessageHandlerState::SendingPayload(message);
+            sender.message_handler.state = MessageHandlerState::SendingPayload(message, after);
+
+            Ok(MessageState::Waiting)
+        },
+        e => {
+            serial_println!("Send failed: {:?}", e);
             Ok(e)
         }
     }
@@ -274,24 +699,64 @@ pub unsafe fn send_payload(sender_pid: Pid, message: PayloadMessage, scheduler:
 
 /// Refreshes the IPC status of the given process, attempting to send or receive a message as needed
 /// 
-/// Returns `true` if the process finished sending, or false if it's still waiting or listening
+/// Returns `true` if the front of the queue can run, which is the recipient if a message got through, or `false` if the
+/// process is still waiting or listening
 pub fn refresh_ipc(pid: Pid, scheduler: &mut Scheduler) -> bool {
-    // serial_println!("Refreshing {} IPC", pid);
+    let Some(process) = scheduler.queue.iter_mut().find(|p| p.pid == pid) else { return false };
+
+    if process.message_handler.pending_deadline().is_some_and(|deadline| ticks() >= deadline) {
+        match process.message_handler.state {
+            MessageHandlerState::Receiving(_) | MessageHandlerState::ReceivingEndpoint(_) | MessageHandlerState::Listening(..) => {
+                let rax = match process.message_handler.state {
+                    MessageHandlerState::Receiving(_) | MessageHandlerState::ReceivingEndpoint(_) => ReceiveStatus::TimedOut as u64,
+                    _ => WaitStatus::TimedOut as u64,
+                };
+
+                stop_waiting(process);
+                process.reg_state = ReturnRegs { rax, ..Default::default() };
+            },
+            _ => fail_send(process, SendStatus::TimedOut),
+        }
+
+        return true;
+    }
 
-    let Some(process) = scheduler.queue.iter().find(|p| p.pid == pid) else { return false };
+    let result = match &process.message_handler.state {
+        MessageHandlerState::Receiving(_)
+        | MessageHandlerState::ReceivingEndpoint(_)
+        | MessageHandlerState::Listening(..)
+        | MessageHandlerState::AwaitingReply(_) => return false,
+        MessageHandlerState::Idle => return true,
+        MessageHandlerState::Sending(message, after) => send_message(pid, *message, *after, scheduler),
+        MessageHandlerState::SendingEndpoint(endpoint, message, after) => send_endpoint_message(pid, *endpoint, *message, *after, scheduler),
+        MessageHandlerState::SendingPayload(message, after) => {
+            let (message, after, cr3) = (message.clone(), *after, process.cr3);
+            let (old_cr3, _) = Cr3::read();
+
+            // the payload has to be copied out of the sender's address space
+            unsafe {
+                Cr3::write(cr3, Cr3Flags::empty());
+                let result = send_payload(pid, message, after, scheduler);
+                Cr3::write(old_cr3, Cr3Flags::empty());
+
+                result
+            }
+        },
+    };
 
-    match &process.message_handler.state {
-        MessageHandlerState::Receiving(_) => {
-            // serial_println!("{} is receiving, nothing to do here", process.pid);
-            false
+    let sender = scheduler.queue.iter_mut().find(|p| p.pid == pid).unwrap();
+
+    match result {
+        Ok(MessageState::Received) => true,
+        Ok(MessageState::Waiting) => false,
+        Ok(MessageState::Blocked) => {
+            fail_send(sender, SendStatus::Blocked);
+            true
         },
-        MessageHandlerState::Sending(message) => {
-            // serial_println!("{} is sending, trying again", process.pid);
-            match send_message(process.pid, *message, scheduler) {
-                Some(MessageState::Received) => true,
-                _ => false,
-            } 
-        }
-        _ => true,
+        Err(status) => {
+            fail_send(sender, status);
+            true
+        },
+        Ok(e) => panic!("Retried send is {:?}", e),
     }
 }
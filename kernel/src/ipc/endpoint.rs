@@ -0,0 +1,132 @@
+use abi::{endpoint::{EndpointId, DestroyEndpointStatus, GrantEndpointStatus, RevokeEndpointStatus}, ipc::SendStatus};
+use alloc::{vec::Vec, collections::BTreeMap};
+use spin::Mutex;
+
+use crate::process::Pid;
+
+/// Every endpoint that hasn't been destroyed, along with who can send to it
+pub static ENDPOINTS: Mutex<Endpoints> = Mutex::new(Endpoints { endpoints: BTreeMap::new(), next_id: 0 });
+
+pub struct Endpoints {
+    endpoints: BTreeMap<EndpointId, Endpoint>,
+    next_id: EndpointId,
+}
+
+#[derive(Clone, Debug)]
+pub struct Endpoint {
+    /// The process that created the endpoint, which is the only one that can receive on it
+    pub owner: Pid,
+    /// The processes that can send to the endpoint, along with the badge each one's messages carry
+    pub caps: Vec<Capability>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Capability {
+    pub pid: Pid,
+    pub badge: u64,
+}
+
+impl Endpoints {
+    pub fn create(&mut self, owner: Pid) -> EndpointId {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.endpoints.insert(id, Endpoint { owner, caps: Vec::new() });
+
+        id
+    }
+
+    pub fn destroy(&mut self, id: EndpointId, pid: Pid) -> Result<(), DestroyEndpointStatus> {
+        let Some(endpoint) = self.endpoints.get(&id) else {
+            return Err(DestroyEndpointStatus::NotExists);
+        };
+
+        if endpoint.owner != pid {
+            return Err(DestroyEndpointStatus::NotOwner);
+        }
+
+        self.endpoints.remove(&id);
+
+        Ok(())
+    }
+
+    /// Lets `to` send to an endpoint with `badge`, or replaces the badge it already had
+    ///
+    /// The owner can hand out any badge and change existing ones. Other processes that can send to the endpoint only
+    /// pass on their own badge, and only to processes that can't send to it yet, so they can't take over another
+    /// client's session
+    pub fn grant(&mut self, id: EndpointId, from: Pid, to: Pid, badge: u64) -> Result<(), GrantEndpointStatus> {
+        let Some(endpoint) = self.endpoints.get_mut(&id) else {
+            return Err(GrantEndpointStatus::NotExists);
+        };
+
+        let existing = endpoint.caps.iter().position(|cap| cap.pid == to);
+
+        if endpoint.owner == from {
+            match existing {
+                Some(index) => endpoint.caps[index].badge = badge,
+                None => endpoint.caps.push(Capability { pid: to, badge }),
+            }
+
+            return Ok(());
+        }
+
+        let Some(cap) = endpoint.caps.iter().find(|cap| cap.pid == from) else {
+            return Err(GrantEndpointStatus::AccessDenied);
+        };
+
+        if existing.is_some() {
+            return Err(GrantEndpointStatus::AccessDenied);
+        }
+
+        let badge = cap.badge;
+        endpoint.caps.push(Capability { pid: to, badge });
+
+        Ok(())
+    }
+
+    pub fn revoke(&mut self, id: EndpointId, from: Pid, pid: Pid) -> Result<(), RevokeEndpointStatus> {
+        let Some(endpoint) = self.endpoints.get_mut(&id) else {
+            return Err(RevokeEndpointStatus::NotExists);
+        };
+
+        if endpoint.owner != from {
+            return Err(RevokeEndpointStatus::NotOwner);
+        }
+
+        let Some(index) = endpoint.caps.iter().position(|cap| cap.pid == pid) else {
+            return Err(RevokeEndpointStatus::NotGranted);
+        };
+
+        endpoint.caps.remove(index);
+
+        Ok(())
+    }
+
+    /// Finds who a message from `sender` to an endpoint goes to, and the badge it carries
+    pub fn resolve(&self, id: EndpointId, sender: Pid) -> Result<(Pid, u64), SendStatus> {
+        let Some(endpoint) = self.endpoints.get(&id) else {
+            return Err(SendStatus::NoCapability);
+        };
+
+        let Some(cap) = endpoint.caps.iter().find(|cap| cap.pid == sender) else {
+            return Err(SendStatus::NoCapability);
+        };
+
+        Ok((endpoint.owner, cap.badge))
+    }
+
+    /// Gets the owner of an endpoint, if it still exists
+    pub fn owner(&self, id: EndpointId) -> Option<Pid> {
+        self.endpoints.get(&id).map(|endpoint| endpoint.owner)
+    }
+
+    /// Destroys every endpoint `pid` owns and drops its access to the rest, for when it exits
+    pub fn release_all(&mut self, pid: Pid) {
+        self.endpoints.retain(|_, endpoint| endpoint.owner != pid);
+
+        for endpoint in self.endpoints.values_mut() {
+            endpoint.caps.retain(|cap| cap.pid != pid);
+        }
+    }
+}
@@ -1,9 +1,9 @@
-use abi::memshare::{ShareId, CreateShareStatus, JoinShareStatus};
+use abi::{ipc::SendStatus, memshare::{ShareId, ShareGrant, CreateShareStatus, AllocShareStatus, JoinShareStatus, LeaveShareStatus, DestroyShareStatus}, vm::MapFlags};
 use alloc::{vec::Vec, collections::BTreeMap};
 use spin::Mutex;
-use x86_64::{structures::paging::{PhysFrame, Page, PageTableFlags, Mapper, FrameAllocator, Size4KiB}, VirtAddr};
+use x86_64::{structures::paging::{PhysFrame, Page, PageTableFlags, Mapper, FrameDeallocator, Size4KiB}, VirtAddr};
 
-use crate::{process::Pid, memory, serial_println};
+use crate::{process::Pid, memory::{self, cow}, serial_println};
 
 /// This guy keep strack of all the shared memory regions
 pub static MEMORY_SHARE: Mutex<SharedMemory> = Mutex::new(SharedMemory { regions: BTreeMap::new(), next_id: 0 });
@@ -18,40 +18,68 @@ pub struct SharedMemory {
 #[derive(Clone, Debug)]
 pub struct SharedRegion {
     pub frames: Vec<PhysFrame>,
-    pub whitelist: Vec<Pid>,
+    /// The processes that can join and the access they get, anyone can join with any access if it's empty
+    pub whitelist: Vec<ShareGrant>,
+    /// Every process that has the region mapped, the frames are freed once this is empty
     pub members: Vec<Pid>,
+    /// The process that created the region, which is the only one that can destroy it
+    pub owner: Pid,
+    /// Set once the owner destroys the region, so nobody else can join
+    pub destroyed: bool,
 }
 
 impl SharedMemory {
-    pub unsafe fn create(&mut self, start: Page, end: Page, pid: Pid, whitelist: Vec<Pid>) -> Result<ShareId, CreateShareStatus> {
+    pub unsafe fn create(&mut self, start: Page, end: Page, pid: Pid, whitelist: Vec<ShareGrant>) -> Result<ShareId, CreateShareStatus> {
         // the upper half of virtual memory is mapped to the kernel in every address space
         // this may change later
         if end.start_address() >= VirtAddr::new(0xffff_8000_0000_0000) {
             return Err(CreateShareStatus::OutOfBounds);
         }
 
+        let pages = Page::range_inclusive(start, end);
+        // the heap can't grow while the frame allocator is locked
+        let mut frames = Vec::with_capacity(pages.count());
+
         let mut mapper = unsafe { memory::get_mapper() };
         let mut frame_allocator = memory::PHYS_ALLOCATOR.lock();
         let frame_allocator = frame_allocator.0.as_mut().unwrap();
         let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
 
-        let frames: Vec<PhysFrame> = Page::range_inclusive(start, end).map(|page| {
-            let translation = mapper.translate_page(page);
-            
-            if translation.is_err() {
-                let frame = frame_allocator.allocate_frame().unwrap();
-                unsafe { mapper.map_to(page, frame, flags, frame_allocator).unwrap().flush() };
+        // whatever got mapped or copied before running out still belongs to the process, so nothing has to be undone
+        for page in pages {
+            let frame = if mapper.translate_page(page).is_err() {
+                let Some(frame) = memory::zeroed_frame(frame_allocator) else {
+                    return Err(CreateShareStatus::OutOfMemory);
+                };
+
+                match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+                    Ok(flush) => flush.flush(),
+                    Err(_) => {
+                        frame_allocator.deallocate_frame(frame);
+                        return Err(CreateShareStatus::OutOfMemory);
+                    }
+                }
+
                 frame
             } else {
-                translation.unwrap()
-            }
-        }).collect();
+                // a payload sent from here may still be using the frame, and every member has to write to the same one
+                let Some(frame) = cow::make_private(&mut mapper, page, frame_allocator) else {
+                    return Err(CreateShareStatus::OutOfMemory);
+                };
+
+                frame
+            };
+
+            frames.push(frame);
+        }
 
 
         let region = SharedRegion {
             frames,
             whitelist,
             members: Vec::from([pid]),
+            owner: pid,
+            destroyed: false,
         };
 
         let id = self.new_id();
@@ -61,10 +89,11 @@ impl SharedMemory {
         Ok(id)
     }
 
-    pub unsafe fn join(&mut self, id: u64, start: Page, end: Page, pid: Pid, blacklist: Vec<Pid>) -> Result<(), JoinShareStatus> {
+    /// Maps a region into the current address space with `access`, which can't be more than `pid` was granted
+    pub unsafe fn join(&mut self, id: u64, start: Page, end: Page, pid: Pid, access: MapFlags, blacklist: Vec<Pid>) -> Result<(), JoinShareStatus> {
         // serial_println!("{:#018X?}", self.regions);
         
-        if !self.regions.contains_key(&id) {
+        if !self.regions.get(&id).is_some_and(|region| !region.destroyed) {
             return Err(JoinShareStatus::NotExists);
         }
 
@@ -75,17 +104,23 @@ impl SharedMemory {
         let region = self.regions.get_mut(&id).unwrap();
 
         // if there's a whitelist, don't let any process in that's not on it
-        if region.whitelist.len() > 0 && !region.whitelist.contains(&pid) {
-            return Err(JoinShareStatus::NotAllowed);
+        if region.whitelist.len() > 0 {
+            let Some(grant) = region.whitelist.iter().find(|grant| grant.pid == pid) else {
+                return Err(JoinShareStatus::NotAllowed);
+            };
+
+            if !grant.flags().contains(access) {
+                return Err(JoinShareStatus::AccessDenied);
+            }
         }
 
         // processes can pass a blacklist when they join a shared memory region
         // this allows them to ensure the creator of the region didn't allow any processes they dont like
-        if blacklist.iter().any(|pid| region.whitelist.contains(pid)) {
+        if blacklist.iter().any(|pid| region.whitelist.iter().any(|grant| grant.pid == *pid)) {
             return Err(JoinShareStatus::BlacklistClash);
         }
 
-        let mut pages = Page::range_inclusive(start, end);
+        let pages = Page::range_inclusive(start, end);
 
         {
             let page_count = pages.count();
@@ -97,43 +132,185 @@ impl SharedMemory {
             }
         }
 
-        let mut mapper = unsafe { memory::get_mapper() };
+        region.members.push(pid);
+        self.map(id, start, memory::user_page_flags(access));
+
+        Ok(())
+    }
 
-        let none_mapped = {
-            pages.all(|page| {
-                let translation = mapper.translate_page(page);
+    /// Creates a region out of `pages` fresh zeroed frames, which the caller still has to map with `map`
+    pub unsafe fn alloc(&mut self, pages: usize, pid: Pid, whitelist: Vec<ShareGrant>) -> Result<ShareId, AllocShareStatus> {
+        let mut frames = Vec::with_capacity(pages);
 
-                if translation.is_err() {
-                    true
-                } else {
-                    false
+        let mut frame_allocator = memory::PHYS_ALLOCATOR.lock();
+        let frame_allocator = frame_allocator.0.as_mut().unwrap();
+
+        for _ in 0..pages {
+            let Some(frame) = memory::zeroed_frame(frame_allocator) else {
+                for frame in frames {
+                    frame_allocator.deallocate_frame(frame);
                 }
-            })
-        };
 
-        if !none_mapped {
-            return Err(JoinShareStatus::AlreadyMapped);
+                return Err(AllocShareStatus::OutOfMemory);
+            };
+
+            frames.push(frame);
         }
 
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+        let region = SharedRegion {
+            frames,
+            whitelist,
+            members: Vec::from([pid]),
+            owner: pid,
+            destroyed: false,
+        };
+
+        let id = self.new_id();
+
+        self.regions.insert(id, region);
+
+        Ok(id)
+    }
+
+    /// Maps every frame of a region into the current address space, starting at `start`
+    pub unsafe fn map(&self, id: ShareId, start: Page, flags: PageTableFlags) {
+        let region = &self.regions[&id];
+
+        let mut mapper = memory::get_mapper();
         let mut frame_allocator = memory::PHYS_ALLOCATOR.lock();
         let frame_allocator = frame_allocator.0.as_mut().unwrap();
 
-        // serial_println!("# frames: {}", region.frames.len());
+        let pages = Page::range(start, start + region.frames.len() as u64);
 
-        let pages = Page::range_inclusive(start, end);
+        for (page, frame) in pages.zip(region.frames.iter()) {
+            mapper.map_to(page, *frame, flags, frame_allocator).unwrap().flush();
+        }
+    }
 
-        for pair in region.frames.iter().zip(pages) {
-            // serial_println!("Mapping {:#018X?} to {:#018X}", pair.1, pair.0.start_address());
+    /// Returns how many pages a region has, if it can still be joined
+    pub fn page_count(&self, id: ShareId) -> Option<usize> {
+        self.regions.get(&id).filter(|region| !region.destroyed).map(|region| region.frames.len())
+    }
+
+    /// Checks that `from` can pass `access` to a region on to another process
+    ///
+    /// The owner can grant anything, other members can only grant as much access as they have
+    pub fn check_grant(&self, id: ShareId, from: Pid, access: MapFlags) -> Result<(), SendStatus> {
+        let Some(region) = self.regions.get(&id).filter(|region| !region.destroyed) else {
+            return Err(SendStatus::InvalidShare);
+        };
 
-            unsafe { mapper.map_to(pair.1, *pair.0, flags, frame_allocator).unwrap().flush() };
+        if region.owner == from {
+            return Ok(());
         }
 
-        region.members.push(pid);
+        if !region.members.contains(&from) {
+            return Err(SendStatus::AccessDenied);
+        }
+
+        let allowed = match region.whitelist.iter().find(|grant| grant.pid == from) {
+            Some(grant) => grant.flags().contains(access),
+            None => region.whitelist.is_empty(),
+        };
+
+        if allowed {
+            Ok(())
+        } else {
+            Err(SendStatus::AccessDenied)
+        }
+    }
+
+    /// Adds `pid` to the whitelist of a region with `access`, or adds to its access if it's already on there
+    ///
+    /// Regions without a whitelist can already be joined by anyone, so they're left alone
+    pub fn grant(&mut self, id: ShareId, pid: Pid, access: MapFlags) {
+        let Some(region) = self.regions.get_mut(&id) else {
+            return;
+        };
+
+        if region.whitelist.is_empty() {
+            return;
+        }
+
+        match region.whitelist.iter_mut().find(|grant| grant.pid == pid) {
+            Some(grant) => {
+                let flags = grant.flags();
+                *grant = ShareGrant::new(pid, MapFlags {
+                    write: flags.write || access.write,
+                    execute: flags.execute || access.execute,
+                });
+            }
+            None => region.whitelist.push(ShareGrant::new(pid, access)),
+        }
+    }
+
+    /// Drops `pid` from the members of a region
+    ///
+    /// The caller has to unmap the region from the process first, since the frames are freed if it was the last member
+    pub unsafe fn leave(&mut self, id: ShareId, pid: Pid) -> Result<(), LeaveShareStatus> {
+        let Some(region) = self.regions.get_mut(&id) else {
+            return Err(LeaveShareStatus::NotExists);
+        };
+
+        let Some(index) = region.members.iter().position(|&member| member == pid) else {
+            return Err(LeaveShareStatus::NotMember);
+        };
+
+        region.members.remove(index);
+        self.free_if_unused(id);
+
+        Ok(())
+    }
+
+    /// Stops anyone from joining a region, and drops `pid` from its members if it's still in there
+    ///
+    /// Members that are left keep the region mapped until they leave too
+    pub unsafe fn destroy(&mut self, id: ShareId, pid: Pid) -> Result<(), DestroyShareStatus> {
+        let Some(region) = self.regions.get_mut(&id).filter(|region| !region.destroyed) else {
+            return Err(DestroyShareStatus::NotExists);
+        };
+
+        if region.owner != pid {
+            return Err(DestroyShareStatus::NotOwner);
+        }
+
+        region.destroyed = true;
+        region.members.retain(|&member| member != pid);
+        self.free_if_unused(id);
 
         Ok(())
     }
 
+    /// Drops `pid` from every region it's a member of, for when it exits
+    pub unsafe fn leave_all(&mut self, pid: Pid) {
+        let ids: Vec<ShareId> = self.regions.iter()
+            .filter(|(_, region)| region.members.contains(&pid))
+            .map(|(&id, _)| id)
+            .collect();
+
+        for id in ids {
+            self.leave(id, pid).unwrap();
+        }
+    }
+
+    /// Frees the frames of a region and forgets about it once nobody has it mapped
+    unsafe fn free_if_unused(&mut self, id: ShareId) {
+        if !self.regions.get(&id).is_some_and(|region| region.members.is_empty()) {
+            return;
+        }
+
+        let region = self.regions.remove(&id).unwrap();
+
+        let mut frame_allocator = memory::PHYS_ALLOCATOR.lock();
+        let frame_allocator = frame_allocator.0.as_mut().unwrap();
+
+        for frame in region.frames {
+            frame_allocator.deallocate_frame(frame);
+        }
+
+        serial_println!("Freed memshare {}", id);
+    }
+
     fn new_id(&mut self) -> ShareId {
         let id = self.next_id;
         self.next_id += 1;
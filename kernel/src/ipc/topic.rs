@@ -0,0 +1,105 @@
+use abi::topic::{TopicId, DestroyTopicStatus, SubscribeTopicStatus, UnsubscribeTopicStatus, PublishTopicStatus};
+use alloc::{vec::Vec, collections::BTreeMap};
+use spin::Mutex;
+
+use crate::process::Pid;
+
+/// Every topic that hasn't been destroyed, along with who's subscribed to it
+pub static TOPICS: Mutex<Topics> = Mutex::new(Topics { topics: BTreeMap::new(), next_id: 0 });
+
+pub struct Topics {
+    topics: BTreeMap<TopicId, Topic>,
+    next_id: TopicId,
+}
+
+#[derive(Clone, Debug)]
+pub struct Topic {
+    /// The process that created the topic, which is the only one that can publish to it
+    pub owner: Pid,
+    pub subscribers: Vec<Pid>,
+}
+
+impl Topics {
+    pub fn create(&mut self, owner: Pid) -> TopicId {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.topics.insert(id, Topic { owner, subscribers: Vec::new() });
+
+        id
+    }
+
+    pub fn destroy(&mut self, id: TopicId, pid: Pid) -> Result<(), DestroyTopicStatus> {
+        let Some(topic) = self.topics.get(&id) else {
+            return Err(DestroyTopicStatus::NotExists);
+        };
+
+        if topic.owner != pid {
+            return Err(DestroyTopicStatus::NotOwner);
+        }
+
+        self.topics.remove(&id);
+
+        Ok(())
+    }
+
+    /// Subscribes `pid` to a topic, `from` has to be either `pid` or the owner
+    pub fn subscribe(&mut self, id: TopicId, from: Pid, pid: Pid) -> Result<(), SubscribeTopicStatus> {
+        let Some(topic) = self.topics.get_mut(&id) else {
+            return Err(SubscribeTopicStatus::NotExists);
+        };
+
+        if from != pid && from != topic.owner {
+            return Err(SubscribeTopicStatus::AccessDenied);
+        }
+
+        if topic.subscribers.contains(&pid) {
+            return Err(SubscribeTopicStatus::AlreadySubscribed);
+        }
+
+        topic.subscribers.push(pid);
+
+        Ok(())
+    }
+
+    /// Unsubscribes `pid` from a topic, `from` has to be either `pid` or the owner
+    pub fn unsubscribe(&mut self, id: TopicId, from: Pid, pid: Pid) -> Result<(), UnsubscribeTopicStatus> {
+        let Some(topic) = self.topics.get_mut(&id) else {
+            return Err(UnsubscribeTopicStatus::NotExists);
+        };
+
+        if from != pid && from != topic.owner {
+            return Err(UnsubscribeTopicStatus::AccessDenied);
+        }
+
+        let Some(index) = topic.subscribers.iter().position(|subscriber| *subscriber == pid) else {
+            return Err(UnsubscribeTopicStatus::NotSubscribed);
+        };
+
+        topic.subscribers.remove(index);
+
+        Ok(())
+    }
+
+    /// Gets who an event `from` publishes to a topic goes to
+    pub fn subscribers(&self, id: TopicId, from: Pid) -> Result<Vec<Pid>, PublishTopicStatus> {
+        let Some(topic) = self.topics.get(&id) else {
+            return Err(PublishTopicStatus::NotExists);
+        };
+
+        if topic.owner != from {
+            return Err(PublishTopicStatus::NotOwner);
+        }
+
+        Ok(topic.subscribers.clone())
+    }
+
+    /// Destroys every topic `pid` owns and unsubscribes it from the rest, for when it exits
+    pub fn release_all(&mut self, pid: Pid) {
+        self.topics.retain(|_, topic| topic.owner != pid);
+
+        for topic in self.topics.values_mut() {
+            topic.subscribers.retain(|subscriber| *subscriber != pid);
+        }
+    }
+}
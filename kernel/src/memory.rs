@@ -1,6 +1,7 @@
 use core::{fmt::Debug};
 
-use alloc::vec::Vec;
+use abi::vm::MapFlags;
+
 use lazy_static::lazy_static;
 use limine::{
     LimineMemmapRequest, 
@@ -11,7 +12,7 @@ use limine::{
     LimineKernelAddressResponse,
     LimineKernelAddressRequest, LimineHhdmResponse
 };
-use spin::Mutex;
+use spin::{Mutex, Once};
 use x86_64::{
     VirtAddr,
     structures::{
@@ -20,7 +21,8 @@ use x86_64::{
             PhysFrame,
             FrameAllocator,
             Size4KiB,
-            OffsetPageTable, PageTableFlags, page_table::PageTableEntry, Page, mapper::MapToError, Mapper
+            FrameDeallocator,
+            OffsetPageTable, PageTableFlags, page_table::PageTableEntry, Page, mapper::{MapToError, FlagUpdateError}, Mapper
         }, 
         gdt::{
             self,
@@ -39,6 +41,12 @@ use x86_64::{
 
 use crate::{allocator, serial_println};
 
+use self::frames::BitmapFrameAllocator;
+
+pub mod cow;
+pub mod frames;
+pub mod user;
+
 const FRAME_SIZE: usize = 4096;
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 pub const HARDWARE_IST_INDEX: u16 = 1;
@@ -104,13 +112,18 @@ lazy_static! {
     pub static ref PHYS_ALLOCATOR: Mutex<PhysAllocator> = Mutex::new(PhysAllocator(None));
 }
 
-pub struct PhysAllocator(pub Option<PhysBumpAllocator>);
+pub struct PhysAllocator(pub Option<BitmapFrameAllocator>);
+
+/// The address space the kernel booted in, which is used while a process' address space is being freed
+static KERNEL_PML4: Once<PhysFrame> = Once::new();
 
 /// Starts allocation of memory
 pub unsafe fn init() {
     serial_println!("Initializing memory...");
     init_gdt();
 
+    KERNEL_PML4.call_once(|| registers::control::Cr3::read().0);
+
     let mut frame_allocator = BootstrapAllocator::new();
 
     // unsafe
@@ -124,6 +137,9 @@ pub unsafe fn init() {
         if !pml4[i].flags().contains(PageTableFlags::PRESENT) {
             let frame = frame_allocator.allocate_frame().expect("Out of memory");
 
+            // usable frames aren't guaranteed to be zeroed, and garbage entries would look mapped
+            get_pml4(frame.start_address()).zero();
+
             pml4[i] = PageTableEntry::new();
             pml4[i].set_frame(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
         }
@@ -148,7 +164,14 @@ unsafe fn init_gdt() {
 }
 
 unsafe fn init_phys_allocator(old_allocator: BootstrapAllocator) {
-    let new_allocator = PhysBumpAllocator::new(old_allocator);
+    let new_allocator = BitmapFrameAllocator::new(old_allocator);
+
+    serial_println!(
+        "Physical memory: {} frames, {} used, {} free",
+        new_allocator.total_frames(),
+        new_allocator.used_frames(),
+        new_allocator.free_frames(),
+    );
 
     PHYS_ALLOCATOR.lock().0 = Some(new_allocator);
 }
@@ -208,17 +231,50 @@ pub unsafe fn new_pml4() -> PhysFrame {
     frame
 }
 
+/// Turns the access a process asked for into the flags its pages get mapped with
+pub fn user_page_flags(flags: MapFlags) -> PageTableFlags {
+    let mut page_flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+
+    if flags.write {
+        page_flags |= PageTableFlags::WRITABLE;
+    }
+
+    if !flags.execute {
+        page_flags |= PageTableFlags::NO_EXECUTE;
+    }
+
+    page_flags
+}
+
+/// Allocates a frame and fills it with zeroes, since it could still hold another process' data
+pub fn zeroed_frame(frame_allocator: &mut BitmapFrameAllocator) -> Option<PhysFrame> {
+    let frame = frame_allocator.allocate_frame()?;
+    let ptr = (physical_offset() + frame.start_address().as_u64()) as *mut u8;
+
+    unsafe { ptr.write_bytes(0, FRAME_SIZE) };
+
+    Some(frame)
+}
+
+/// Maps a zeroed frame at `page`
 pub unsafe fn map_page(page: Page, flags: PageTableFlags) -> Result<(), MapToError<Size4KiB>> {
     let mut mapper = get_mapper();
     let mut frame_allocator = PHYS_ALLOCATOR.lock();
     let frame_allocator = frame_allocator.0.as_mut().unwrap();
-    let frame = frame_allocator.allocate_frame().ok_or(MapToError::FrameAllocationFailed)?;
+    let frame = zeroed_frame(frame_allocator).ok_or(MapToError::FrameAllocationFailed)?;
     
-    unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+    match mapper.map_to(page, frame, flags, frame_allocator) {
+        Ok(flush) => flush.flush(),
+        Err(e) => {
+            frame_allocator.deallocate_frame(frame);
+            return Err(e);
+        }
+    }
 
     Ok(())
 }
 
+/// Maps zeroed frames from `start` to `end` (inclusive), skipping pages that are already mapped
 pub unsafe fn map_area(start: VirtAddr, end: VirtAddr, flags: PageTableFlags) -> Result<(), MapToError<Size4KiB>> {
     let start_page = Page::containing_address(start);
     let end_page = Page::containing_address(end);
@@ -229,14 +285,14 @@ pub unsafe fn map_area(start: VirtAddr, end: VirtAddr, flags: PageTableFlags) ->
     let frame_allocator = frame_allocator.0.as_mut().unwrap();
 
     for page in page_range {
-        let frame = frame_allocator
-            .allocate_frame()
-            .ok_or(MapToError::FrameAllocationFailed)?;
+        let frame = zeroed_frame(frame_allocator).ok_or(MapToError::FrameAllocationFailed)?;
 
         let mapped = unsafe { mapper.map_to(page, frame, flags, frame_allocator) };
 
         match mapped {
             Err(e) => {
+                frame_allocator.deallocate_frame(frame);
+
                 match e {
                     MapToError::PageAlreadyMapped(_) => {},
                     _ => return Err(e),
@@ -249,6 +305,100 @@ pub unsafe fn map_area(start: VirtAddr, end: VirtAddr, flags: PageTableFlags) ->
     Ok(())
 }
 
+/// Unmaps every mapped page from `start` to `end` (inclusive), freeing the frames behind them if `free` is set
+///
+/// # Safety
+///
+/// If `free` is set, the frames must only be mapped here, otherwise whatever else maps them will be using freed memory
+pub unsafe fn unmap_area(start: VirtAddr, end: VirtAddr, free: bool) {
+    let start_page: Page = Page::containing_address(start);
+    let end_page: Page = Page::containing_address(end);
+
+    let mut mapper = get_mapper();
+    let mut frame_allocator = PHYS_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.0.as_mut().unwrap();
+
+    for page in Page::range_inclusive(start_page, end_page) {
+        let Ok((frame, flush)) = mapper.unmap(page) else {
+            continue;
+        };
+
+        flush.flush();
+
+        if free {
+            frame_allocator.deallocate_frame(frame);
+        }
+    }
+}
+
+/// Frees the page tables of an address space, along with its level 4 table
+///
+/// Everything in the lower half should be unmapped first, frames that are still mapped get leaked instead of freed
+pub unsafe fn free_address_space(pml4: PhysFrame) {
+    use registers::control::{Cr3, Cr3Flags};
+
+    // the level 4 table can't be freed while it's still in use
+    if Cr3::read().0 == pml4 {
+        Cr3::write(*KERNEL_PML4.get().unwrap(), Cr3Flags::empty());
+    }
+
+    let mut frame_allocator = PHYS_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.0.as_mut().unwrap();
+
+    // the upper half is shared with every other address space
+    for entry in get_pml4(pml4.start_address()).iter().take(256) {
+        if let Ok(frame) = entry.frame() {
+            free_table(frame, 3, frame_allocator);
+        }
+    }
+
+    frame_allocator.deallocate_frame(pml4);
+}
+
+/// Frees a page table and every table below it, `level` being 1 for tables that point straight to pages
+unsafe fn free_table(frame: PhysFrame, level: u8, frame_allocator: &mut BitmapFrameAllocator) {
+    if level > 1 {
+        for entry in get_pml4(frame.start_address()).iter() {
+            // `frame` fails for huge pages, which aren't tables
+            if let Ok(child) = entry.frame() {
+                free_table(child, level - 1, frame_allocator);
+            }
+        }
+    }
+
+    frame_allocator.deallocate_frame(frame);
+}
+
+/// Replaces the flags of every mapped page from `start` to `end` (inclusive)
+///
+/// Pages that haven't been touched yet are skipped, they get their flags from the area they're in once they're populated
+pub unsafe fn protect_area(start: VirtAddr, end: VirtAddr, flags: PageTableFlags) -> Result<(), FlagUpdateError> {
+    let start_page: Page = Page::containing_address(start);
+    let end_page: Page = Page::containing_address(end);
+
+    let mut mapper = get_mapper();
+
+    let frame_allocator = PHYS_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.0.as_ref().unwrap();
+
+    for page in Page::range_inclusive(start_page, end_page) {
+        let Ok(frame) = mapper.translate_page(page) else {
+            continue;
+        };
+
+        // frames that are still shared copy-on-write can only become writable once they're copied
+        let flags = if flags.contains(PageTableFlags::WRITABLE) && frame_allocator.ref_count(frame) > 1 {
+            (flags - PageTableFlags::WRITABLE) | cow::COW
+        } else {
+            flags - cow::COW
+        };
+
+        mapper.update_flags(page, flags)?.flush();
+    }
+
+    Ok(())
+}
+
 /// Allocates physical frames before the kernel heap is initialized
 pub struct BootstrapAllocator {
     map: &'static [NonNullPtr<LimineMemmapEntry>],
@@ -287,31 +437,3 @@ unsafe impl FrameAllocator<Size4KiB> for BootstrapAllocator {
         frame
     }
 }
-
-pub struct PhysBumpAllocator {
-    map: Vec<PhysFrame>,
-    next: usize,
-}
-
-impl PhysBumpAllocator {
-    /// Creates a bump allocator for physical frames
-    pub fn new(mut old: BootstrapAllocator) -> Self {
-        let frames = old.usable_frames();
-        let map: Vec<PhysFrame> = frames.collect();
-
-        Self {
-            map,
-            next: old.next,
-        }
-    }
-}
-
-unsafe impl FrameAllocator<Size4KiB> for PhysBumpAllocator {
-    /// Returns the next frame and moves to the next
-    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
-        let frame = self.map[self.next];
-        self.next += 1;
-
-        Some(PhysFrame::containing_address(frame.start_address()))
-    }
-}
\ No newline at end of file
@@ -0,0 +1,143 @@
+//! Copy-on-write sharing of frames between address spaces
+//!
+//! A copy-on-write page is mapped read only with the `COW` bit set, and its frame has a reference for every address space
+//! it's in. The first write faults, and `handle_write_fault` gives the writer its own copy
+
+use core::ptr::copy_nonoverlapping;
+
+use x86_64::{
+    VirtAddr,
+    structures::paging::{
+        FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTableFlags, PhysFrame, Size4KiB, Translate,
+        mapper::{MapToError, MappedFrame, TranslateResult},
+    },
+};
+
+use super::{get_mapper, get_pml4, physical_offset, PHYS_ALLOCATOR, frames::BitmapFrameAllocator};
+
+/// Marks a page that's writable, but shares its frame until the first write
+pub const COW: PageTableFlags = PageTableFlags::BIT_9;
+
+/// Maps `count` pages starting at `src` in the current address space to `dst` in the one at `dst_pml4`, copy-on-write
+/// in both
+///
+/// Whatever `dst_pml4` had mapped there is unmapped and freed first. `flags` are the flags `dst` would have if it
+/// wasn't copy-on-write
+pub unsafe fn remap_area(src: VirtAddr, dst: VirtAddr, count: u64, dst_pml4: PhysFrame, flags: PageTableFlags) -> Result<(), MapToError<Size4KiB>> {
+    let src_start: Page = Page::containing_address(src);
+    let dst_start: Page = Page::containing_address(dst);
+    let dst_flags = (flags - PageTableFlags::WRITABLE) | COW;
+
+    let mut mapper = get_mapper();
+    let mut dst_mapper = OffsetPageTable::new(get_pml4(dst_pml4.start_address()), VirtAddr::new(physical_offset()));
+
+    let mut frame_allocator = PHYS_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.0.as_mut().unwrap();
+
+    for i in 0..count {
+        let (src_page, dst_page) = (src_start + i, dst_start + i);
+
+        unmap_pages(&mut dst_mapper, dst_page, 1, frame_allocator);
+
+        // untouched pages read as zeroes on both sides anyway
+        let TranslateResult::Mapped { frame: MappedFrame::Size4KiB(frame), flags, .. } = mapper.translate(src_page.start_address()) else {
+            continue;
+        };
+
+        if flags.contains(PageTableFlags::WRITABLE) {
+            mapper.update_flags(src_page, (flags - PageTableFlags::WRITABLE) | COW).unwrap().flush();
+        }
+
+        match dst_mapper.map_to(dst_page, frame, dst_flags, frame_allocator) {
+            Ok(flush) => flush.ignore(),
+            Err(e) => {
+                // leave none of the payload behind
+                unmap_pages(&mut dst_mapper, dst_start, i, frame_allocator);
+                return Err(e);
+            }
+        }
+
+        frame_allocator.add_ref(frame);
+    }
+
+    Ok(())
+}
+
+/// Unmaps `count` pages starting at `dst` in the address space at `dst_pml4`, dropping a reference to each frame
+///
+/// Undoes `remap_area`, which is why the address space can't be the active one
+pub unsafe fn unmap_area(dst: VirtAddr, count: u64, dst_pml4: PhysFrame) {
+    let mut dst_mapper = OffsetPageTable::new(get_pml4(dst_pml4.start_address()), VirtAddr::new(physical_offset()));
+
+    let mut frame_allocator = PHYS_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.0.as_mut().unwrap();
+
+    unmap_pages(&mut dst_mapper, Page::containing_address(dst), count, frame_allocator);
+}
+
+unsafe fn unmap_pages(mapper: &mut OffsetPageTable, start: Page, count: u64, frame_allocator: &mut BitmapFrameAllocator) {
+    for i in 0..count {
+        // the other address space isn't active, so there's nothing to flush
+        if let Ok((frame, flush)) = mapper.unmap(start + i) {
+            flush.ignore();
+            frame_allocator.deallocate_frame(frame);
+        }
+    }
+}
+
+/// Gives the current address space its own copy of a copy-on-write page at `addr`
+///
+/// Returns false if the page isn't copy-on-write, or there's no memory left to copy it into
+pub unsafe fn handle_write_fault(addr: VirtAddr) -> bool {
+    let page: Page = Page::containing_address(addr);
+    let mut mapper = get_mapper();
+
+    let TranslateResult::Mapped { flags, .. } = mapper.translate(addr) else {
+        return false;
+    };
+
+    if !flags.contains(COW) {
+        return false;
+    }
+
+    let mut frame_allocator = PHYS_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.0.as_mut().unwrap();
+
+    make_private(&mut mapper, page, frame_allocator).is_some()
+}
+
+/// Makes `page` writable with a frame nobody else has, copying it if it's copy-on-write and still shared
+///
+/// Returns the frame the page ends up with, or `None` if it isn't mapped or there's no memory left to copy it into
+pub unsafe fn make_private(mapper: &mut OffsetPageTable, page: Page, frame_allocator: &mut BitmapFrameAllocator) -> Option<PhysFrame> {
+    let TranslateResult::Mapped { frame: MappedFrame::Size4KiB(frame), flags, .. } = mapper.translate(page.start_address()) else {
+        return None;
+    };
+
+    if !flags.contains(COW) {
+        return Some(frame);
+    }
+
+    let flags = (flags - COW) | PageTableFlags::WRITABLE;
+
+    // everyone else already made their own copy
+    if frame_allocator.ref_count(frame) <= 1 {
+        mapper.update_flags(page, flags).unwrap().flush();
+        return Some(frame);
+    }
+
+    let copy = frame_allocator.allocate_frame()?;
+
+    let offset = physical_offset();
+    let src = (offset + frame.start_address().as_u64()) as *const u8;
+    let dst = (offset + copy.start_address().as_u64()) as *mut u8;
+
+    copy_nonoverlapping(src, dst, 4096);
+
+    mapper.unmap(page).unwrap().1.flush();
+    mapper.map_to(page, copy, flags, frame_allocator).unwrap().flush();
+
+    frame_allocator.deallocate_frame(frame);
+
+    Some(copy)
+}
@@ -0,0 +1,234 @@
+//! Physical frame allocation
+//!
+//! Every frame between the lowest and highest usable address gets one bit, set while the frame is in use.
+//! Frames that aren't usable in the memory map are permanently marked as used
+//!
+//! Frames mapped in more than one place, like copy-on-write pages, also get a reference count,
+//! and are only freed once every reference is gone
+
+use alloc::{vec, vec::Vec, collections::BTreeMap};
+use limine::LimineMemoryMapEntryType;
+use x86_64::{
+    PhysAddr,
+    structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB, frame::PhysFrameRange},
+};
+
+use super::{BootstrapAllocator, FRAME_SIZE};
+
+pub struct BitmapFrameAllocator {
+    /// Address of the first frame tracked by the bitmap
+    base: u64,
+    bitmap: Vec<u64>,
+    /// Number of frames tracked by the bitmap, usable or not
+    frame_count: usize,
+    /// Number of usable frames in the memory map
+    usable: usize,
+    free: usize,
+    /// Where the next search for a free frame starts
+    next: usize,
+    /// References to a frame beyond the first, frames with only one aren't in here
+    extra_refs: BTreeMap<usize, usize>,
+}
+
+impl BitmapFrameAllocator {
+    /// Creates a bitmap allocator, keeping every frame the bootstrap allocator handed out as used
+    pub fn new(mut old: BootstrapAllocator) -> Self {
+        let usable = old.map.iter().filter(|e| e.typ == LimineMemoryMapEntryType::Usable);
+
+        let base = usable.clone().map(|e| e.base).min().unwrap_or(0) & !(FRAME_SIZE as u64 - 1);
+        let end = usable.map(|e| e.base + e.len).max().unwrap_or(0);
+
+        let frame_count = ((end.saturating_sub(base)) / FRAME_SIZE as u64) as usize;
+
+        // everything starts out used, then the usable frames are freed
+        let mut allocator = Self {
+            base,
+            bitmap: vec![u64::MAX; (frame_count + 63) / 64],
+            frame_count,
+            usable: 0,
+            free: 0,
+            next: 0,
+            extra_refs: BTreeMap::new(),
+        };
+
+        for frame in old.usable_frames().skip(old.next) {
+            let index = allocator.index(frame);
+            allocator.clear(index);
+            allocator.free += 1;
+        }
+
+        allocator.usable = allocator.free + old.next;
+
+        allocator
+    }
+
+    /// Number of frames that can still be allocated
+    pub fn free_frames(&self) -> usize {
+        self.free
+    }
+
+    /// Number of usable frames that are currently allocated
+    pub fn used_frames(&self) -> usize {
+        self.usable - self.free
+    }
+
+    /// Number of usable frames in the memory map
+    pub fn total_frames(&self) -> usize {
+        self.usable
+    }
+
+    /// Adds a reference to an allocated frame, so it takes one more `deallocate_frame` to free it
+    pub fn add_ref(&mut self, frame: PhysFrame) {
+        *self.extra_refs.entry(self.index(frame)).or_insert(0) += 1;
+    }
+
+    /// Returns how many references there are to a frame, 0 if it's free
+    pub fn ref_count(&self, frame: PhysFrame) -> usize {
+        if frame.start_address().as_u64() < self.base {
+            return 0;
+        }
+
+        let index = self.index(frame);
+
+        if index >= self.frame_count || !self.is_used(index) {
+            return 0;
+        }
+
+        1 + self.extra_refs.get(&index).copied().unwrap_or(0)
+    }
+
+    /// Allocates `count` physically contiguous frames, with the first one aligned to `align` frames
+    pub fn allocate_contiguous(&mut self, count: usize, align: usize) -> Option<PhysFrameRange> {
+        if count == 0 || count > self.free {
+            return None;
+        }
+
+        let align = align.max(1);
+        let base_frame = (self.base / FRAME_SIZE as u64) as usize;
+
+        // alignment is by physical address, not by index into the bitmap
+        let mut start = align_index(0, align, base_frame);
+
+        while start + count <= self.frame_count {
+            match (start..start + count).rev().find(|&i| self.is_used(i)) {
+                // skip past the used frame, it can't be part of any run starting before it
+                Some(used) => start = align_index(used + 1, align, base_frame),
+                None => {
+                    for i in start..start + count {
+                        self.set(i);
+                    }
+
+                    self.free -= count;
+
+                    let first = self.frame(start);
+                    return Some(PhysFrame::range(first, first + count as u64));
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Frees every frame in `range`
+    ///
+    /// # Safety
+    ///
+    /// The frames must have been allocated by this allocator and must not be in use anymore
+    pub unsafe fn deallocate_contiguous(&mut self, range: PhysFrameRange) {
+        for frame in range {
+            self.deallocate_frame(frame);
+        }
+    }
+
+    fn index(&self, frame: PhysFrame) -> usize {
+        ((frame.start_address().as_u64() - self.base) / FRAME_SIZE as u64) as usize
+    }
+
+    fn frame(&self, index: usize) -> PhysFrame {
+        PhysFrame::containing_address(PhysAddr::new(self.base + (index * FRAME_SIZE) as u64))
+    }
+
+    fn is_used(&self, index: usize) -> bool {
+        self.bitmap[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    fn set(&mut self, index: usize) {
+        self.bitmap[index / 64] |= 1 << (index % 64);
+    }
+
+    fn clear(&mut self, index: usize) {
+        self.bitmap[index / 64] &= !(1 << (index % 64));
+    }
+}
+
+/// Rounds `index` up so that the frame it refers to is aligned to `align` frames
+fn align_index(index: usize, align: usize, base_frame: usize) -> usize {
+    let remainder = (base_frame + index) % align;
+
+    if remainder == 0 {
+        index
+    } else {
+        index + align - remainder
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
+    /// Returns the first free frame, starting from the last one allocated
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        if self.free == 0 {
+            return None;
+        }
+
+        let words = self.bitmap.len();
+        let start_word = self.next / 64;
+
+        for offset in 0..words {
+            let word = (start_word + offset) % words;
+
+            if self.bitmap[word] == u64::MAX {
+                continue;
+            }
+
+            // bits past the end of the bitmap are always set, so this is a real frame
+            let index = word * 64 + (!self.bitmap[word]).trailing_zeros() as usize;
+
+            self.set(index);
+            self.free -= 1;
+            self.next = index;
+
+            return Some(self.frame(index));
+        }
+
+        None
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    /// Drops a reference to the frame, freeing it if that was the last one
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        let addr = frame.start_address().as_u64();
+
+        if addr < self.base {
+            panic!("Tried to free frame {:#018X}, which isn't tracked by the allocator", addr);
+        }
+
+        let index = self.index(frame);
+
+        if index >= self.frame_count || !self.is_used(index) {
+            panic!("Tried to free frame {:#018X}, which isn't allocated", addr);
+        }
+
+        if let Some(refs) = self.extra_refs.get_mut(&index) {
+            *refs -= 1;
+
+            if *refs == 0 {
+                self.extra_refs.remove(&index);
+            }
+
+            return;
+        }
+
+        self.clear(index);
+        self.free += 1;
+    }
+}
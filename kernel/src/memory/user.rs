@@ -0,0 +1,144 @@
+//! Checked access to user memory
+//!
+//! Every pointer a process passes to a syscall should go through here before the kernel touches it,
+//! so a bad pointer turns into an error status instead of a page fault in kernel mode
+
+use core::{mem::{size_of, align_of}, ptr::copy_nonoverlapping};
+
+use alloc::vec::Vec;
+use x86_64::{VirtAddr, structures::paging::{Page, PageTableFlags, Translate, mapper::TranslateResult, Size4KiB, OffsetPageTable, PhysFrame}};
+
+use crate::process::vma;
+
+use super::{get_mapper, get_pml4, physical_offset, cow};
+
+/// Everything at or above this address belongs to the kernel
+pub const USER_END: u64 = 0x0000_8000_0000_0000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UserAccessError {
+    /// The range wraps around or reaches into kernel memory
+    OutOfBounds,
+    /// The pointer isn't aligned for the type being copied
+    Unaligned,
+    /// Part of the range isn't mapped
+    NotMapped,
+    /// Part of the range is mapped, but not accessible from user mode
+    NotUser,
+    /// Write access was needed, but part of the range is read only
+    NotWritable,
+}
+
+/// Checks that `len` bytes starting at `start` are mapped and user accessible in the current address space
+///
+/// Reserved pages in the range get populated. If `write` is set, the whole range must also be writable,
+/// and copy-on-write pages in it get copied
+pub fn check_user_range(start: u64, len: u64, write: bool) -> Result<(), UserAccessError> {
+    if len == 0 {
+        return Ok(());
+    }
+
+    let Some(end) = start.checked_add(len - 1) else {
+        return Err(UserAccessError::OutOfBounds);
+    };
+
+    if end >= USER_END {
+        return Err(UserAccessError::OutOfBounds);
+    }
+
+    let mapper = unsafe { get_mapper() };
+    let start_page: Page<Size4KiB> = Page::containing_address(VirtAddr::new(start));
+    let end_page: Page<Size4KiB> = Page::containing_address(VirtAddr::new(end));
+
+    for page in Page::range_inclusive(start_page, end_page) {
+        // reserved pages only get a frame once they're touched, so the kernel touching them counts too
+        if matches!(mapper.translate(page.start_address()), TranslateResult::NotMapped) {
+            unsafe { vma::populate_current(page.start_address()) };
+        }
+
+        let TranslateResult::Mapped { flags, .. } = mapper.translate(page.start_address()) else {
+            return Err(UserAccessError::NotMapped);
+        };
+
+        if !flags.contains(PageTableFlags::USER_ACCESSIBLE) {
+            return Err(UserAccessError::NotUser);
+        }
+
+        // the kernel ignores read only pages unless CR0.WP is set, so the copy can't be left to the page fault handler
+        if write && flags.contains(cow::COW) {
+            if !unsafe { cow::handle_write_fault(page.start_address()) } {
+                return Err(UserAccessError::NotWritable);
+            }
+
+            continue;
+        }
+
+        if write && !flags.contains(PageTableFlags::WRITABLE) {
+            return Err(UserAccessError::NotWritable);
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that `count` values of `T` starting at `start` can be accessed, returning their length in bytes
+fn check_user_array<T>(start: u64, count: usize, write: bool) -> Result<u64, UserAccessError> {
+    if count > 0 && start % align_of::<T>() as u64 != 0 {
+        return Err(UserAccessError::Unaligned);
+    }
+
+    let Some(len) = (count as u64).checked_mul(size_of::<T>() as u64) else {
+        return Err(UserAccessError::OutOfBounds);
+    };
+
+    check_user_range(start, len, write)?;
+
+    Ok(len)
+}
+
+/// Copies `count` values of `T` out of user memory starting at `start`
+pub unsafe fn copy_from_user<T: Copy>(start: u64, count: usize) -> Result<Vec<T>, UserAccessError> {
+    check_user_array::<T>(start, count, false)?;
+
+    let mut out = Vec::with_capacity(count);
+
+    copy_nonoverlapping(start as *const T, out.as_mut_ptr(), count);
+    out.set_len(count);
+
+    Ok(out)
+}
+
+/// Copies `src` into user memory starting at `dst`
+pub unsafe fn copy_slice_to_user<T: Copy>(dst: u64, src: &[T]) -> Result<(), UserAccessError> {
+    check_user_array::<T>(dst, src.len(), true)?;
+
+    copy_nonoverlapping(src.as_ptr(), dst as *mut T, src.len());
+
+    Ok(())
+}
+
+/// Writes `value` into user memory at `dst`
+pub unsafe fn copy_to_user<T: Copy>(dst: u64, value: &T) -> Result<(), UserAccessError> {
+    copy_slice_to_user(dst, core::slice::from_ref(value))
+}
+
+/// Copies `len` bytes from `src` in the address space at `src_pml4` to `dst` in the current address space
+///
+/// The source has to have been checked with `check_user_range` while its address space was active, and the destination
+/// has to be mapped and writable. Reads the source frames through the physical memory mapping, so neither side gets
+/// copied into the kernel heap first
+pub unsafe fn copy_from_space(src_pml4: PhysFrame, src: u64, dst: u64, len: u64) {
+    let src_mapper = OffsetPageTable::new(get_pml4(src_pml4.start_address()), VirtAddr::new(physical_offset()));
+    let mut copied = 0;
+
+    while copied < len {
+        let addr = VirtAddr::new(src + copied);
+        let phys = src_mapper.translate_addr(addr).expect("Payload was unmapped after it was checked");
+
+        // copy up to the end of the source page, since the next one can be anywhere
+        let chunk = (4096 - u64::from(addr.page_offset())).min(len - copied);
+
+        copy_nonoverlapping((physical_offset() + phys.as_u64()) as *const u8, (dst + copied) as *mut u8, chunk as usize);
+        copied += chunk;
+    }
+}
@@ -1,17 +1,17 @@
 use core::arch::asm;
 
-use abi::ipc::Message;
-use alloc::vec::Vec;
+use abi::{ipc::Message, layout::{STACK_BOTTOM, STACK_SIZE, USER_GS, RESPONSE_BUFFER}};
+use alloc::{vec, vec::Vec};
 use lazy_static::lazy_static;
 use spin::RwLock;
 use x86_64::{structures::paging::{Page, PageTableFlags, Size4KiB, PhysFrame}, VirtAddr, registers::control::{Cr3, Cr3Flags}, instructions::interrupts::{self, without_interrupts}};
 
-use crate::{memory, syscall, serial_println, ipc::{MessageHandler, self}};
+use crate::{memory, serial_println, ipc::{MessageHandler, self}};
 
 mod elf;
+pub mod vma;
 
-const STACK_BOTTOM: u64 = 0x6800_0000_0000;
-const STACK_SIZE: u64 = 4096 * 16;
+use vma::{VmaList, Vma, VmaKind, Backing};
 
 lazy_static! {
     pub static ref SCHEDULER: RwLock<Scheduler> = {
@@ -36,11 +36,57 @@ pub struct Process {
     pub message_handler: MessageHandler,
     pub privileged: bool,
     pub response_buffer: Option<ResponseBuffer>,
+    /// Everything mapped in the lower half of the address space
+    pub vmas: VmaList,
 }
 
+/// Where payloads get copied, split into slots that each hold one payload until the recipient releases it
 #[derive(Clone, Debug)]
 pub struct ResponseBuffer {
-    pub size: u64,
+    /// Size of each slot, a multiple of the page size
+    pub slot_size: u64,
+    /// Whether each slot holds a payload that hasn't been released yet
+    pub used: Vec<bool>,
+}
+
+impl ResponseBuffer {
+    pub fn new(slot_size: u64, slots: usize) -> Self {
+        Self {
+            slot_size,
+            used: vec![false; slots],
+        }
+    }
+
+    /// Takes a free slot, returning its address
+    pub fn claim(&mut self) -> Option<u64> {
+        let slot = self.used.iter().position(|used| !used)?;
+        self.used[slot] = true;
+
+        Some(RESPONSE_BUFFER + slot as u64 * self.slot_size)
+    }
+
+    /// Frees the slot starting at `addr`, returning `false` if it isn't the start of a taken slot
+    pub fn release(&mut self, addr: u64) -> bool {
+        let Some(offset) = addr.checked_sub(RESPONSE_BUFFER) else {
+            return false;
+        };
+
+        if offset % self.slot_size != 0 {
+            return false;
+        }
+
+        match self.used.get_mut((offset / self.slot_size) as usize) {
+            Some(used) if *used => {
+                *used = false;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.used.iter().all(|used| *used)
+    }
 }
 
 #[derive(Clone, Copy, Default, Debug)]
@@ -84,36 +130,39 @@ impl Scheduler {
         // switch to the new address space to map the program and other required pages
         Cr3::write(new_cr3, Cr3Flags::empty());
 
+        let mut vmas = VmaList::new();
+
         let (pid, entry) = match program {
             Program::Current1 => {
                 let contents = include_bytes!("../../target/programs/current1.elf");
                 let pid = self.next_pid;
                 self.next_pid += 1;
 
-                (pid, elf::load_elf(contents).unwrap())
+                (pid, elf::load_elf(contents, &mut vmas).unwrap())
             }
             Program::Graphics => {
                 let contents = include_bytes!("../../target/servers/graphics.elf");
-                (1, elf::load_elf(contents).unwrap())
+                (1, elf::load_elf(contents, &mut vmas).unwrap())
             }
             Program::Input => {
                 let contents = include_bytes!("../../target/servers/input.elf");
-                (3, elf::load_elf(contents).unwrap())
+                (3, elf::load_elf(contents, &mut vmas).unwrap())
             }
         };
     
-        let stack_start = VirtAddr::new(STACK_BOTTOM);
         let stack_end = VirtAddr::new(STACK_BOTTOM + STACK_SIZE - 64);
         let flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::WRITABLE;
-    
-        memory::map_area(stack_start, stack_end, flags).unwrap();
+
+        // the stack is only reserved, pages get populated as it grows into them
+        vmas.insert(Vma::new(STACK_BOTTOM, STACK_BOTTOM + STACK_SIZE, VmaKind::Stack, flags, Backing::Owned)).unwrap();
     
         let rsp: *const () = stack_end.as_ptr();
-        let user_gs = VirtAddr::new(syscall::USER_GS);
+        let user_gs = VirtAddr::new(USER_GS);
         let gs_page: Page<Size4KiB> = Page::containing_address(user_gs);
         let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
 
         memory::map_page(gs_page, flags).unwrap();
+        vmas.insert(Vma::new(USER_GS, USER_GS + 4096, VmaKind::Gs, flags, Backing::Owned)).unwrap();
         
         // put the new stack pointer in user gs for this address space
         asm!(
@@ -132,7 +181,7 @@ impl Scheduler {
             message_handler: MessageHandler::new(),
             privileged,
             response_buffer: None,
-            
+            vmas,
         };
 
         self.queue.push(new_process);
@@ -141,7 +190,12 @@ impl Scheduler {
         serial_println!("New process with PID {}", pid);
     }
 
+    /// Moves the next process that can run to the front of the queue
+    ///
+    /// Returns `None` if nothing can run until a wait times out or an interrupt sends a notification
     pub unsafe fn next(&mut self) -> Option<&Process> {
+        let mut can_wake = false;
+
         for _ in 0..self.queue.len() {
             self.queue.rotate_left(1);
 
@@ -159,6 +213,8 @@ impl Scheduler {
                         return self.queue.get(0);
                     }
 
+                    let handler = &self.queue[0].message_handler;
+                    can_wake |= handler.pending_deadline().is_some() || handler.wants_any_notif();
                 }
                 _ => {
                     return self.queue.get(0);
@@ -166,6 +222,10 @@ impl Scheduler {
             }
         }
 
+        if can_wake {
+            return None;
+        }
+
         panic!("Deadlock");
     }
 
@@ -179,6 +239,14 @@ impl Scheduler {
     }
 }
 
+impl From<[u64; 6]> for ReturnRegs {
+    fn from(value: [u64; 6]) -> Self {
+        let [rax, rdi, rsi, rdx, r8, r9] = value;
+
+        Self { rax, rdi, rsi, rdx, r8, r9 }
+    }
+}
+
 impl ReturnRegs {
     pub fn new() -> Self {
         Self {
@@ -201,10 +269,10 @@ impl ReturnRegs {
 }
 
 pub fn run_next() -> ! {
-    without_interrupts(|| {
-        let mut scheduler = SCHEDULER.write();
-        unsafe { scheduler.next().unwrap() };
-    });
+    // if everything is waiting on a timeout, sleep until a tick lets one of them give up
+    while without_interrupts(|| unsafe { SCHEDULER.write().next().is_none() }) {
+        interrupts::enable_and_hlt();
+    }
 
     run_process();
 }
@@ -4,6 +4,8 @@ use x86_64::{structures::paging::PageTableFlags, VirtAddr};
 
 use crate::memory;
 
+use super::vma::{VmaList, Vma, VmaKind, Backing};
+
 const ELF_MAGIC: [u8; 4] = [0x7F, 0x45, 0x4C, 0x46];
 /// The 64-bit class
 const ELF_CLASS: u8 = 2;
@@ -58,10 +60,10 @@ impl Endianness {
     }
 }
 
-/// Parses an ELF file and loads the data into memory
+/// Parses an ELF file and loads the data into memory, adding the segments to `vmas`
 /// 
 /// Returns the entry point of the program
-pub fn load_elf(program: &[u8]) -> Result<*const (), ElfParsingError> {
+pub fn load_elf(program: &[u8], vmas: &mut VmaList) -> Result<*const (), ElfParsingError> {
     let magic = &program[..4];
     
     if magic != &ELF_MAGIC {
@@ -107,12 +109,18 @@ pub fn load_elf(program: &[u8]) -> Result<*const (), ElfParsingError> {
 
             let start = VirtAddr::new(p_vaddr);
             let end = VirtAddr::new(p_vaddr + p_memsz);
+            let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+
+            // only the pages holding file data are mapped now, the rest are zero and get populated when they're touched
+            if p_filesz > 0 {
+                unsafe { memory::map_area(start, start + (p_filesz as u64 - 1), flags).unwrap() };
+            }
+
+            // segments can share a page, which only needs to be tracked once
+            let vma_start = start.align_down(4096u64).as_u64().max(vmas.last_of(VmaKind::Image).map_or(0, |vma| vma.end));
+            let vma_end = end.align_up(4096u64).as_u64();
 
-            unsafe { memory::map_area(
-                start,
-                end,
-                PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE
-            ).unwrap() };
+            vmas.insert(Vma::new(vma_start, vma_end, VmaKind::Image, flags, Backing::Owned)).unwrap();
 
             let src = program[p_offset..p_offset + p_filesz].as_ptr();
             let dst = p_vaddr as *mut u8;
@@ -0,0 +1,270 @@
+//! Tracks what each process has mapped in the lower half of its address space
+//!
+//! Areas the process owns are only reserved when they're created. Each page gets a zeroed frame the first time
+//! it's touched, so reserving a lot of memory costs nothing until it's used
+
+use abi::memshare::ShareId;
+use alloc::vec::Vec;
+use x86_64::{VirtAddr, structures::paging::{Page, PageTableFlags, Size4KiB, Translate, mapper::TranslateResult}};
+
+use crate::memory;
+
+use super::SCHEDULER;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VmaKind {
+    /// Segments loaded from the program's ELF file
+    Image,
+    Stack,
+    /// The page holding the user stack pointer while in the kernel
+    Gs,
+    Heap,
+    /// Pages from `map_memory`
+    Anonymous,
+    ResponseBuffer,
+    Framebuffer,
+    /// Pages that belong to a memory share
+    Shared,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backing {
+    /// Frames allocated for this process alone, freed when unmapped
+    Owned,
+    /// Frames that belong to a memory share, only freed when the share is
+    Share(ShareId),
+    /// Frames that never came from the allocator, like the framebuffer
+    Device,
+}
+
+/// A range of pages mapped with the same flags and backing
+#[derive(Clone, Copy, Debug)]
+pub struct Vma {
+    /// First address, page aligned
+    pub start: u64,
+    /// One past the last address, page aligned
+    pub end: u64,
+    pub kind: VmaKind,
+    pub flags: PageTableFlags,
+    pub backing: Backing,
+}
+
+impl Vma {
+    pub fn new(start: u64, end: u64, kind: VmaKind, flags: PageTableFlags, backing: Backing) -> Self {
+        Self { start, end, kind, flags, backing }
+    }
+
+    fn overlaps(&self, start: u64, end: u64) -> bool {
+        self.start < end && start < self.end
+    }
+
+    /// Checks if `other` can be merged onto the end of this area
+    fn continues_into(&self, other: &Vma) -> bool {
+        self.end == other.start
+            && self.kind == other.kind
+            && self.flags == other.flags
+            && self.backing == other.backing
+    }
+
+    /// Unmaps the pages in this area, freeing the frames if they belong to the process
+    pub unsafe fn unmap(&self) {
+        let free = self.backing == Backing::Owned;
+
+        memory::unmap_area(VirtAddr::new(self.start), VirtAddr::new(self.end - 1), free);
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct VmaOverlap;
+
+/// Every area a process has mapped, sorted by address and never overlapping
+#[derive(Clone, Debug, Default)]
+pub struct VmaList {
+    areas: Vec<Vma>,
+}
+
+impl VmaList {
+    pub fn new() -> Self {
+        Self { areas: Vec::new() }
+    }
+
+    /// Returns the last area of a kind
+    pub fn last_of(&self, kind: VmaKind) -> Option<&Vma> {
+        self.areas.iter().rev().find(|vma| vma.kind == kind)
+    }
+
+    /// Returns the area `addr` is in
+    pub fn find(&self, addr: u64) -> Option<&Vma> {
+        self.areas.iter().find(|vma| vma.start <= addr && addr < vma.end)
+    }
+
+    /// Checks if anything is mapped from `start` up to `end`
+    pub fn overlaps(&self, start: u64, end: u64) -> bool {
+        self.areas.iter().any(|vma| vma.overlaps(start, end))
+    }
+
+    /// Returns every area that's partly or fully in the range from `start` up to `end`
+    pub fn in_range(&self, start: u64, end: u64) -> impl Iterator<Item = &Vma> {
+        self.areas.iter().filter(move |vma| vma.overlaps(start, end))
+    }
+
+    /// Checks if every page from `start` up to `end` is mapped
+    pub fn covers(&self, start: u64, end: u64) -> bool {
+        let mut covered = start;
+
+        for vma in self.in_range(start, end) {
+            if vma.start > covered {
+                return false;
+            }
+
+            covered = vma.end;
+        }
+
+        covered >= end
+    }
+
+    /// Checks if every page from `start` up to `end` is mapped with frames that belong to the process
+    pub fn owns(&self, start: u64, end: u64) -> bool {
+        self.covers(start, end) && self.in_range(start, end).all(|vma| vma.backing == Backing::Owned)
+    }
+
+    /// Adds an area, merging it with its neighbours if they match
+    pub fn insert(&mut self, vma: Vma) -> Result<(), VmaOverlap> {
+        if vma.start >= vma.end {
+            return Ok(());
+        }
+
+        if self.overlaps(vma.start, vma.end) {
+            return Err(VmaOverlap);
+        }
+
+        let index = self.areas.iter().position(|other| other.start > vma.start).unwrap_or(self.areas.len());
+        self.areas.insert(index, vma);
+
+        if index + 1 < self.areas.len() && self.areas[index].continues_into(&self.areas[index + 1]) {
+            self.areas[index].end = self.areas.remove(index + 1).end;
+        }
+
+        if index > 0 && self.areas[index - 1].continues_into(&self.areas[index]) {
+            self.areas[index - 1].end = self.areas.remove(index).end;
+        }
+
+        Ok(())
+    }
+
+    /// Takes the range from `start` up to `end` out of the list, splitting areas that are only partly in it
+    ///
+    /// Returns the pieces that were removed, without unmapping anything
+    pub fn remove(&mut self, start: u64, end: u64) -> Vec<Vma> {
+        let mut removed = Vec::new();
+        let mut kept = Vec::with_capacity(self.areas.len());
+
+        for vma in self.areas.drain(..) {
+            if !vma.overlaps(start, end) {
+                kept.push(vma);
+                continue;
+            }
+
+            if vma.start < start {
+                kept.push(Vma { end: start, ..vma });
+            }
+
+            removed.push(Vma { start: vma.start.max(start), end: vma.end.min(end), ..vma });
+
+            if vma.end > end {
+                kept.push(Vma { start: end, ..vma });
+            }
+        }
+
+        self.areas = kept;
+        removed
+    }
+
+    /// Takes every area backed by the memory share `id` out of the list, without unmapping anything
+    pub fn remove_share(&mut self, id: ShareId) -> Vec<Vma> {
+        let (removed, kept) = self.areas.drain(..).partition(|vma| vma.backing == Backing::Share(id));
+
+        self.areas = kept;
+        removed
+    }
+
+    /// Changes the flags of everything from `start` up to `end`
+    pub fn protect(&mut self, start: u64, end: u64, flags: PageTableFlags) {
+        for vma in self.remove(start, end) {
+            self.insert(Vma { flags, ..vma }).unwrap();
+        }
+    }
+
+    /// Finds the lowest `size` bytes between `start` and `end` that have nothing mapped
+    pub fn find_free(&self, start: u64, end: u64, size: u64) -> Option<u64> {
+        let mut candidate = start;
+
+        for vma in self.in_range(start, end) {
+            if candidate.checked_add(size).is_some_and(|candidate_end| vma.start >= candidate_end) {
+                break;
+            }
+
+            candidate = vma.end;
+        }
+
+        match candidate.checked_add(size) {
+            Some(candidate_end) if candidate_end <= end => Some(candidate),
+            _ => None,
+        }
+    }
+
+    /// Gives every page from `start` up to `end` that hasn't been touched yet a zeroed frame
+    ///
+    /// Returns false if part of the range isn't owned by the process, or there's no memory left.
+    /// Has to run in the process' address space
+    pub unsafe fn populate(&self, start: u64, end: u64) -> bool {
+        if start >= end {
+            return true;
+        }
+
+        let mapper = memory::get_mapper();
+        let start_page: Page<Size4KiB> = Page::containing_address(VirtAddr::new(start));
+        let end_page: Page<Size4KiB> = Page::containing_address(VirtAddr::new(end - 1));
+
+        for page in Page::range_inclusive(start_page, end_page) {
+            if !matches!(mapper.translate(page.start_address()), TranslateResult::NotMapped) {
+                continue;
+            }
+
+            let Some(vma) = self.find(page.start_address().as_u64()) else {
+                return false;
+            };
+
+            // shares and devices are mapped in full when they're created
+            if vma.backing != Backing::Owned || memory::map_page(page, vma.flags).is_err() {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Unmaps every area, freeing the frames that belong to the process
+    ///
+    /// Has to run in the process' address space
+    pub unsafe fn unmap_all(&mut self) {
+        for vma in self.areas.drain(..) {
+            vma.unmap();
+        }
+    }
+}
+
+/// Populates the page at `addr` if the current process has it reserved
+///
+/// Gives up instead of waiting if the scheduler is locked, since the page fault handler calls this
+pub unsafe fn populate_current(addr: VirtAddr) -> bool {
+    let Some(scheduler) = SCHEDULER.try_read() else {
+        return false;
+    };
+
+    let Some(process) = scheduler.queue.get(0) else {
+        return false;
+    };
+
+    process.vmas.populate(addr.as_u64(), addr.as_u64() + 1)
+}
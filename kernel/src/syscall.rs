@@ -1,18 +1,31 @@
 use core::arch::asm;
-use alloc::{slice, vec::Vec};
 use x86_64::{registers, VirtAddr, structures::{paging::{PageTableFlags, Mapper, Page}, gdt::SegmentSelector}, PrivilegeLevel, instructions::interrupts::{without_interrupts, self}};
 
-use crate::{serial_println, println, memory, process::{self, ReturnRegs, SCHEDULER, ResponseBuffer}, syscall::dev::sys_request_fb};
-use abi::{Syscall, ConfigRBufferStatus, ipc::{RESPONSE_BUFFER, RESPONSE_BUFFER_SIZE, ReceiveStatus, SendStatus}};
+use crate::{serial_println, println, memory, allocator, process::{self, ReturnRegs, SCHEDULER, ResponseBuffer, vma::{Vma, VmaKind, Backing}}, syscall::dev::sys_request_fb};
+use abi::{
+    Syscall,
+    SyscallHandler,
+    ConfigRBufferStatus,
+    ReleaseRBufferStatus,
+    GetPidResponse,
+    GetPidStatus,
+    dev::{FramebufferDescriptor, RequestFbStatus, SerialStatus},
+    endpoint::{EndpointId, CreateEndpointResponse, DestroyEndpointStatus, GrantEndpointStatus, RevokeEndpointStatus, EndpointReceiveResponse},
+    layout::{RESPONSE_BUFFER, RESPONSE_BUFFER_SIZE, USER_GS},
+    ipc::{Pid, MailboxFlags, ReceiveResponse, SendStatus, NotifyStatus, ConfigMailboxStatus, ReadMailboxResponse, CallResponse, ReplyStatus, CancelSendStatus, Timeout, WaitFlags, WaitResponse, OverflowPolicy, MailboxInfo},
+    memshare::{ShareId, ShareGrant, CreateShareResponse, JoinShareResponse, AllocShareResponse, LeaveShareStatus, DestroyShareStatus},
+    topic::{TopicId, CreateTopicResponse, DestroyTopicStatus, SubscribeTopicStatus, UnsubscribeTopicStatus, PublishTopicResponse},
+    vm::{GrowHeapResponse, MapFlags, MapMemoryResponse, UnmapMemoryStatus, ProtectMemoryStatus},
+};
 
 pub const KERNEL_GS: u64 = 0xFFFF_A000_0000_0000;
-pub const USER_GS: u64 = 0x0000_7FFF_FFFF_F000;
 
 // mod graphics;
 mod serial;
 mod ipc;
 mod memshare;
 mod dev;
+mod vm;
 
 #[no_mangle]
 pub unsafe fn init_syscalls() {
@@ -22,6 +35,8 @@ pub unsafe fn init_syscalls() {
     
     let mut efer_flags = Efer::read();
     efer_flags.set(EferFlags::SYSTEM_CALL_EXTENSIONS, true);
+    // lets `map_memory` and `protect_memory` make pages non executable
+    efer_flags.set(EferFlags::NO_EXECUTE_ENABLE, true);
 
     Efer::write(efer_flags);
 
@@ -84,6 +99,7 @@ pub unsafe fn syscall() {
     let rdx: u64;
     let r8: u64;
     let r9: u64;
+    let r10: u64;
     let sp: u64;
 
     asm!(
@@ -97,6 +113,7 @@ pub unsafe fn syscall() {
         out("rdx") rdx,
         out("r8") r8,
         out("r9") r9,
+        out("r10") r10,
         sp = out(reg) sp,
     );
 
@@ -107,6 +124,7 @@ pub unsafe fn syscall() {
     // serial_println!("Syscall arg 3: {:#018X}", rdx);
     // serial_println!("Syscall arg 4: {:#018X}", r8);
     // serial_println!("Syscall arg 5: {:#018X}", r9);
+    // serial_println!("Syscall arg 6: {:#018X}", r10);
     serial_println!("[SYSCALL] Stack: {:#018X}", sp);
 
     let Ok(out): Result<Syscall, _> = number.try_into() else {
@@ -120,126 +138,11 @@ pub unsafe fn syscall() {
 
     serial_println!("[SYSCALL] {:?}", out);
 
-    let out = match out {
-        Syscall::exit => {
-            sys_exit();
-        }
-        Syscall::config_rbuffer => {
-            let status = sys_config_rbuffer(rdi);
-
-            ReturnRegs {
-                rax: status as u64,
-                ..Default::default()
-            }
-        }
-        Syscall::send => {
-            without_interrupts(|| {
-                let scheduler = &mut process::SCHEDULER.write();
-                let sender = scheduler.get_current().unwrap();
-
-                sender.pc = rcx as u64;
-            });
-
-            let Some(status) = ipc::sys_send(rdi, rsi, rdx, r8, r9) else { sys_yield(rcx) };
-
-            ReturnRegs {
-                rax: status as u64,
-                ..Default::default()
-            }
-        }
-        Syscall::receive => {
-            let status = ipc::sys_receive(rdi, rsi);
-
-            match status {
-                ReceiveStatus::Success => {
-                    sys_yield(rcx);
-                }
-                _ => ReturnRegs {
-                    rax: status as u64,
-                    ..Default::default()
-                }
-            }
-        }
-        Syscall::notify => {
-            let status = ipc::sys_notify(rdi, rsi, rdx, r8, r9);
-
-            ReturnRegs {
-                rax: status as u64,
-                ..Default::default()
-            }
-        }
-        Syscall::read_mailbox => {
-            ipc::sys_read_mailbox(rdi, rsi)
-        }
-        Syscall::config_mailbox => {
-            let status = ipc::sys_config_mailbox(rdi, rsi, rdx);
-
-            ReturnRegs {
-                rax: status as u64,
-                ..Default::default()
-            }
-        }
-        Syscall::send_payload => {
-            without_interrupts(|| {
-                let scheduler = &mut process::SCHEDULER.write();
-                let sender = scheduler.get_current().unwrap();
-
-                sender.pc = rcx as u64;
-            });
-
-            let Some(status) = ipc::sys_send_payload(rdi, rsi, rdx, r8, r9) else { sys_yield(rcx) };
-
-            ReturnRegs {
-                rax: status as u64,
-                ..Default::default()
-            }
-        }
-        Syscall::create_memshare => {
-            let out = memshare::sys_create_memshare(rdi, rsi, rdx, r8);
-
-            ReturnRegs {
-                rax: out.status as u64,
-                rdi: out.id.unwrap_or(0),
-                ..Default::default()
-            }
-        }
-        Syscall::join_memshare => {
-            let status = memshare::sys_join_memshare(rdi, rsi, rdx, r8, r9) as u64;
-
-            ReturnRegs {
-                rax: status,
-                ..Default::default()
-            }
-        }
-        Syscall::request_fb => {
-            let out = sys_request_fb(rdi) as u64;
+    let mut handler = SyscallContext { rcx };
 
-            ReturnRegs {
-                rax: out,
-                ..Default::default()
-            }
-        }
-        Syscall::getpid => {
-            let out = sys_getpid(rdi);
-
-            ReturnRegs {
-                rax: out.status,
-                rdi: out.pid,
-                ..Default::default()
-            }
-        }
-        Syscall::sys_yield => {
-            sys_yield(rcx);
-        }
-        Syscall::send_serial => {
-            let status = serial::sys_send_serial(rdi, rsi) as u64;
-
-            ReturnRegs {
-                rax: status,
-                ..Default::default()
-            }
-        }
-        _ => ReturnRegs {
+    let out = match abi::dispatch(&mut handler, out, [rdi, rsi, rdx, r8, r9, r10]) {
+        Some(regs) => ReturnRegs::from(regs),
+        None => ReturnRegs {
             rax: 0xFF,
             ..Default::default()
         },
@@ -270,13 +173,251 @@ pub unsafe fn syscall() {
     );
 }
 
+/// Runs syscalls on behalf of the current process
+struct SyscallContext {
+    /// Where the process continues once the syscall is done
+    rcx: *const (),
+}
+
+impl SyscallContext {
+    /// Saves where the current process should continue, for syscalls that might block
+    fn save_pc(&self) {
+        without_interrupts(|| {
+            let scheduler = &mut process::SCHEDULER.write();
+            let sender = scheduler.get_current().unwrap();
+
+            sender.pc = self.rcx as u64;
+        });
+    }
+}
+
+impl SyscallHandler for SyscallContext {
+    fn exit(&mut self) {
+        sys_exit();
+    }
+
+    fn config_rbuffer(&mut self, slot_size: u64, slots: u64) -> ConfigRBufferStatus {
+        unsafe { sys_config_rbuffer(slot_size, slots) }
+    }
+
+    fn grow_heap(&mut self, size: u64) -> GrowHeapResponse {
+        unsafe { vm::sys_grow_heap(size) }
+    }
+
+    fn release_rbuffer(&mut self, addr: u64) -> ReleaseRBufferStatus {
+        sys_release_rbuffer(addr)
+    }
+
+    fn send(&mut self, pid: Pid, data0: u64, data1: u64, data2: u64, data3: u64, timeout: Timeout) -> SendStatus {
+        self.save_pc();
+
+        let Some(status) = ipc::sys_send(pid, data0, data1, data2, data3, timeout) else { sys_yield(self.rcx) };
+
+        status
+    }
+
+    fn receive(&mut self, whitelist: *const Pid, whitelist_len: usize, timeout: Timeout) -> ReceiveResponse {
+        let Some(response) = (unsafe { ipc::sys_receive(whitelist as u64, whitelist_len as u64, timeout) }) else { sys_yield(self.rcx) };
+
+        response
+    }
+
+    fn notify(&mut self, pid: Pid, data0: u64, data1: u64, data2: u64, data3: u64) -> NotifyStatus {
+        ipc::sys_notify(pid, data0, data1, data2, data3)
+    }
+
+    fn read_mailbox(&mut self, sender: Pid, filter: bool) -> ReadMailboxResponse {
+        ipc::sys_read_mailbox(sender, filter)
+    }
+
+    fn config_mailbox(&mut self, flags: MailboxFlags, whitelist: *const Pid, whitelist_len: usize, capacity: usize, policy: OverflowPolicy) -> ConfigMailboxStatus {
+        unsafe { ipc::sys_config_mailbox(flags, whitelist as u64, whitelist_len as u64, capacity as u64, policy) }
+    }
+
+    fn send_payload(&mut self, pid: Pid, data0: u64, data1: u64, payload: *const u8, payload_len: usize, timeout: Timeout) -> SendStatus {
+        self.save_pc();
+
+        let Some(status) = ipc::sys_send_payload(pid, data0, data1, payload as u64, payload_len as u64, timeout) else { sys_yield(self.rcx) };
+
+        status
+    }
+
+    fn mailbox_info(&mut self, reset_dropped: bool) -> MailboxInfo {
+        ipc::sys_mailbox_info(reset_dropped)
+    }
+
+    fn send_grant(&mut self, pid: Pid, share: ShareId, access: MapFlags, data0: u64, data1: u64) -> SendStatus {
+        self.save_pc();
+
+        let Some(status) = ipc::sys_send_grant(pid, share, access, data0, data1) else { sys_yield(self.rcx) };
+
+        status
+    }
+
+    fn create_memshare(&mut self, start: u64, end: u64, whitelist: *const ShareGrant, whitelist_len: usize) -> CreateShareResponse {
+        unsafe { memshare::sys_create_memshare(start, end, whitelist as u64, whitelist_len as u64) }
+    }
+
+    fn join_memshare(&mut self, id: ShareId, start: u64, end: u64, access: MapFlags, blacklist: *const Pid, blacklist_len: usize) -> JoinShareResponse {
+        unsafe { memshare::sys_join_memshare(id, start, end, access, blacklist as u64, blacklist_len as u64) }
+    }
+
+    fn leave_memshare(&mut self, id: ShareId) -> LeaveShareStatus {
+        unsafe { memshare::sys_leave_memshare(id) }
+    }
+
+    fn destroy_memshare(&mut self, id: ShareId) -> DestroyShareStatus {
+        unsafe { memshare::sys_destroy_memshare(id) }
+    }
+
+    fn alloc_memshare(&mut self, pages: u64, whitelist: *const ShareGrant, whitelist_len: usize) -> AllocShareResponse {
+        unsafe { memshare::sys_alloc_memshare(pages, whitelist as u64, whitelist_len as u64) }
+    }
+
+    fn map_memory(&mut self, start: u64, size: u64, flags: MapFlags) -> MapMemoryResponse {
+        unsafe { vm::sys_map_memory(start, size, flags) }
+    }
+
+    fn unmap_memory(&mut self, start: u64, size: u64) -> UnmapMemoryStatus {
+        unsafe { vm::sys_unmap_memory(start, size) }
+    }
+
+    fn protect_memory(&mut self, start: u64, size: u64, flags: MapFlags) -> ProtectMemoryStatus {
+        unsafe { vm::sys_protect_memory(start, size, flags) }
+    }
+
+    fn request_fb(&mut self, descriptor: *mut FramebufferDescriptor) -> RequestFbStatus {
+        sys_request_fb(descriptor as u64)
+    }
+
+    fn getpid(&mut self, selector: u64) -> GetPidResponse {
+        sys_getpid(selector)
+    }
+
+    fn sys_yield(&mut self) {
+        sys_yield(self.rcx);
+    }
+
+    fn call(&mut self, pid: Pid, data0: u64, data1: u64, data2: u64, data3: u64) -> CallResponse {
+        self.save_pc();
+
+        let Some(status) = ipc::sys_call(pid, data0, data1, data2, data3) else { sys_yield(self.rcx) };
+
+        CallResponse { status, message: None }
+    }
+
+    fn call_payload(&mut self, pid: Pid, data0: u64, data1: u64, payload: *const u8, payload_len: usize) -> CallResponse {
+        self.save_pc();
+
+        let Some(status) = ipc::sys_call_payload(pid, data0, data1, payload as u64, payload_len as u64) else { sys_yield(self.rcx) };
+
+        CallResponse { status, message: None }
+    }
+
+    fn reply(&mut self, pid: Pid, data0: u64, data1: u64, data2: u64, data3: u64) -> ReplyStatus {
+        ipc::sys_reply(pid, data0, data1, data2, data3)
+    }
+
+    fn reply_and_receive(&mut self, pid: Pid, data0: u64, data1: u64, data2: u64, data3: u64) -> ReceiveResponse {
+        let Some(response) = ipc::sys_reply_and_receive(pid, data0, data1, data2, data3) else { sys_yield(self.rcx) };
+
+        response
+    }
+
+    fn cancel_send(&mut self, pid: Pid) -> CancelSendStatus {
+        ipc::sys_cancel_send(pid)
+    }
+
+    fn wait(&mut self, flags: WaitFlags, whitelist: *const Pid, whitelist_len: usize, notif_sender: Pid, timeout: Timeout) -> WaitResponse {
+        let Some(response) = (unsafe { ipc::sys_wait(flags, whitelist as u64, whitelist_len as u64, notif_sender, timeout) }) else { sys_yield(self.rcx) };
+
+        response
+    }
+
+    fn create_endpoint(&mut self) -> CreateEndpointResponse {
+        ipc::sys_create_endpoint()
+    }
+
+    fn destroy_endpoint(&mut self, endpoint: EndpointId) -> DestroyEndpointStatus {
+        ipc::sys_destroy_endpoint(endpoint)
+    }
+
+    fn grant_endpoint(&mut self, endpoint: EndpointId, pid: Pid, badge: u64) -> GrantEndpointStatus {
+        ipc::sys_grant_endpoint(endpoint, pid, badge)
+    }
+
+    fn revoke_endpoint(&mut self, endpoint: EndpointId, pid: Pid) -> RevokeEndpointStatus {
+        ipc::sys_revoke_endpoint(endpoint, pid)
+    }
+
+    fn send_endpoint(&mut self, endpoint: EndpointId, data0: u64, data1: u64, data2: u64, timeout: Timeout) -> SendStatus {
+        self.save_pc();
+
+        let Some(status) = ipc::sys_send_endpoint(endpoint, data0, data1, data2, timeout) else { sys_yield(self.rcx) };
+
+        status
+    }
+
+    fn call_endpoint(&mut self, endpoint: EndpointId, data0: u64, data1: u64, data2: u64) -> CallResponse {
+        self.save_pc();
+
+        let Some(status) = ipc::sys_call_endpoint(endpoint, data0, data1, data2) else { sys_yield(self.rcx) };
+
+        CallResponse { status, message: None }
+    }
+
+    fn receive_endpoint(&mut self, endpoint: EndpointId, timeout: Timeout) -> EndpointReceiveResponse {
+        let Some(response) = ipc::sys_receive_endpoint(endpoint, timeout) else { sys_yield(self.rcx) };
+
+        response
+    }
+
+    fn create_topic(&mut self) -> CreateTopicResponse {
+        ipc::sys_create_topic()
+    }
+
+    fn destroy_topic(&mut self, topic: TopicId) -> DestroyTopicStatus {
+        ipc::sys_destroy_topic(topic)
+    }
+
+    fn subscribe_topic(&mut self, topic: TopicId, pid: Pid) -> SubscribeTopicStatus {
+        ipc::sys_subscribe_topic(topic, pid)
+    }
+
+    fn unsubscribe_topic(&mut self, topic: TopicId, pid: Pid) -> UnsubscribeTopicStatus {
+        ipc::sys_unsubscribe_topic(topic, pid)
+    }
+
+    fn publish_topic(&mut self, topic: TopicId, data0: u64, data1: u64, data2: u64, data3: u64) -> PublishTopicResponse {
+        ipc::sys_publish_topic(topic, data0, data1, data2, data3)
+    }
+
+    fn send_serial(&mut self, text: *const u8, len: usize) -> SerialStatus {
+        unsafe { serial::sys_send_serial(text as u64, len as u64) }
+    }
+}
+
 fn sys_exit() -> ! {
     println!("Process exited");
     
     without_interrupts(|| {        
         let mut scheduler = process::SCHEDULER.write();
         scheduler.queue.rotate_left(1);
-        scheduler.queue.pop();
+        let mut process = scheduler.queue.pop().unwrap();
+
+        unsafe {
+            process.vmas.unmap_all();
+            crate::ipc::MEMORY_SHARE.lock().leave_all(process.pid);
+            crate::ipc::ENDPOINTS.lock().release_all(process.pid);
+            crate::ipc::TOPICS.lock().release_all(process.pid);
+            crate::ipc::abandon_calls(process.pid, &mut scheduler);
+            memory::free_address_space(process.cr3);
+        }
+
+        let frames = memory::PHYS_ALLOCATOR.lock().0.as_ref().unwrap().free_frames();
+        let heap = allocator::stats();
+        serial_println!("Kernel heap: {} bytes live, {} peak, {} total", heap.live, heap.peak, heap.size);
+        serial_println!("Free frames: {}", frames);
 
         if scheduler.queue.len() == 0 {
             loop {}
@@ -286,39 +427,66 @@ fn sys_exit() -> ! {
     process::run_next();
 }
 
-unsafe fn sys_config_rbuffer(size: u64) -> ConfigRBufferStatus {
+unsafe fn sys_config_rbuffer(slot_size: u64, slots: u64) -> ConfigRBufferStatus {
     serial_println!("Giving this bad boye a response buffer");
 
-    if size > RESPONSE_BUFFER_SIZE {
-        return ConfigRBufferStatus::TooBig;
+    if slot_size == 0 || slots == 0 {
+        return ConfigRBufferStatus::InvalidSlots;
     }
 
+    // slots start on a page so payloads never share one
+    let Some(slot_size) = slot_size.checked_add(4095).map(|size| size & !4095) else {
+        return ConfigRBufferStatus::TooBig;
+    };
+
+    let Some(size) = slot_size.checked_mul(slots).filter(|size| *size <= RESPONSE_BUFFER_SIZE) else {
+        return ConfigRBufferStatus::TooBig;
+    };
+
     let start = VirtAddr::new(RESPONSE_BUFFER);
     let end = start + (size - 1);
     let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
 
-    memory::map_area(start, end, flags).unwrap();
-
+    // the buffer is only reserved, payloads populate whatever pages they need
     without_interrupts(|| {
         let mut scheduler = SCHEDULER.write();
         let process = scheduler.get_current().unwrap();
-        
-        match process.response_buffer {
-            Some(ref mut rb) => rb.size = size,
-            None => process.response_buffer = Some(ResponseBuffer { size }),
+
+        // a smaller buffer gives back the pages past its end
+        let vma_end = (end + 1u64).align_up(4096u64).as_u64();
+
+        for vma in process.vmas.remove(vma_end, RESPONSE_BUFFER + RESPONSE_BUFFER_SIZE) {
+            vma.unmap();
         }
+
+        process.vmas.remove(RESPONSE_BUFFER, vma_end);
+        process.vmas.insert(Vma::new(RESPONSE_BUFFER, vma_end, VmaKind::ResponseBuffer, flags, Backing::Owned)).unwrap();
+        
+        process.response_buffer = Some(ResponseBuffer::new(slot_size, slots as usize));
     });
 
     ConfigRBufferStatus::Success
 }
 
-struct GetPidResponse {
-    status: u64,
-    pid: u64,
+fn sys_release_rbuffer(addr: u64) -> ReleaseRBufferStatus {
+    without_interrupts(|| {
+        let mut scheduler = SCHEDULER.write();
+        let process = scheduler.get_current().unwrap();
+
+        let Some(buffer) = process.response_buffer.as_mut() else {
+            return ReleaseRBufferStatus::NotInUse;
+        };
+
+        if buffer.release(addr) {
+            ReleaseRBufferStatus::Success
+        } else {
+            ReleaseRBufferStatus::NotInUse
+        }
+    })
 }
 
-fn sys_getpid(rdi: u64) -> GetPidResponse {
-    if rdi == 0 {
+fn sys_getpid(selector: u64) -> GetPidResponse {
+    if selector == 0 {
         interrupts::disable();
 
         let scheduler = process::SCHEDULER.read();
@@ -327,12 +495,12 @@ fn sys_getpid(rdi: u64) -> GetPidResponse {
         interrupts::enable();
 
         GetPidResponse {
-            status: 0,
+            status: GetPidStatus::Success,
             pid,
         }
     } else {
         GetPidResponse {
-            status: 10,
+            status: GetPidStatus::InvalidSelector,
             pid: 0,
         }
     }
@@ -348,18 +516,3 @@ fn sys_yield(rcx: *const ()) -> ! {
 
     process::run_next();
 }
-
-/// Builds a Vec from a start and a length
-/// 
-/// Returns an error if `start` is in kernel memory
-pub unsafe fn build_user_vec<T>(start: u64, len: usize) -> Result<Vec<T>, ()>
-where
-    T: Clone
-{
-    if start >= 0xffff_8000_0000_0000 {
-        return Err(());
-    }
-
-    let ptr = start as *const T;
-    Ok(slice::from_raw_parts(ptr, len as usize).to_vec())
-}
\ No newline at end of file
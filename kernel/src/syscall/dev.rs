@@ -1,28 +1,68 @@
-use abi::dev::{RequestFbStatus, FramebufferDescriptor};
-use x86_64::{structures::paging::{Page, Mapper, PageTableFlags, mapper::TranslateError, Size4KiB, Size2MiB}, VirtAddr, instructions::interrupts::{without_interrupts, self}};
+use core::mem::size_of;
 
-use crate::{vga, memory, process};
+use abi::{dev::{RequestFbStatus, FramebufferDescriptor}, layout::FB_START};
+use x86_64::{structures::paging::{Page, Mapper, PageTableFlags, mapper::TranslateError, Size4KiB, Size2MiB}, VirtAddr, instructions::interrupts::without_interrupts};
 
-const FB_START: u64 = 0x0000_7fff_0000_0000;
+use crate::{vga, memory::{self, user::{check_user_range, copy_to_user}}, process::{self, vma::{Vma, VmaKind, Backing}}};
 
 pub fn sys_request_fb(descriptor_ptr: u64) -> RequestFbStatus {
-    {
-        interrupts::disable();
-
+    let privileged = without_interrupts(|| {
         let scheduler = process::SCHEDULER.read();
-        let p = scheduler.queue.get(0).unwrap();
-    
-        if !p.privileged {
-            return RequestFbStatus::NotAllowed;
+        scheduler.queue.get(0).unwrap().privileged
+    });
+
+    if !privileged {
+        return RequestFbStatus::NotAllowed;
+    }
+
+    // make sure the descriptor can be written before mapping anything
+    if check_user_range(descriptor_ptr, size_of::<FramebufferDescriptor>() as u64, true).is_err() {
+        return RequestFbStatus::InvalidDescriptor;
+    }
+
+    let fb = &vga::FB;
+    let fb_virt = VirtAddr::new(fb.address);
+
+    without_interrupts(|| {
+        let mut scheduler = process::SCHEDULER.write();
+        let vmas = &mut scheduler.get_current().unwrap().vmas;
+
+        // asking again just gets the descriptor again
+        if vmas.last_of(VmaKind::Framebuffer).is_none() {
+            let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+            let end = unsafe { map_framebuffer(flags) };
+
+            vmas.insert(Vma::new(FB_START, end, VmaKind::Framebuffer, flags, Backing::Device)).unwrap();
         }
+    });
+
+    let user_fb_address = FB_START + u64::from(fb_virt.page_offset());
+
+    let descriptor = FramebufferDescriptor {
+        address: user_fb_address,
+        width: fb.width,
+        height: fb.height,
+        pitch: fb.pitch,
+        bpp: fb.bpp,
+        red_mask_size: fb.red_mask_size,
+        red_mask_shift: fb.red_mask_shift,
+        green_mask_size: fb.green_mask_size,
+        green_mask_shift: fb.green_mask_shift,
+        blue_mask_size: fb.blue_mask_size,
+        blue_mask_shift: fb.blue_mask_shift,
+    };
 
-        interrupts::enable();
+    if unsafe { copy_to_user(descriptor_ptr, &descriptor) }.is_err() {
+        return RequestFbStatus::InvalidDescriptor;
     }
 
+    RequestFbStatus::Success
+}
 
+/// Maps the framebuffer at `FB_START` in the current address space, returning the end of the mapping
+unsafe fn map_framebuffer(flags: PageTableFlags) -> u64 {
     let fb = &vga::FB;
     let size = fb.pitch * fb.height;
-    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
 
     let mut mapper = unsafe { memory::get_mapper() };
     let fb_virt = VirtAddr::new(fb.address);
@@ -31,6 +71,7 @@ pub fn sys_request_fb(descriptor_ptr: u64) -> RequestFbStatus {
 
     let mut frame_allocator = memory::PHYS_ALLOCATOR.lock();
     let frame_allocator = frame_allocator.0.as_mut().unwrap();
+    let mut end = FB_START;
 
     match frame {
         Ok(_) => {
@@ -43,6 +84,7 @@ pub fn sys_request_fb(descriptor_ptr: u64) -> RequestFbStatus {
                 let user_page = Page::from_start_address(user_page_start).unwrap();
 
                 unsafe { mapper.map_to(user_page, frame, flags, frame_allocator).unwrap().flush() };
+                end = user_page_start.as_u64() + 4096;
             }
         },
         Err(TranslateError::ParentEntryHugePage) => {
@@ -61,29 +103,11 @@ pub fn sys_request_fb(descriptor_ptr: u64) -> RequestFbStatus {
                 let user_page = Page::from_start_address(user_page_start).unwrap();
 
                 unsafe { mapper.map_to(user_page, frame, flags, frame_allocator).unwrap().flush() };
+                end = user_page_start.as_u64() + 1024 * 2048;
             }
         }
         _ => panic!("Framebuffer not mapped"),
     }
 
-    let user_fb_address = FB_START + u64::from(fb_virt.page_offset());
-
-    let descriptor_ptr = descriptor_ptr as *mut FramebufferDescriptor;
-    let descriptor = FramebufferDescriptor {
-        address: user_fb_address,
-        width: fb.width,
-        height: fb.height,
-        pitch: fb.pitch,
-        bpp: fb.bpp,
-        red_mask_size: fb.red_mask_size,
-        red_mask_shift: fb.red_mask_shift,
-        green_mask_size: fb.green_mask_size,
-        green_mask_shift: fb.green_mask_shift,
-        blue_mask_size: fb.blue_mask_size,
-        blue_mask_shift: fb.blue_mask_shift,
-    };
-
-    unsafe { *descriptor_ptr = descriptor };
-
-    RequestFbStatus::Success
-}
\ No newline at end of file
+    end
+}
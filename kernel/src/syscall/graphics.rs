@@ -1,7 +1,7 @@
 use abi::render::{DrawBitmapStatus, DrawStringStatus};
 use alloc::{string::String, vec::Vec};
 
-use crate::{vga, print, syscall::build_user_vec};
+use crate::{vga, print, memory::user::copy_from_user};
 
 #[no_mangle]
 pub unsafe fn sys_draw_bitmap(rdi: u64, rsi: u64, rdx: u64, _: u64, _: u64, _: u64) -> DrawBitmapStatus {
@@ -15,7 +15,7 @@ pub unsafe fn sys_draw_bitmap(rdi: u64, rsi: u64, rdx: u64, _: u64, _: u64, _: u
 
     let scale = (rdx & 0xFF) as u8;
 
-    let Ok(bitmap): Result<Vec<u8>, _> = build_user_vec(bitmap_start, width as usize * height as usize) else {
+    let Ok(bitmap): Result<Vec<u8>, _> = copy_from_user(bitmap_start, width as usize * height as usize) else {
         return DrawBitmapStatus::InvalidStart;
     };
 
@@ -43,7 +43,7 @@ pub unsafe fn sys_draw_string(rdi: u64, rsi: u64, rdx: u64, _: u64, _: u64, _: u
     let color = rdx_bytes[2] as u16 | ((rdx_bytes[3] as u16) << 8);
     let scale = (rdx & 0xFF) as u8;
 
-    let Ok(text_bytes): Result<Vec<u8>, _> = build_user_vec(text_start, length as usize) else {
+    let Ok(text_bytes): Result<Vec<u8>, _> = copy_from_user(text_start, length as usize) else {
         return DrawStringStatus::InvalidStart;
     };
 
@@ -61,7 +61,7 @@ pub unsafe fn sys_print(rdi: u64, rsi: u64, _: u64, _: u64, _: u64, _: u64) -> D
     let text_start = rdi;
     let length = rsi as usize;
 
-    let Ok(text_bytes): Result<Vec<u8>, _> = build_user_vec(text_start, length) else {
+    let Ok(text_bytes): Result<Vec<u8>, _> = copy_from_user(text_start, length) else {
         return DrawStringStatus::InvalidStart;
     };
 
@@ -1,69 +1,463 @@
-use abi::ipc::{SendStatus, Message, Pid, PayloadMessage, NotifyStatus, MailboxFlags, ConfigMailboxStatus, ReceiveStatus};
+use abi::{
+    Status,
+    endpoint::{EndpointId, EndpointMessage, EndpointReceiveResponse, CreateEndpointResponse, CreateEndpointStatus, DestroyEndpointStatus, GrantEndpointStatus, RevokeEndpointStatus},
+    ipc::{SendStatus, Message, Pid, PayloadMessage, NotifyStatus, MailboxFlags, ConfigMailboxStatus, ReceiveStatus, ReceiveResponse, ReadMailboxResponse, ReplyStatus, CancelSendStatus, Timeout, WaitFlags, WaitStatus, WaitResponse, OverflowPolicy, MailboxInfo, MAX_MAILBOX_CAPACITY},
+    memshare::ShareId,
+    topic::{TopicId, CreateTopicResponse, CreateTopicStatus, DestroyTopicStatus, SubscribeTopicStatus, UnsubscribeTopicStatus, PublishTopicResponse, PublishTopicStatus},
+    vm::MapFlags,
+};
 
 use alloc::vec::Vec;
 use x86_64::instructions::interrupts;
 
-use crate::{ipc::{MessageState, self}, process::{SCHEDULER, ReturnRegs, self}, serial_println};
+use crate::{ipc::{MessageState, AfterSend, self}, process::{SCHEDULER, self}, serial_println};
 
-use super::build_user_vec;
+use crate::memory::user::copy_from_user;
 
 /// Sets a message to be sent to the process with PID `pid`
 /// 
 /// If the recipient is not currently waiting for a message, the system will reattempt sending the message every time this process is scheduled until it is received
 /// 
-/// Returns Some if the message send completed, or None if the recipient was not yet ready
-pub fn sys_send(pid: Pid, data0: u64, data1: u64, data2: u64, data3: u64) -> Option<SendStatus> {
+/// Returns Some if the message send completed, or None if the recipient was not yet ready. If the recipient isn't
+/// ready and `timeout` is `Poll`, the send is dropped instead
+pub fn sys_send(pid: Pid, data0: u64, data1: u64, data2: u64, data3: u64, timeout: Timeout) -> Option<SendStatus> {
+    send(Message { pid, data0, data1, data2, data3 }, AfterSend::Return, timeout)
+}
+
+/// Sets a message to be sent to the process with PID `pid`, which lets it join the memory share `share` with `access`
+///
+/// Follows the same rules as `send`, the recipient only gets added to the share's whitelist once it receives the message
+pub fn sys_send_grant(pid: Pid, share: ShareId, access: MapFlags, data0: u64, data1: u64) -> Option<SendStatus> {
     interrupts::disable();
 
     let from = SCHEDULER.read().queue.get(0).unwrap().pid;
-    let scheduler = &mut SCHEDULER.write();
-    let state = ipc::send_message(from, Message { pid, data0, data1, data2, data3 }, scheduler);
-
-    if let Some(state) = state {
-        match state {
-            MessageState::Received => {
-                {
-                    let sender = scheduler.queue.iter_mut().find(|p| p.pid == from).unwrap();
-                    sender.reg_state = ReturnRegs {
-                        rax: SendStatus::Success as u64,
-                        ..Default::default()
-                    };
-                }
-
-                let scheduler = 0;
-
-                process::run_process();
-            },
-            MessageState::Blocked => {
-                interrupts::enable();
-                Some(SendStatus::Blocked)
-            },
-            MessageState::Waiting => {
-                interrupts::enable();
-                None
-            },
-            e => panic!("sys_send is {:?}", e),
-        }
-    } else {
-        Some(SendStatus::InvalidRecipient)
+
+    if let Err(e) = ipc::MEMORY_SHARE.lock().check_grant(share, from, access) {
+        interrupts::enable();
+        return Some(e);
+    }
+
+    let message = Message { pid, data0, data1, data2: share, data3: access.into() };
+
+    send(message, AfterSend::Grant(share, access), Timeout::Forever)
+}
+
+/// Sets a message to be sent to the process with PID `pid`, then blocks until it replies
+///
+/// Follows the same rules as `send`, except the caller keeps waiting after the message is received. Returns Some if the
+/// call failed before that
+pub fn sys_call(pid: Pid, data0: u64, data1: u64, data2: u64, data3: u64) -> Option<SendStatus> {
+    send(Message { pid, data0, data1, data2, data3 }, AfterSend::AwaitReply, Timeout::Forever)
+}
+
+/// Sets a payload message to be sent to the process with PID `pid`, then blocks until it replies
+///
+/// Follows the same rules as `call`
+pub fn sys_call_payload(pid: Pid, data0: u64, data1: u64, payload: u64, payload_len: u64) -> Option<SendStatus> {
+    send_payload(PayloadMessage { pid, data0, data1, payload, payload_len }, AfterSend::AwaitReply, Timeout::Forever)
+}
+
+/// Wakes up the process with PID `pid` with a reply, if it's waiting on one from the current process
+pub fn sys_reply(pid: Pid, data0: u64, data1: u64, data2: u64, data3: u64) -> ReplyStatus {
+    interrupts::disable();
+
+    let mut scheduler = SCHEDULER.write();
+    let from = scheduler.queue.get(0).unwrap().pid;
+
+    let status = ipc::reply(from, Message { pid, data0, data1, data2, data3 }, &mut scheduler);
+
+    interrupts::enable();
+
+    status
+}
+
+/// Replies to the process with PID `pid` like `reply`, then receives a message from any process like `receive`
+pub fn sys_reply_and_receive(pid: Pid, data0: u64, data1: u64, data2: u64, data3: u64) -> Option<ReceiveResponse> {
+    let status = sys_reply(pid, data0, data1, data2, data3);
+
+    if status.is_err() {
+        serial_println!("[IPC] Dropped reply to PID {}: {:?}", pid, status);
     }
+
+    receive(Vec::new(), Timeout::Forever)
+}
+
+/// Fails a send or call from the process with PID `pid` that's waiting on the current process
+pub fn sys_cancel_send(pid: Pid) -> CancelSendStatus {
+    interrupts::disable();
+
+    let mut scheduler = SCHEDULER.write();
+    let recipient = scheduler.queue.get(0).unwrap().pid;
+
+    let status = ipc::cancel_send(recipient, pid, &mut scheduler);
+
+    interrupts::enable();
+
+    status
 }
 
+/// Creates an endpoint owned by the current process
+pub fn sys_create_endpoint() -> CreateEndpointResponse {
+    interrupts::disable();
 
-/// Blocks until a message is received from a whitelisted process
-pub unsafe fn sys_receive(whitelist_start: u64, whitelist_len: u64) -> ReceiveStatus {
+    let owner = SCHEDULER.read().queue.get(0).unwrap().pid;
+    let id = ipc::ENDPOINTS.lock().create(owner);
+
+    interrupts::enable();
+
+    CreateEndpointResponse { status: CreateEndpointStatus::Success, id }
+}
+
+/// Destroys an endpoint the current process owns
+///
+/// Sends to it that haven't been received yet fail the next time they're retried
+pub fn sys_destroy_endpoint(endpoint: EndpointId) -> DestroyEndpointStatus {
     interrupts::disable();
 
     let pid = SCHEDULER.read().queue.get(0).unwrap().pid;
+    let status = ipc::ENDPOINTS.lock().destroy(endpoint, pid);
+
+    interrupts::enable();
+
+    match status {
+        Ok(()) => DestroyEndpointStatus::Success,
+        Err(e) => e,
+    }
+}
+
+/// Lets the process with PID `pid` send to an endpoint with `badge`, or with the current process's badge if it isn't the owner
+pub fn sys_grant_endpoint(endpoint: EndpointId, pid: Pid, badge: u64) -> GrantEndpointStatus {
+    interrupts::disable();
+
+    let from = SCHEDULER.read().queue.get(0).unwrap().pid;
+    let status = ipc::ENDPOINTS.lock().grant(endpoint, from, pid, badge);
 
     interrupts::enable();
 
-    let Ok(whitelist): Result<Vec<u64>, _> = build_user_vec(whitelist_start, whitelist_len as usize) else {
-        return ReceiveStatus::InvalidWhitelist;    
+    match status {
+        Ok(()) => GrantEndpointStatus::Success,
+        Err(e) => e,
+    }
+}
+
+/// Stops the process with PID `pid` from sending to an endpoint the current process owns
+pub fn sys_revoke_endpoint(endpoint: EndpointId, pid: Pid) -> RevokeEndpointStatus {
+    interrupts::disable();
+
+    let from = SCHEDULER.read().queue.get(0).unwrap().pid;
+    let status = ipc::ENDPOINTS.lock().revoke(endpoint, from, pid);
+
+    interrupts::enable();
+
+    match status {
+        Ok(()) => RevokeEndpointStatus::Success,
+        Err(e) => e,
+    }
+}
+
+/// Sets a message to be sent to the owner of `endpoint`
+///
+/// Follows the same rules as `send`
+pub fn sys_send_endpoint(endpoint: EndpointId, data0: u64, data1: u64, data2: u64, timeout: Timeout) -> Option<SendStatus> {
+    send_endpoint(endpoint, EndpointMessage { data0, data1, data2, ..Default::default() }, AfterSend::Return, timeout)
+}
+
+/// Sets a message to be sent to the owner of `endpoint`, then blocks until it replies
+///
+/// Follows the same rules as `call`
+pub fn sys_call_endpoint(endpoint: EndpointId, data0: u64, data1: u64, data2: u64) -> Option<SendStatus> {
+    send_endpoint(endpoint, EndpointMessage { data0, data1, data2, ..Default::default() }, AfterSend::AwaitReply, Timeout::Forever)
+}
+
+/// Receives a message sent to `endpoint`, taking it from a process that's already waiting to send if there is one
+///
+/// Returns like `sys_receive`
+pub fn sys_receive_endpoint(endpoint: EndpointId, timeout: Timeout) -> Option<EndpointReceiveResponse> {
+    interrupts::disable();
+
+    let mut scheduler = SCHEDULER.write();
+    let pid = scheduler.queue.get(0).unwrap().pid;
+
+    if ipc::ENDPOINTS.lock().owner(endpoint) != Some(pid) {
+        drop(scheduler);
+        interrupts::enable();
+
+        return Some(ReceiveStatus::InvalidEndpoint.into());
+    }
+
+    ipc::receive_endpoint(scheduler.get_current().unwrap(), endpoint, ipc::deadline(timeout));
+
+    let response = if let Some(message) = ipc::pull_endpoint_message(pid, &mut scheduler) {
+        Some(EndpointReceiveResponse { status: ReceiveStatus::Success, message: Some(message) })
+    } else if timeout == Timeout::Poll {
+        ipc::stop_waiting(scheduler.get_current().unwrap());
+        Some(ReceiveStatus::WouldBlock.into())
+    } else {
+        None
     };
 
-    ipc::receive_message(pid, whitelist);
-    ReceiveStatus::Success
+    drop(scheduler);
+    interrupts::enable();
+
+    response
+}
+
+/// Creates a topic owned by the current process
+pub fn sys_create_topic() -> CreateTopicResponse {
+    interrupts::disable();
+
+    let owner = SCHEDULER.read().queue.get(0).unwrap().pid;
+    let id = ipc::TOPICS.lock().create(owner);
+
+    interrupts::enable();
+
+    CreateTopicResponse { status: CreateTopicStatus::Success, id }
+}
+
+/// Destroys a topic the current process owns
+pub fn sys_destroy_topic(topic: TopicId) -> DestroyTopicStatus {
+    interrupts::disable();
+
+    let pid = SCHEDULER.read().queue.get(0).unwrap().pid;
+    let status = ipc::TOPICS.lock().destroy(topic, pid);
+
+    interrupts::enable();
+
+    match status {
+        Ok(()) => DestroyTopicStatus::Success,
+        Err(e) => e,
+    }
+}
+
+/// Subscribes the process with PID `pid` to a topic, which has to be the current process unless it owns the topic
+pub fn sys_subscribe_topic(topic: TopicId, pid: Pid) -> SubscribeTopicStatus {
+    interrupts::disable();
+
+    let from = SCHEDULER.read().queue.get(0).unwrap().pid;
+    let status = ipc::TOPICS.lock().subscribe(topic, from, pid);
+
+    interrupts::enable();
+
+    match status {
+        Ok(()) => SubscribeTopicStatus::Success,
+        Err(e) => e,
+    }
+}
+
+/// Unsubscribes the process with PID `pid` from a topic, which has to be the current process unless it owns the topic
+pub fn sys_unsubscribe_topic(topic: TopicId, pid: Pid) -> UnsubscribeTopicStatus {
+    interrupts::disable();
+
+    let from = SCHEDULER.read().queue.get(0).unwrap().pid;
+    let status = ipc::TOPICS.lock().unsubscribe(topic, from, pid);
+
+    interrupts::enable();
+
+    match status {
+        Ok(()) => UnsubscribeTopicStatus::Success,
+        Err(e) => e,
+    }
+}
+
+/// Notifies every subscriber of a topic the current process owns
+///
+/// Subscribers whose mailboxes turn the event away just miss it, the rest are counted in the response
+pub fn sys_publish_topic(topic: TopicId, data0: u64, data1: u64, data2: u64, data3: u64) -> PublishTopicResponse {
+    interrupts::disable();
+
+    let mut scheduler = SCHEDULER.write();
+    let from = scheduler.queue.get(0).unwrap().pid;
+
+    let subscribers = match ipc::TOPICS.lock().subscribers(topic, from) {
+        Ok(subscribers) => subscribers,
+        Err(e) => {
+            drop(scheduler);
+            interrupts::enable();
+
+            return e.into();
+        }
+    };
+
+    let delivered = subscribers.into_iter()
+        .filter(|pid| ipc::notify(from, Message { pid: *pid, data0, data1, data2, data3 }, &mut scheduler) == NotifyStatus::Success)
+        .count();
+
+    drop(scheduler);
+    interrupts::enable();
+
+    PublishTopicResponse { status: PublishTopicStatus::Success, delivered: delivered as u64 }
+}
+
+/// Tries to deliver a message from the current process, applying `after` to it if it got through
+///
+/// Switches to the recipient if the message was received, otherwise returns like `sys_send`
+fn send(message: Message, after: AfterSend, timeout: Timeout) -> Option<SendStatus> {
+    interrupts::disable();
+
+    let state = {
+        let scheduler = &mut SCHEDULER.write();
+        let from = scheduler.queue.get(0).unwrap().pid;
+
+        ipc::send_message(from, message, after, scheduler)
+    };
+
+    settle(state, timeout)
+}
+
+/// Tries to deliver a payload message from the current process, applying `after` to it if it got through
+///
+/// Follows the same rules as `send`
+fn send_payload(message: PayloadMessage, after: AfterSend, timeout: Timeout) -> Option<SendStatus> {
+    interrupts::disable();
+
+    let state = {
+        let scheduler = &mut SCHEDULER.write();
+        let from = scheduler.queue.get(0).unwrap().pid;
+
+        unsafe { ipc::send_payload(from, message, after, scheduler) }
+    };
+
+    settle(state, timeout)
+}
+
+/// Tries to deliver a message from the current process to the owner of `endpoint`, applying `after` to it if it got through
+///
+/// Follows the same rules as `send`
+fn send_endpoint(endpoint: EndpointId, message: EndpointMessage, after: AfterSend, timeout: Timeout) -> Option<SendStatus> {
+    interrupts::disable();
+
+    let state = {
+        let scheduler = &mut SCHEDULER.write();
+        let from = scheduler.queue.get(0).unwrap().pid;
+
+        ipc::send_endpoint_message(from, endpoint, message, after, scheduler)
+    };
+
+    settle(state, timeout)
+}
+
+/// Runs the recipient if a message was received, otherwise turns the result of a send into what the syscall returns
+///
+/// A sender that has to wait gets its deadline from `timeout`, or stops sending if it can't wait.
+/// Interrupts have to be disabled already, they're enabled again if this returns
+fn settle(state: Result<MessageState, SendStatus>, timeout: Timeout) -> Option<SendStatus> {
+    let status = match state {
+        Ok(MessageState::Received) => process::run_process(),
+        Ok(MessageState::Waiting) => {
+            let mut scheduler = SCHEDULER.write();
+            let sender = scheduler.get_current().unwrap();
+
+            if timeout == Timeout::Poll {
+                ipc::stop_waiting(sender);
+                Some(SendStatus::WouldBlock)
+            } else {
+                sender.message_handler.deadline = ipc::deadline(timeout);
+                None
+            }
+        },
+        Ok(MessageState::Blocked) => Some(SendStatus::Blocked),
+        Ok(e) => panic!("Send is {:?}", e),
+        Err(status) => Some(status),
+    };
+
+    interrupts::enable();
+
+    status
+}
+
+
+/// Receives a message from a whitelisted process, taking it from a process that's already waiting to send if there is one
+///
+/// Returns Some if the receive completed, or None if the current process has to block until a message is sent or
+/// `timeout` runs out
+pub unsafe fn sys_receive(whitelist_start: u64, whitelist_len: u64, timeout: Timeout) -> Option<ReceiveResponse> {
+    let Ok(whitelist): Result<Vec<u64>, _> = copy_from_user(whitelist_start, whitelist_len as usize) else {
+        return Some(ReceiveStatus::InvalidWhitelist.into());
+    };
+
+    receive(whitelist, timeout)
+}
+
+/// Starts receiving a message for the current process, returning like `sys_receive`
+fn receive(whitelist: Vec<Pid>, timeout: Timeout) -> Option<ReceiveResponse> {
+    interrupts::disable();
+
+    let mut scheduler = SCHEDULER.write();
+    let pid = scheduler.queue.get(0).unwrap().pid;
+
+    ipc::receive_message(scheduler.get_current().unwrap(), whitelist, ipc::deadline(timeout));
+
+    let response = if let Some((message, payload)) = ipc::pull_message(pid, &mut scheduler) {
+        let status = if payload { ReceiveStatus::Payload } else { ReceiveStatus::Success };
+
+        Some(ReceiveResponse { status, message: Some(message) })
+    } else if timeout == Timeout::Poll {
+        ipc::stop_waiting(scheduler.get_current().unwrap());
+        Some(ReceiveStatus::WouldBlock.into())
+    } else {
+        None
+    };
+
+    drop(scheduler);
+    interrupts::enable();
+
+    response
+}
+
+/// Waits for a synchronous message from a whitelisted process or a notification, depending on `flags`
+///
+/// Notifications already in the mailbox are taken first, then messages from processes already waiting to send. Returns
+/// Some if the wait completed, or None if the current process has to block until something arrives or `timeout` runs out
+pub unsafe fn sys_wait(flags: WaitFlags, whitelist_start: u64, whitelist_len: u64, notif_sender: Pid, timeout: Timeout) -> Option<WaitResponse> {
+    let whitelist = if flags.receive {
+        let Ok(whitelist): Result<Vec<u64>, _> = copy_from_user(whitelist_start, whitelist_len as usize) else {
+            return Some(WaitStatus::InvalidWhitelist.into());
+        };
+
+        whitelist
+    } else {
+        Vec::new()
+    };
+
+    interrupts::disable();
+
+    let mut scheduler = SCHEDULER.write();
+    let process = scheduler.get_current().unwrap();
+    let pid = process.pid;
+
+    let response = 'wait: {
+        if flags.notify {
+            if !process.message_handler.mailbox.enabled {
+                break 'wait Some(WaitStatus::MailboxDisabled.into());
+            }
+
+            if let Some(message) = ipc::read_mailbox(process, notif_sender, flags.filter).message {
+                break 'wait Some(WaitResponse { status: WaitStatus::Notified, message: Some(message) });
+            }
+        }
+
+        ipc::listen(process, whitelist, flags, notif_sender, ipc::deadline(timeout));
+
+        if let Some((message, payload)) = ipc::pull_message(pid, &mut scheduler) {
+            let status = if payload { WaitStatus::ReceivedPayload } else { WaitStatus::Received };
+
+            Some(WaitResponse { status, message: Some(message) })
+        } else if timeout == Timeout::Poll {
+            ipc::stop_waiting(scheduler.get_current().unwrap());
+            Some(WaitStatus::WouldBlock.into())
+        } else {
+            None
+        }
+    };
+
+    drop(scheduler);
+
+    // a notification from an interrupt could wake it up before it yields, so interrupts stay off until it does
+    if response.is_some() {
+        interrupts::enable();
+    }
+
+    response
 }
 
 /// Sends a message to the mailbox of the target process without blocking
@@ -81,24 +475,37 @@ pub fn sys_notify(pid: Pid, data0: u64, data1: u64, data2: u64, data3: u64) -> N
 }
 
 /// Reads the newest message from the mailbox, or returns an error if there is none
-pub fn sys_read_mailbox(sender_pid: Pid, filter: u64) -> ReturnRegs {
+pub fn sys_read_mailbox(sender_pid: Pid, filter: bool) -> ReadMailboxResponse {
     interrupts::disable();
 
     let mut scheduler = SCHEDULER.write();
     let recipient = scheduler.get_current().unwrap();
 
-    let regs = ipc::read_mailbox(recipient, sender_pid, filter > 0);
+    let response = ipc::read_mailbox(recipient, sender_pid, filter);
 
     interrupts::enable();
 
-    regs
+    response
 }
 
 /// Configures the mailbox of the current process
 /// 
 /// If the `enable` flag (flags.0) is unset, the whitelist won't be changed
-pub unsafe fn sys_config_mailbox(flags: u64, whitelist_ptr: u64, whitelist_len: u64) -> ConfigMailboxStatus {
-    let flags: MailboxFlags = flags.into();
+pub unsafe fn sys_config_mailbox(flags: MailboxFlags, whitelist_ptr: u64, whitelist_len: u64, capacity: u64, policy: OverflowPolicy) -> ConfigMailboxStatus {
+    if flags.set_capacity && (capacity == 0 || capacity > MAX_MAILBOX_CAPACITY as u64) {
+        return ConfigMailboxStatus::InvalidCapacity;
+    }
+
+    // validate the whitelist before touching the mailbox so a bad pointer leaves it unchanged
+    let whitelist = if flags.set_whitelist {
+        let Ok(whitelist): Result<Vec<u64>, _> = copy_from_user(whitelist_ptr, whitelist_len as usize) else {
+            return ConfigMailboxStatus::InvalidWhitelist;
+        };
+
+        Some(whitelist)
+    } else {
+        None
+    };
 
     interrupts::disable();
 
@@ -108,57 +515,41 @@ pub unsafe fn sys_config_mailbox(flags: u64, whitelist_ptr: u64, whitelist_len:
 
     mailbox.enabled = flags.enable;
 
-    if flags.set_whitelist {
-        let Ok(whitelist): Result<Vec<u64>, _> = build_user_vec(whitelist_ptr, whitelist_len as usize) else {
-            return ConfigMailboxStatus::InvalidWhitelist;
-        };
-    
+    if let Some(whitelist) = whitelist {
         mailbox.whitelist = whitelist;
     }
 
+    if flags.set_capacity {
+        mailbox.resize(capacity as usize, policy);
+    }
+
     interrupts::enable();
 
     ConfigMailboxStatus::Success
 }
 
-/// Sets a payload message to be sent to the process with PID `pid`
-/// 
-/// Follows the same rules as `send`
-pub fn sys_send_payload(pid: Pid, data0: u64, data1: u64, payload: u64, payload_len: u64) -> Option<(SendStatus)> {
+/// Gets the state of the current process's mailbox, resetting its dropped notification counter if `reset_dropped` is set
+pub fn sys_mailbox_info(reset_dropped: bool) -> MailboxInfo {
     interrupts::disable();
 
-    let from = SCHEDULER.read().queue.get(0).unwrap().pid;
-    let state = {
-        let scheduler = &mut SCHEDULER.write();
-        unsafe { ipc::send_payload(from, PayloadMessage { pid, data0, data1, payload, payload_len }, scheduler) }
-    };
+    let mut scheduler = SCHEDULER.write();
+    let mailbox = &mut scheduler.get_current().unwrap().message_handler.mailbox;
 
-    match state {
-        Ok(state) => match state {
-            MessageState::Received => {
-                {
-                    let scheduler = &mut SCHEDULER.write();
-                    let sender = scheduler.queue.iter_mut().find(|p| p.pid == from).unwrap();
-                    sender.reg_state = ReturnRegs {
-                        rax: SendStatus::Success as u64,
-                        ..Default::default()
-                    };
-                }
-    
-                process::run_process();
-            },
-            MessageState::Blocked => {
-                interrupts::enable();
-                Some(SendStatus::Blocked)
-            },
-            MessageState::Waiting => {
-                interrupts::enable();
-                None
-            },
-            e => panic!("sys_send_payload is {:?}", e),
-        }
-        Err(status) => {
-            Some(status)
-        }
+    let info = mailbox.info();
+
+    if reset_dropped {
+        mailbox.dropped = 0;
     }
-}
\ No newline at end of file
+
+    drop(scheduler);
+    interrupts::enable();
+
+    info
+}
+
+/// Sets a payload message to be sent to the process with PID `pid`
+/// 
+/// Follows the same rules as `send`
+pub fn sys_send_payload(pid: Pid, data0: u64, data1: u64, payload: u64, payload_len: u64, timeout: Timeout) -> Option<SendStatus> {
+    send_payload(PayloadMessage { pid, data0, data1, payload, payload_len }, AfterSend::Return, timeout)
+}
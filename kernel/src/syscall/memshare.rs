@@ -1,9 +1,16 @@
 use alloc::vec::Vec;
-use x86_64::{structures::paging::{Page, Size4KiB}, VirtAddr, instructions::interrupts};
+use x86_64::{structures::paging::{Page, PageTableFlags, Size4KiB}, VirtAddr, instructions::interrupts::{self, without_interrupts}};
 
-use crate::{ipc, process, serial_println, syscall::build_user_vec};
-use abi::memshare::{CreateShareStatus, JoinShareStatus, CreateShareResponse};
+use crate::{ipc, process::{self, vma::{Vma, VmaKind, Backing}}, serial_println, memory::{self, user::{copy_from_user, USER_END}}};
+use abi::{
+    memshare::{
+        ShareId, ShareGrant, CreateShareStatus, JoinShareStatus, LeaveShareStatus, DestroyShareStatus, AllocShareStatus,
+        CreateShareResponse, JoinShareResponse, AllocShareResponse,
+    },
+    vm::{MapFlags, MAP_START, MAP_END},
+};
 
+const SHARE_FLAGS: PageTableFlags = PageTableFlags::PRESENT.union(PageTableFlags::WRITABLE).union(PageTableFlags::USER_ACCESSIBLE);
 
 pub unsafe fn sys_create_memshare(start: u64, end: u64, whitelist_start: u64, whitelist_len: u64) -> CreateShareResponse {
     let Ok(start_page): Result<Page<Size4KiB>, _> = Page::from_start_address(VirtAddr::new(start)) else {
@@ -14,48 +21,182 @@ pub unsafe fn sys_create_memshare(start: u64, end: u64, whitelist_start: u64, wh
         return CreateShareStatus::UnalignedEnd.into();
     };
 
+    if start > end || end >= USER_END {
+        return CreateShareStatus::OutOfBounds.into();
+    }
+
     interrupts::disable();
     let pid = process::SCHEDULER.read().queue.get(0).unwrap().pid;
     interrupts::enable();
 
-    let Ok(whitelist): Result<Vec<u64>, _> = build_user_vec(whitelist_start, whitelist_len as usize) else {
+    let Ok(whitelist): Result<Vec<ShareGrant>, _> = copy_from_user(whitelist_start, whitelist_len as usize) else {
         return CreateShareResponse {
             status: CreateShareStatus::OutOfBounds,
             id: None,
         };
     };
 
-    serial_println!("Creating memshare");
+    // only memory the process mapped for itself can be handed over to a share, not anything the kernel manages
+    let not_owned = without_interrupts(|| {
+        let mut scheduler = process::SCHEDULER.write();
+        let vmas = &scheduler.get_current().unwrap().vmas;
+
+        !vmas.owns(start, end + 4096)
+            || vmas.in_range(start, end + 4096).any(|vma| !matches!(vma.kind, VmaKind::Anonymous | VmaKind::Heap))
+    });
 
-    match ipc::MEMORY_SHARE.lock().create(start_page, end_page, pid, whitelist) {
-        Ok(id) => CreateShareResponse { status: CreateShareStatus::Success, id: Some(id) },
-        Err(e) => e.into(),
+    if not_owned {
+        return CreateShareStatus::NotOwned.into();
     }
+
+    serial_println!("Creating memshare");
+
+    let id = match ipc::MEMORY_SHARE.lock().create(start_page, end_page, pid, whitelist) {
+        Ok(id) => id,
+        Err(e) => return e.into(),
+    };
+
+    // the frames belong to the share now, so they don't get freed along with the process
+    without_interrupts(|| {
+        let mut scheduler = process::SCHEDULER.write();
+        let vmas = &mut scheduler.get_current().unwrap().vmas;
+
+        vmas.remove(start, end + 4096);
+        vmas.insert(Vma::new(start, end + 4096, VmaKind::Shared, SHARE_FLAGS, Backing::Share(id))).unwrap();
+    });
+
+    CreateShareResponse { status: CreateShareStatus::Success, id: Some(id) }
 }
 
-pub unsafe fn sys_join_memshare(id: u64, start: u64, end: u64, blacklist_start: u64, blacklist_len: u64) -> JoinShareStatus {
+pub unsafe fn sys_join_memshare(id: u64, start: u64, end: u64, access: MapFlags, blacklist_start: u64, blacklist_len: u64) -> JoinShareResponse {
+    let (start, end) = if start == 0 && end == 0 {
+        let Some(pages) = ipc::MEMORY_SHARE.lock().page_count(id) else {
+            return JoinShareStatus::NotExists.into();
+        };
+
+        let size = pages as u64 * 4096;
+
+        let found = without_interrupts(|| {
+            process::SCHEDULER.write().get_current().unwrap().vmas.find_free(MAP_START, MAP_END, size)
+        });
+
+        let Some(start) = found else {
+            return JoinShareStatus::NoSpace.into();
+        };
+
+        (start, start + size - 4096)
+    } else {
+        (start, end)
+    };
+
     let Ok(start_page): Result<Page<Size4KiB>, _> = Page::from_start_address(VirtAddr::new(start)) else {
-        return JoinShareStatus::UnalignedStart;
+        return JoinShareStatus::UnalignedStart.into();
     };
 
     let Ok(end_page): Result<Page<Size4KiB>, _> = Page::from_start_address(VirtAddr::new(end)) else {
-        return JoinShareStatus::UnalignedEnd;
+        return JoinShareStatus::UnalignedEnd.into();
     };
 
+    if start > end || end >= USER_END {
+        return JoinShareStatus::OutOfBounds.into();
+    }
+
     interrupts::disable();
     let pid = process::SCHEDULER.read().queue.get(0).unwrap().pid;
     interrupts::enable();
 
-    let Ok(blacklist): Result<Vec<u64>, _> = build_user_vec(blacklist_start, blacklist_len as usize) else {
-        return JoinShareStatus::OutOfBounds;
+    let Ok(blacklist): Result<Vec<u64>, _> = copy_from_user(blacklist_start, blacklist_len as usize) else {
+        return JoinShareStatus::OutOfBounds.into();
     };
 
+    let overlaps = without_interrupts(|| {
+        process::SCHEDULER.write().get_current().unwrap().vmas.overlaps(start, end + 4096)
+    });
+
+    if overlaps {
+        return JoinShareStatus::AlreadyMapped.into();
+    }
+
     serial_println!("Joining memshare");
 
-    match ipc::MEMORY_SHARE.lock().join(id, start_page, end_page, pid, blacklist) {
-        Ok(_) => {
-            JoinShareStatus::Success
-        },
-        Err(e) => e,
+    if let Err(e) = ipc::MEMORY_SHARE.lock().join(id, start_page, end_page, pid, access, blacklist) {
+        return e.into();
     }
-}
\ No newline at end of file
+
+    without_interrupts(|| {
+        let mut scheduler = process::SCHEDULER.write();
+        let vmas = &mut scheduler.get_current().unwrap().vmas;
+
+        vmas.insert(Vma::new(start, end + 4096, VmaKind::Shared, memory::user_page_flags(access), Backing::Share(id))).unwrap();
+    });
+
+    JoinShareResponse { status: JoinShareStatus::Success, start, size: end + 4096 - start }
+}
+
+pub unsafe fn sys_leave_memshare(id: ShareId) -> LeaveShareStatus {
+    without_interrupts(|| {
+        let mut scheduler = process::SCHEDULER.write();
+        let process = scheduler.get_current().unwrap();
+
+        // the frames stay with the share, and get freed below if this was the last member
+        for vma in process.vmas.remove_share(id) {
+            vma.unmap();
+        }
+
+        match ipc::MEMORY_SHARE.lock().leave(id, process.pid) {
+            Ok(()) => LeaveShareStatus::Success,
+            Err(e) => e,
+        }
+    })
+}
+
+pub unsafe fn sys_destroy_memshare(id: ShareId) -> DestroyShareStatus {
+    without_interrupts(|| {
+        let mut scheduler = process::SCHEDULER.write();
+        let process = scheduler.get_current().unwrap();
+        let mut shares = ipc::MEMORY_SHARE.lock();
+
+        if let Err(e) = shares.destroy(id, process.pid) {
+            return e;
+        }
+
+        for vma in process.vmas.remove_share(id) {
+            vma.unmap();
+        }
+
+        DestroyShareStatus::Success
+    })
+}
+
+pub unsafe fn sys_alloc_memshare(pages: u64, whitelist_start: u64, whitelist_len: u64) -> AllocShareResponse {
+    let Some(size) = pages.checked_mul(4096).filter(|&size| size > 0) else {
+        return AllocShareStatus::InvalidSize.into();
+    };
+
+    let Ok(whitelist): Result<Vec<ShareGrant>, _> = copy_from_user(whitelist_start, whitelist_len as usize) else {
+        return AllocShareStatus::InvalidWhitelist.into();
+    };
+
+    without_interrupts(|| {
+        let mut scheduler = process::SCHEDULER.write();
+        let process = scheduler.get_current().unwrap();
+
+        let Some(start) = process.vmas.find_free(MAP_START, MAP_END, size) else {
+            return AllocShareStatus::NoSpace.into();
+        };
+
+        let mut shares = ipc::MEMORY_SHARE.lock();
+
+        let id = match shares.alloc(pages as usize, process.pid, whitelist) {
+            Ok(id) => id,
+            Err(e) => return e.into(),
+        };
+
+        shares.map(id, Page::containing_address(VirtAddr::new(start)), SHARE_FLAGS);
+        process.vmas.insert(Vma::new(start, start + size, VmaKind::Shared, SHARE_FLAGS, Backing::Share(id))).unwrap();
+
+        serial_println!("Allocated memshare {} at {:#018X}", id, start);
+
+        AllocShareResponse { status: AllocShareStatus::Success, id: Some(id), start }
+    })
+}
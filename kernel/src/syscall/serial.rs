@@ -1,7 +1,7 @@
 use abi::dev::SerialStatus;
 use alloc::{string::String, vec::Vec};
 
-use crate::{serial_print, syscall::build_user_vec};
+use crate::{serial_print, memory::user::copy_from_user};
 
 
 pub unsafe fn sys_send_serial(rdi: u64, rsi: u64) -> SerialStatus {
@@ -9,7 +9,7 @@ pub unsafe fn sys_send_serial(rdi: u64, rsi: u64) -> SerialStatus {
     let rsi_bytes = rsi.to_le_bytes();
     let length = rsi_bytes[0] as u16 | ((rsi_bytes[1] as u16) << 8);
 
-    let Ok(text_bytes): Result<Vec<u8>, _> = build_user_vec(text_start, length as usize) else {
+    let Ok(text_bytes): Result<Vec<u8>, _> = copy_from_user(text_start, length as usize) else {
         return SerialStatus::InvalidStart;
     };
     let Ok(text) = String::from_utf8(text_bytes) else {
@@ -0,0 +1,173 @@
+use abi::vm::{
+    GrowHeapResponse, GrowHeapStatus, HEAP_START, HEAP_MAX,
+    MapFlags, MapMemoryResponse, MapMemoryStatus, UnmapMemoryStatus, ProtectMemoryStatus, MAP_START, MAP_END,
+};
+use x86_64::{VirtAddr, structures::paging::PageTableFlags, instructions::interrupts::without_interrupts};
+
+use crate::{memory, process::{SCHEDULER, vma::{Vma, VmaKind, Backing}}};
+
+pub unsafe fn sys_grow_heap(size: u64) -> GrowHeapResponse {
+    let Some(size) = size.checked_add(4095).map(|size| size & !4095) else {
+        return GrowHeapStatus::TooLarge.into();
+    };
+
+    let (heap_end, blocked) = without_interrupts(|| {
+        let mut scheduler = SCHEDULER.write();
+        let vmas = &scheduler.get_current().unwrap().vmas;
+        let heap_end = vmas.last_of(VmaKind::Heap).map_or(HEAP_START, |vma| vma.end);
+
+        (heap_end, vmas.overlaps(heap_end, heap_end.saturating_add(size)))
+    });
+
+    if size == 0 {
+        return GrowHeapResponse { status: GrowHeapStatus::Success, start: heap_end, size: 0 };
+    }
+
+    if size > HEAP_MAX - (heap_end - HEAP_START) {
+        return GrowHeapStatus::TooLarge.into();
+    }
+
+    if blocked {
+        return GrowHeapStatus::AlreadyMapped.into();
+    }
+
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+
+    // the pages are only reserved, they get populated when they're first touched
+    without_interrupts(|| {
+        let mut scheduler = SCHEDULER.write();
+        let vmas = &mut scheduler.get_current().unwrap().vmas;
+
+        vmas.insert(Vma::new(heap_end, heap_end + size, VmaKind::Heap, flags, Backing::Owned)).unwrap();
+    });
+
+    GrowHeapResponse { status: GrowHeapStatus::Success, start: heap_end, size }
+}
+
+/// Reasons a range passed to one of the mapping syscalls can be rejected
+enum RangeError {
+    Unaligned,
+    OutOfBounds,
+    InvalidSize,
+}
+
+/// Checks that `size` bytes from `start` are page aligned and inside the mapping region, returning the last address
+fn check_map_range(start: u64, size: u64) -> Result<u64, RangeError> {
+    if start % 4096 != 0 || size % 4096 != 0 {
+        return Err(RangeError::Unaligned);
+    }
+
+    if size == 0 {
+        return Err(RangeError::InvalidSize);
+    }
+
+    let Some(end) = start.checked_add(size - 1) else {
+        return Err(RangeError::OutOfBounds);
+    };
+
+    if start < MAP_START || end >= MAP_END {
+        return Err(RangeError::OutOfBounds);
+    }
+
+    Ok(end)
+}
+
+pub unsafe fn sys_map_memory(start: u64, size: u64, flags: MapFlags) -> MapMemoryResponse {
+    let start = if start == 0 {
+        if size == 0 || size % 4096 != 0 {
+            return MapMemoryStatus::InvalidSize.into();
+        }
+
+        let found = without_interrupts(|| {
+            SCHEDULER.write().get_current().unwrap().vmas.find_free(MAP_START, MAP_END, size)
+        });
+
+        let Some(start) = found else {
+            return MapMemoryStatus::OutOfBounds.into();
+        };
+
+        start
+    } else {
+        start
+    };
+
+    if let Err(e) = check_map_range(start, size) {
+        return match e {
+            RangeError::Unaligned => MapMemoryStatus::Unaligned,
+            RangeError::OutOfBounds => MapMemoryStatus::OutOfBounds,
+            RangeError::InvalidSize => MapMemoryStatus::InvalidSize,
+        }.into();
+    }
+
+    let overlaps = without_interrupts(|| {
+        SCHEDULER.write().get_current().unwrap().vmas.overlaps(start, start + size)
+    });
+
+    if overlaps {
+        return MapMemoryStatus::AlreadyMapped.into();
+    }
+
+    let flags = memory::user_page_flags(flags);
+
+    // the pages are only reserved, they get populated when they're first touched
+    without_interrupts(|| {
+        let mut scheduler = SCHEDULER.write();
+        let vmas = &mut scheduler.get_current().unwrap().vmas;
+
+        vmas.insert(Vma::new(start, start + size, VmaKind::Anonymous, flags, Backing::Owned)).unwrap();
+    });
+
+    MapMemoryResponse { status: MapMemoryStatus::Success, start }
+}
+
+pub unsafe fn sys_unmap_memory(start: u64, size: u64) -> UnmapMemoryStatus {
+    if let Err(e) = check_map_range(start, size) {
+        return match e {
+            RangeError::Unaligned => UnmapMemoryStatus::Unaligned,
+            RangeError::OutOfBounds => UnmapMemoryStatus::OutOfBounds,
+            RangeError::InvalidSize => UnmapMemoryStatus::InvalidSize,
+        };
+    }
+
+    without_interrupts(|| {
+        let mut scheduler = SCHEDULER.write();
+        let vmas = &mut scheduler.get_current().unwrap().vmas;
+
+        // shared pages only get unmapped here, their frames stay with the share
+        for vma in vmas.remove(start, start + size) {
+            vma.unmap();
+        }
+    });
+
+    UnmapMemoryStatus::Success
+}
+
+pub unsafe fn sys_protect_memory(start: u64, size: u64, flags: MapFlags) -> ProtectMemoryStatus {
+    let end = match check_map_range(start, size) {
+        Ok(end) => end,
+        Err(RangeError::Unaligned) => return ProtectMemoryStatus::Unaligned,
+        Err(RangeError::OutOfBounds) => return ProtectMemoryStatus::OutOfBounds,
+        Err(RangeError::InvalidSize) => return ProtectMemoryStatus::InvalidSize,
+    };
+
+    let flags = memory::user_page_flags(flags);
+
+    without_interrupts(|| {
+        let mut scheduler = SCHEDULER.write();
+        let vmas = &mut scheduler.get_current().unwrap().vmas;
+
+        if !vmas.covers(start, start + size) {
+            return ProtectMemoryStatus::NotMapped;
+        }
+
+        // a share checks each member's access when it joins, which this would get around
+        if !vmas.owns(start, start + size) {
+            return ProtectMemoryStatus::NotOwned;
+        }
+
+        memory::protect_area(VirtAddr::new(start), VirtAddr::new(end), flags).unwrap();
+        vmas.protect(start, start + size, flags);
+
+        ProtectMemoryStatus::Success
+    })
+}
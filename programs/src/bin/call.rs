@@ -0,0 +1,38 @@
+//! This program starts a server in process 1 and a client in process 2
+//! The client calls the server with a number and the server replies with it doubled, without either side polling
+
+#![no_std]
+#![no_main]
+
+use std::{getpid, exit, println, ipc::{call, receive, reply_and_receive, Message}};
+
+#[no_mangle]
+pub unsafe extern "C" fn _start() {
+    let pid = getpid();
+
+    match pid {
+        1 => run_server(),
+        2 => run_client(),
+        e => panic!("why god why ({})", e),
+    }
+}
+
+fn run_server() {
+    let mut request = receive(&[]).unwrap();
+
+    loop {
+        let response = Message { pid: request.pid, data0: request.data0 * 2, ..Default::default() };
+
+        request = reply_and_receive(response).unwrap();
+    }
+}
+
+fn run_client() {
+    for i in 0..8 {
+        let response = call(Message { pid: 1, data0: i, ..Default::default() }).unwrap();
+
+        println!("2: {} doubled is {}", i, response.data0);
+    }
+
+    exit();
+}
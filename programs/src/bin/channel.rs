@@ -0,0 +1,63 @@
+//! This program starts a producer in process 1 and a consumer in process 2
+//! The producer opens a channel to the consumer and streams more bytes through it than the ring can hold at once,
+//! so both the empty and full wakeups get used
+
+#![no_std]
+#![no_main]
+
+use std::{
+    getpid, exit, println,
+    ipc::receive,
+    channel::{channel_to, Receiver},
+};
+
+const TOTAL: usize = 16384;
+
+#[no_mangle]
+pub unsafe extern "C" fn _start() {
+    let pid = getpid();
+
+    match pid {
+        1 => run_producer(),
+        2 => run_consumer(),
+        e => panic!("why god why ({})", e),
+    }
+}
+
+fn run_producer() {
+    let mut sender = channel_to(2, 1024).unwrap();
+
+    println!("1: Channel open");
+
+    let chunk: [u8; 256] = core::array::from_fn(|i| i as u8);
+
+    for _ in 0..TOTAL / chunk.len() {
+        sender.send(&chunk).unwrap();
+    }
+
+    println!("1: Sent {} bytes", TOTAL);
+
+    exit();
+}
+
+fn run_consumer() {
+    let message = receive(&[1]).unwrap();
+    let mut receiver = Receiver::accept(&message).unwrap().expect("2: Expected a channel");
+
+    println!("2: Channel joined");
+
+    let mut buf = [0; 300];
+    let mut received = 0;
+    let mut sum: u64 = 0;
+
+    while received < TOTAL {
+        let len = receiver.recv(&mut buf).unwrap();
+
+        sum += buf[..len].iter().map(|&b| b as u64).sum::<u64>();
+        received += len;
+    }
+
+    println!("2: Received {} bytes, sum {}", received, sum);
+
+    exit();
+}
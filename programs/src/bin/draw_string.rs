@@ -11,9 +11,9 @@ pub unsafe extern "C" fn _start() {
     let content = "Those who gunkless are not lost in the gunk, for they have none";
 
     if pid == 2 {
-        draw_string(content, 0, 200, 0xFFFF, 1);
+        let _ = draw_string(content, 0, 200, 0xFFFF, 1);
     } else {
-        draw_string(content, 50, 400, 0xFF80, 1);
+        let _ = draw_string(content, 50, 400, 0xFF80, 1);
     }
 
     exit();
@@ -0,0 +1,50 @@
+//! This program starts a server in process 1 and clients in processes 2 and 3
+//! The server hands each client access to an endpoint with its own badge, then tells sessions apart by badge alone
+
+#![no_std]
+#![no_main]
+
+use std::{getpid, exit, println, ipc::{receive, reply, send_message, Message}, endpoint::{create_endpoint, grant_endpoint, receive_endpoint, call_endpoint}};
+
+#[no_mangle]
+pub unsafe extern "C" fn _start() {
+    let pid = getpid();
+
+    match pid {
+        1 => run_server(),
+        2 | 3 => run_client(pid),
+        e => panic!("why god why ({})", e),
+    }
+}
+
+fn run_server() {
+    let endpoint = create_endpoint();
+    let mut counts = [0; 2];
+
+    // clients only learn the endpoint, not which process serves it
+    for (client, badge) in [(2, 0), (3, 1)] {
+        grant_endpoint(endpoint, client, badge).unwrap();
+        send_message(Message { pid: client, data0: endpoint, ..Default::default() }).unwrap();
+    }
+
+    loop {
+        let request = receive_endpoint(endpoint).unwrap();
+        let count = &mut counts[request.badge as usize];
+
+        *count += request.data0;
+
+        reply(Message { pid: request.pid, data0: *count, ..Default::default() }).unwrap();
+    }
+}
+
+fn run_client(pid: u64) {
+    let endpoint = receive(&[1]).unwrap().data0;
+
+    for i in 1..=4 {
+        let response = call_endpoint(endpoint, [i * pid, 0, 0]).unwrap();
+
+        println!("{}: session total is {}", pid, response.data0);
+    }
+
+    exit();
+}
@@ -5,8 +5,7 @@ use std::{dev::request_fb, println, exit};
 
 #[no_mangle]
 pub unsafe extern "C" fn _start() {
-    let (_, descriptor) = request_fb();
-    let descriptor = descriptor.unwrap();
+    let descriptor = request_fb().unwrap();
     let fb_ptr = descriptor.address as *mut u16;
 
     println!("Ragnarok be upon ye!");
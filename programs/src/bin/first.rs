@@ -14,11 +14,11 @@ pub unsafe extern "C" fn _start() {
     println!("nice");
 
     match draw_bitmap(&[0x0F, 0xF0, 0xF0, 0x0F, 0x0F, 0xF0], 400, 100, 0b11111_000000_00000, 2, 3, 10) {
-        DrawBitmapStatus::InvalidLength => { serial_print!("Bitmap has an invalid length :("); },
+        Err(e) if e.is(DrawBitmapStatus::InvalidLength) => { serial_print!("Bitmap has an invalid length :("); },
         _ => {},
     }
 
-    draw_string("gort", 300, 125, 0xFFFF, 10);
+    let _ = draw_string("gort", 300, 125, 0xFFFF, 10);
     serial_print!("me when i go fucking apeshit am i right");
 
     {
@@ -3,26 +3,30 @@
 
 extern crate alloc;
 
-use std::{input, println, getpid, ipc::{set_mailbox_enabled, set_mailbox_whitelist}, graphics::draw_string, await_notif_from};
+use std::{input::{self, KeyEvent}, encoding::MessageData, println, getpid, ipc::{set_mailbox_enabled, set_mailbox_whitelist}, graphics::draw_string, await_notif_from};
 
 use alloc::format;
 
 #[no_mangle]
 pub unsafe extern "C" fn _start() {
-    input::subscribe();
-    set_mailbox_enabled(true);
-    set_mailbox_whitelist(&[3]);
+    set_mailbox_enabled(true).unwrap();
+    set_mailbox_whitelist(&[3]).unwrap();
 
-    draw_string(&format!("[{}] Started", getpid()), 300, 400, 0xFF80, 1);
+    if let Err(e) = input::subscribe() {
+        panic!("Couldn't subscribe to input: {}", e);
+    }
+
+    let _ = draw_string(&format!("[{}] Started", getpid()), 300, 400, 0xFF80, 1);
     
     loop {
         let notif = await_notif_from(3, 0);
         
         match notif {
-            Ok((status, msg)) => {
-                println!("[{}] {:?} {:?}", getpid(), status, msg);
-            }
-            Err(status) => panic!("Failure: {:?}", status),
+            Ok(msg) => match KeyEvent::from_message(&msg) {
+                Ok(event) => println!("[{}] {:?} {:?}", getpid(), event.code, event.state),
+                Err(e) => println!("[{}] Couldn't decode {:?}: {:?}", getpid(), msg, e),
+            },
+            Err(e) => panic!("Failure: {}", e),
         }
     }
 }
\ No newline at end of file
@@ -12,15 +12,15 @@ pub unsafe extern "C" fn _start() {
     if pid == 1 {
         let mut counter = 0;
         loop {
-            receive(&[2]);
-            send_message(Message { pid: 2, data0: counter, ..Default::default() });
+            receive(&[2]).unwrap();
+            send_message(Message { pid: 2, data0: counter, ..Default::default() }).unwrap();
             counter += 1
         }
     } else {
         let mut counter = u64::MAX;
         loop {
-            send_message(Message { pid: 1, data0: counter, ..Default::default() });
-            receive(&[1]);
+            send_message(Message { pid: 1, data0: counter, ..Default::default() }).unwrap();
+            receive(&[1]).unwrap();
             counter -= 1;
         }
     }
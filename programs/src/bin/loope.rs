@@ -10,18 +10,13 @@ extern crate alloc;
 #[no_mangle]
 pub unsafe extern "C" fn _start() {
     let e: Vec<u8> = Vec::with_capacity(1);
-    let end = std::ALLOCATOR.0.lock().heap_end as u64;
 
     loop {
-        let allocations = std::ALLOCATOR.0.lock().total;
-        let next = std::ALLOCATOR.0.lock().next;
+        let heap = std::ALLOCATOR.0.lock();
+        let (allocations, live, size) = (heap.allocations, heap.live, heap.size);
+        drop(heap);
 
-        serial_println!("Allocations: {}\nNext: {:#018X}\nEnd: {:#018X}", allocations, next as u64, end);
-        // 0x000060000001DF00
-        // Vec::<u8>::with_capacity(1);
-
-        // 0x000060000001DE6E
-        // Vec::<u8>::with_capacity(1000);
+        serial_println!("Allocations: {}\nLive: {} bytes\nSize: {} bytes", allocations, live, size);
         
         "a".to_owned();
     }
@@ -10,7 +10,8 @@
 use std::{
     getpid, exit, println,
     ipc::{send_message, receive},
-    memshare::{join_memshare, create_memshare, CreateShareStatus, JoinShareStatus}, 
+    memshare::{join_granted, alloc_memshare, send_grant, ShareGrant},
+    vm::MapFlags,
     ipc::Message
 };
 
@@ -27,45 +28,40 @@ pub unsafe extern "C" fn _start() {
 
 fn run_server() {
     println!("1: Server started");
-    
-    let start = 0;
-    let end = 16384;
 
-    match create_memshare(start, end, &[2]).status {
-        CreateShareStatus::Success => {},
-        s => panic!("1: Share failed: {:?}", s),
-    }
+    // only this process is on the whitelist, the client gets added when it receives the grant
+    let read_write = MapFlags { write: true, execute: false };
 
-    println!("1: Memshare created");
+    let (id, start) = match alloc_memshare(4, &[ShareGrant::new(getpid(), read_write)]) {
+        Ok(share) => share,
+        Err(e) => panic!("1: Share failed: {}", e),
+    };
 
-    send_message(Message {
-        pid: 2,
-        data0: start,
-        data1: end,
-        ..Default::default()
-    });
+    println!("1: Memshare {} created at {:#018X}", id, start);
+
+    send_grant(2, id, read_write, 0, 0).unwrap();
 
     println!("1: Message sent");
 
-    receive(&[2]);
+    receive(&[2]).unwrap();
 
     println!("1: Checking *ptr");
 
-    let ptr = 2048 as *const u8;
-    let target = 10240;
+    let ptr = (start + 2048) as *const u8;
+    let offset = 10240;
 
     println!("1: *ptr: {}", unsafe { *ptr });
-    println!("1: Hey 2 look at ${:#04X} u16 style", target);
+    println!("1: Hey 2 look at offset {:#06X} u16 style", offset);
 
-    let ptr = target as *mut u16;
+    let ptr = (start + offset) as *mut u16;
 
     unsafe { *ptr = 16384 };
 
     send_message(Message {
         pid: 2,
-        data0: target,
+        data0: offset,
         ..Default::default()
-    });
+    }).unwrap();
 
     println!("1: Exiting");
 
@@ -75,37 +71,37 @@ fn run_server() {
 fn run_client() {
     println!("2: Client started");
 
-    let msg = receive(&[1]);
+    let msg = receive(&[1]).unwrap();
 
     println!("2: Memshare ready, joining"); 
 
-    match join_memshare(1, msg.data0, msg.data1, &[]) {
-        JoinShareStatus::Success => {},
-        e => panic!("2: Share failed: {:?}", e),
-    }
+    let start = match join_granted(&msg) {
+        Ok((start, _)) => start,
+        Err(e) => panic!("2: Share failed: {}", e),
+    };
 
-    println!("2: Memshare joined");
+    println!("2: Memshare joined at {:#018X}", start);
 
-    let ptr = 2048 as *mut u8;
+    let ptr = (start + 2048) as *mut u8;
 
     unsafe { *ptr = 69 };
 
     println!("2: *ptr set");
 
-    let ptr = 2048 as *const u8;
+    let ptr = (start + 2048) as *const u8;
 
     println!("2: *ptr: {}", unsafe { *ptr });
 
     send_message(Message {
         pid: 1,
         ..Default::default()
-    });
+    }).unwrap();
 
-    let msg = receive(&[1]);
-    let ptr = msg.data0 as *const u16;
+    let msg = receive(&[1]).unwrap();
+    let ptr = (start + msg.data0) as *const u16;
 
     println!("2: Haha! It's {}", unsafe { *ptr });
     println!("2: Exiting");
 
     exit();
-}
\ No newline at end of file
+}
@@ -9,14 +9,14 @@ pub unsafe extern "C" fn _start() {
 
     match pid {
         2 => {
-            set_mailbox_enabled(true);
-            set_mailbox_whitelist(&[3]);
+            set_mailbox_enabled(true).unwrap();
+            set_mailbox_whitelist(&[3]).unwrap();
 
             let mut notif = read_mailbox();
 
             let mut counter = 3;
 
-            while notif.0 == ReadMailboxStatus::NoMessages {
+            while matches!(notif, Err(e) if e.is(ReadMailboxStatus::NoMessages)) {
                 sys_yield();
                 println!("[{}] Reading mailbox", pid);
                 notif = read_mailbox();
@@ -29,18 +29,18 @@ pub unsafe extern "C" fn _start() {
                 }
             }
 
-            while notif.0 == ReadMailboxStatus::MoreMessages {
+            while matches!(notif, Ok((ReadMailboxStatus::MoreMessages, _))) {
                 println!("[{}] {:?}", pid, notif);
                 notif = read_mailbox();
             }
 
             println!("[{}] {:?}", pid, notif);
     
-            let msg = notif.1.unwrap();
+            let (_, msg) = notif.unwrap();
 
             println!("[{}] {:?}", pid, msg);
 
-            send_message(Message { pid: 3, data0: msg.data3, data1: msg.data2, data2: msg.data1, data3: msg.data0 });
+            send_message(Message { pid: 3, data0: msg.data3, data1: msg.data2, data2: msg.data1, data3: msg.data0 }).unwrap();
 
             exit();
         }
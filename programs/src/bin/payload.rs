@@ -3,7 +3,7 @@
 
 extern crate alloc;
 
-use std::{getpid, config_rbuffer, ipc::{receive, PayloadMessage, send_payload}, println, exit};
+use std::{getpid, config_rbuffer, release_payload, ipc::{receive, PayloadMessage, send_payload}, println, exit};
 
 use alloc::{slice, string::String};
 
@@ -14,19 +14,25 @@ pub unsafe extern "C" fn _start() {
     println!("[{}]", pid);
 
     if pid == 1 {
-        config_rbuffer(4096);
+        // room for both payloads at once, so the second doesn't overwrite the first
+        config_rbuffer(4096, 2).unwrap();
 
-        let msg = receive(&[2]);
-        println!("[1] Message received");
+        let first: PayloadMessage = receive(&[2]).unwrap().into();
+        let second: PayloadMessage = receive(&[2]).unwrap().into();
+        println!("[1] Messages received");
 
-        let command = (msg.data0 >> 56) as u8;
-        if command == 0x45 {
-            let msg: PayloadMessage = msg.into();
-            let payload_ptr = msg.payload as *const u8;
-            let payload_bytes = slice::from_raw_parts(payload_ptr, msg.payload_len as usize);
-            let payload = String::from_utf8(payload_bytes.into()).unwrap();
+        for msg in [first, second] {
+            let command = (msg.data0 >> 56) as u8;
 
-            println!("Received a {} byte long string: {}", msg.payload_len, payload);
+            if command == 0x45 {
+                let payload_ptr = msg.payload as *const u8;
+                let payload_bytes = slice::from_raw_parts(payload_ptr, msg.payload_len as usize);
+                let payload = String::from_utf8(payload_bytes.into()).unwrap();
+
+                println!("Received a {} byte long string at {:#X}: {}", msg.payload_len, msg.payload, payload);
+            }
+
+            release_payload(&msg).unwrap();
         }
     } else if pid == 2 {
         let out = "This string is so longgggg look how long the string is wow that's a long string and it's even got some apostrophes :D";
@@ -37,8 +43,18 @@ pub unsafe extern "C" fn _start() {
             data1: 0,
             payload: out.as_ptr() as u64,
             payload_len: out.len() as u64,
-        });
-        println!("[2] Message sent");
+        }).unwrap();
+
+        let out = "And a short one";
+
+        send_payload(PayloadMessage {
+            pid: 1,
+            data0: 0x45 << 56,
+            data1: 0,
+            payload: out.as_ptr() as u64,
+            payload_len: out.len() as u64,
+        }).unwrap();
+        println!("[2] Messages sent");
     }
 
     exit();
@@ -17,7 +17,7 @@ pub unsafe extern "C" fn _start() {
     let mut x = counter * 8 * 4;
     let mut y = 0;
 
-    set_mailbox_enabled(true);
+    set_mailbox_enabled(true).unwrap();
 
     loop {
         serial_println!("{}", counter);
@@ -0,0 +1,31 @@
+//! This program starts a process 1 that never sends or receives anything and a process 2 that tries to talk to it
+//! Process 2 gives up on each attempt instead of hanging forever
+
+#![no_std]
+#![no_main]
+
+use std::{getpid, exit, println, sys_yield, ipc::{Message, try_send_message, send_message_timeout, try_receive, receive_timeout}};
+
+#[no_mangle]
+pub unsafe extern "C" fn _start() {
+    let pid = getpid();
+
+    match pid {
+        1 => loop {
+            sys_yield();
+        },
+        2 => run_impatient(),
+        e => panic!("why god why ({})", e),
+    }
+}
+
+fn run_impatient() {
+    let message = Message { pid: 1, data0: 0xAB, ..Default::default() };
+
+    println!("2: try_send_message: {:?}", try_send_message(message));
+    println!("2: send_message_timeout: {:?}", send_message_timeout(message, 20));
+    println!("2: try_receive: {:?}", try_receive(&[]));
+    println!("2: receive_timeout: {:?}", receive_timeout(&[1], 20));
+
+    exit();
+}
@@ -0,0 +1,61 @@
+//! This program starts a publisher in process 1 and subscribers in processes 2 and 3
+//! Process 3 exits after the first event, and the kernel stops delivering to it without the publisher doing anything
+
+#![no_std]
+#![no_main]
+
+use std::{getpid, exit, println, sys_yield, await_notif_from, ipc::{receive, send_message, set_mailbox_enabled, Message}, topic::{create_topic, subscribe_topic, publish_topic}};
+
+#[no_mangle]
+pub unsafe extern "C" fn _start() {
+    let pid = getpid();
+
+    match pid {
+        1 => run_publisher(),
+        2 | 3 => run_subscriber(pid),
+        e => panic!("why god why ({})", e),
+    }
+}
+
+fn run_publisher() {
+    let topic = create_topic();
+
+    for subscriber in [2, 3] {
+        send_message(Message { pid: subscriber, data0: topic, ..Default::default() }).unwrap();
+    }
+
+    // both have subscribed once they answer
+    receive(&[2]).unwrap();
+    receive(&[3]).unwrap();
+
+    for i in 1..=3 {
+        let delivered = publish_topic(topic, [i, 0, 0, 0]).unwrap();
+        println!("1: event {} went to {} subscribers", i, delivered);
+
+        if i == 1 {
+            // wait for process 3 to say goodbye and exit
+            receive(&[3]).unwrap();
+            sys_yield();
+        }
+    }
+
+    exit();
+}
+
+fn run_subscriber(pid: u64) {
+    set_mailbox_enabled(true).unwrap();
+
+    let topic = receive(&[1]).unwrap().data0;
+    subscribe_topic(topic, pid).unwrap();
+    send_message(Message { pid: 1, ..Default::default() }).unwrap();
+
+    loop {
+        let event = await_notif_from(1, 0).unwrap();
+        println!("{}: got event {}", pid, event.data0);
+
+        if pid == 3 {
+            send_message(Message { pid: 1, ..Default::default() }).unwrap();
+            exit();
+        }
+    }
+}
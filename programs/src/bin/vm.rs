@@ -0,0 +1,24 @@
+#![no_std]
+#![no_main]
+
+use std::{vm::{map_memory, protect_memory, unmap_memory, MapFlags}, println, exit};
+
+#[no_mangle]
+pub unsafe extern "C" fn _start() {
+    let size = 4096 * 2;
+    let start = map_memory(None, size, MapFlags { write: true, execute: false }).unwrap();
+    let ptr = start as *mut u64;
+
+    println!("Mapped {} bytes at {:#018X}, first word is {}", size, start, *ptr);
+
+    ptr.write(0xDEAD_BEEF);
+    protect_memory(start, size, MapFlags::default()).unwrap();
+
+    println!("Read only now, first word is {:#X}", *ptr);
+
+    unmap_memory(start, size).unwrap();
+
+    println!("Unmapped");
+
+    exit();
+}
@@ -0,0 +1,55 @@
+//! This program starts a process 1 that receives a big page aligned payload from a process 2
+//! The whole pages are shared instead of copied, and writing to them afterwards doesn't show up on the other side
+
+#![no_std]
+#![no_main]
+
+use std::{getpid, exit, println, config_rbuffer, release_payload, ipc::{receive, send_payload, PayloadMessage}, vm::{map_memory, MapFlags}};
+
+use core::slice;
+
+const SIZE: u64 = 4096 * 16 + 100;
+
+#[no_mangle]
+pub unsafe extern "C" fn _start() {
+    let pid = getpid();
+
+    match pid {
+        1 => run_receiver(),
+        2 => run_sender(),
+        e => panic!("why god why ({})", e),
+    }
+}
+
+unsafe fn run_receiver() {
+    config_rbuffer(SIZE, 1).unwrap();
+
+    let msg: PayloadMessage = receive(&[2]).unwrap().into();
+    let payload = slice::from_raw_parts_mut(msg.payload as *mut u8, msg.payload_len as usize);
+
+    let intact = payload.iter().enumerate().all(|(i, byte)| *byte == i as u8);
+    println!("1: received {} bytes at {:#X}, intact: {}", msg.payload_len, msg.payload, intact);
+
+    // gets a copy of the page instead of writing into the sender's
+    payload[0] = 0xFF;
+
+    release_payload(&msg).unwrap();
+    exit();
+}
+
+unsafe fn run_sender() {
+    // map_memory always hands out whole pages, so the payload is page aligned
+    let start = map_memory(None, SIZE, MapFlags { write: true, execute: false }).unwrap();
+    let buffer = slice::from_raw_parts_mut(start as *mut u8, SIZE as usize);
+
+    for (i, byte) in buffer.iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+
+    send_payload(PayloadMessage { pid: 1, payload: start, payload_len: SIZE, ..Default::default() }).unwrap();
+
+    buffer[1] = 0xFF;
+    println!("2: first bytes after sending are {:#X} {:#X}", buffer[0], buffer[1]);
+
+    exit();
+}
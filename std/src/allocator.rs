@@ -1,9 +1,8 @@
-use core::{alloc::GlobalAlloc, ptr};
+use core::{alloc::{GlobalAlloc, Layout}, mem::size_of, ptr};
 
-use alloc::{borrow::ToOwned, string::String};
 use spin::Mutex;
 
-use crate::{align_up, getpid, graphics::draw_bitmap, serial_println};
+use crate::{align_up, vm::grow_heap};
 
 extern "C" {
     fn _initial_process_heap_start();
@@ -14,79 +13,179 @@ extern "C" {
 const HEAP_START: *mut u8 = _initial_process_heap_start as _;
 const HEAP_END: *const u8 = _initial_process_heap_end as _;
 
+/// The heap grows by at least this much at a time
+const GROWTH_STEP: usize = 64 * 1024;
+
+/// Every block is a multiple of this size, so a free block always fits in any gap
+const BLOCK_ALIGN: usize = size_of::<FreeBlock>();
+
 pub struct Allocator(pub Mutex<Heap>);
 
 #[global_allocator]
 pub static ALLOCATOR: Allocator = Allocator(Mutex::new(unsafe { Heap::new(HEAP_START, HEAP_END) } ));
 
+/// Rounds a layout up to the size and alignment every block in the heap has
+fn block_layout(layout: Layout) -> (usize, usize) {
+    let size = align_up(layout.size().max(1), BLOCK_ALIGN);
+    let align = layout.align().max(BLOCK_ALIGN);
+
+    (size, align)
+}
+
 unsafe impl GlobalAlloc for Allocator {
-    unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
-        let mut bump = self.0.lock();
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.0.lock();
+        heap.init();
+
+        let (size, align) = block_layout(layout);
 
-        let alloc_start = align_up(bump.next as usize, layout.align());
-        let alloc_end = match alloc_start.checked_add(layout.size()) {
-            Some(end) => end,
-            None => return ptr::null_mut(),
+        if let Some(ptr) = heap.take(size, align) {
+            return ptr;
+        }
+
+        // aligning could waste up to `align` bytes at the start of the new pages
+        let Ok((start, grown)) = grow_heap((size + align).max(GROWTH_STEP) as u64) else {
+            return ptr::null_mut(); // out of memory
         };
 
-        if alloc_end > bump.heap_end as usize {
-            draw_bitmap(&[
-                0b11001100,
-                0b00110011,
-                0b11001100,
-                0b00110011,
-                0b11001100,
-                0b00110011,
-                0b11001100,
-                0b00110011,
-            ], 64 + bump.failures, 64 + bump.failures, 0xffff, 1, 8, 1);
-            bump.failures += 1;
-            ptr::null_mut() // out of memory
-        } else {
-            bump.next = alloc_end as _;
-            bump.allocations += 1;
-            bump.total += 1;
+        heap.insert(start as usize, grown as usize);
+        heap.size += grown as usize;
 
-            alloc_start as *mut u8
-        }
+        heap.take(size, align).unwrap_or(ptr::null_mut())
     }
 
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: core::alloc::Layout) {
-        let mut bump = self.0.lock();
-        bump.allocations -= 1;
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut heap = self.0.lock();
+        let (size, _) = block_layout(layout);
 
-        if bump.allocations == 0 {
-            bump.next = bump.heap_start;
-        }
+        heap.insert(ptr as usize, size);
+        heap.live -= size;
+        heap.allocations -= 1;
     }
 }
 
+/// Header written at the start of every free block
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+/// A linked list allocator, with free blocks kept sorted by address so neighbours can be merged
+///
+/// Starts out with the region reserved in the linker script, and asks the kernel for more pages once that runs out
 pub struct Heap {
-    pub heap_start: *mut u8,
-    pub heap_end: *const u8,
-    pub next: *mut u8,
+    initial_start: *mut u8,
+    initial_end: *const u8,
+    /// First free block
+    head: *mut FreeBlock,
+    initialized: bool,
+    /// Total bytes the heap covers, including growth
+    pub size: usize,
+    /// Bytes currently allocated
+    pub live: usize,
+    /// The most bytes that have been allocated at once
+    pub peak: usize,
     pub allocations: usize,
-    pub total: usize,
-    failures: u16,
 }
 
 unsafe impl Send for Heap {}
 
 impl Heap {
-    /// Initializes the bump allocator with the given heap bounds.
-    /// 
+    /// Creates an allocator over the given heap bounds, which get added to the free list on the first allocation
+    ///
     /// # Safety
-    /// 
+    ///
     /// The caller must ensure that the given memory range is unused.
     /// Also, this method must be called only once.
     pub const unsafe fn new(heap_start: *mut u8, heap_end: *const u8) -> Self {
         Self {
-            heap_start,
-            heap_end,
-            next: heap_start,
+            initial_start: heap_start,
+            initial_end: heap_end,
+            head: ptr::null_mut(),
+            initialized: false,
+            size: 0,
+            live: 0,
+            peak: 0,
             allocations: 0,
-            total: 0,
-            failures: 0,
         }
     }
-}
\ No newline at end of file
+
+    unsafe fn init(&mut self) {
+        if self.initialized {
+            return;
+        }
+
+        let start = align_up(self.initial_start as usize, BLOCK_ALIGN);
+        let end = self.initial_end as usize & !(BLOCK_ALIGN - 1);
+
+        self.insert(start, end - start);
+        self.size = end - start;
+        self.initialized = true;
+    }
+
+    /// Removes `size` bytes aligned to `align` from the first free block they fit in
+    unsafe fn take(&mut self, size: usize, align: usize) -> Option<*mut u8> {
+        let mut prev: *mut *mut FreeBlock = &mut self.head;
+
+        while !(*prev).is_null() {
+            let block = *prev;
+            let block_start = block as usize;
+            let block_end = block_start + (*block).size;
+
+            let alloc_start = align_up(block_start, align);
+            let alloc_end = alloc_start + size;
+
+            if alloc_end > block_end {
+                prev = &mut (*block).next;
+                continue;
+            }
+
+            *prev = (*block).next;
+
+            // whatever's left on either side is a multiple of BLOCK_ALIGN, so it can go back in the list
+            if alloc_start > block_start {
+                self.insert(block_start, alloc_start - block_start);
+            }
+
+            if block_end > alloc_end {
+                self.insert(alloc_end, block_end - alloc_end);
+            }
+
+            self.live += size;
+            self.peak = self.peak.max(self.live);
+            self.allocations += 1;
+
+            return Some(alloc_start as *mut u8);
+        }
+
+        None
+    }
+
+    /// Adds a free block to the list, merging it with the blocks next to it
+    unsafe fn insert(&mut self, start: usize, size: usize) {
+        let mut prev: *mut FreeBlock = ptr::null_mut();
+        let mut next = self.head;
+
+        while !next.is_null() && (next as usize) < start {
+            prev = next;
+            next = (*next).next;
+        }
+
+        let block = start as *mut FreeBlock;
+        block.write(FreeBlock { size, next });
+
+        if !next.is_null() && start + size == next as usize {
+            (*block).size += (*next).size;
+            (*block).next = (*next).next;
+        }
+
+        if prev.is_null() {
+            self.head = block;
+        } else if prev as usize + (*prev).size == start {
+            (*prev).size += (*block).size;
+            (*prev).next = (*block).next;
+        } else {
+            (*prev).next = block;
+        }
+    }
+}
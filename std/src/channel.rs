@@ -0,0 +1,278 @@
+//! Single producer, single consumer byte streams over a memory share
+//!
+//! The share starts with a `Header` holding how many bytes have been written and read in total, followed by the ring
+//! itself, which takes up the rest of the share.
+//!
+//! The peer can write anything to the header, so each side works out the size of the ring from its own mapping and
+//! clamps whatever it reads there. Neither side ever blocks the other, notifications are only sent to wake up a peer that's waiting because
+//! the ring was empty or full.
+//!
+//! Waiting takes any notification from the peer out of the mailbox, so other notifications between the two processes
+//! shouldn't be mixed with a channel
+
+use core::{mem::size_of, ptr, sync::atomic::{AtomicBool, AtomicU64, Ordering}};
+
+use abi::{ipc::{Message, Pid}, memshare::{ShareGrant, ShareId}, vm::MapFlags, Error};
+
+use crate::{
+    getpid, align_up, await_notif_from,
+    ipc::{notify, set_mailbox_enabled},
+    memshare::{alloc_memshare, join_granted, leave_memshare, send_grant},
+};
+
+/// Put in `data0` of the grant that sets up a channel, so the receiver can tell it apart from other grants
+pub const CHANNEL_GRANT: u64 = 0xC4A7;
+
+/// Lives at the start of the share, the ring starts right after it
+#[repr(C)]
+struct Header {
+    /// Bytes written since the channel was created, only changed by the sender
+    written: AtomicU64,
+    /// Bytes read since the channel was created, only changed by the receiver
+    read: AtomicU64,
+    /// Set by the receiver while it waits for the ring to stop being empty
+    receiver_waiting: AtomicBool,
+    /// Set by the sender while it waits for the ring to stop being full
+    sender_waiting: AtomicBool,
+}
+
+/// Keeps the ring aligned to a cache line
+const HEADER_SIZE: usize = 64;
+
+const _: () = assert!(size_of::<Header>() <= HEADER_SIZE);
+
+/// The side of a channel shared by both ends
+struct Ring {
+    header: *const Header,
+    data: *mut u8,
+    /// Size of the ring in bytes
+    capacity: u64,
+    share: ShareId,
+    /// The process on the other end
+    peer: Pid,
+}
+
+impl Ring {
+    /// Sets up the ring over the `size` bytes of a share mapped at `start`, which has to be more than the header
+    unsafe fn new(start: u64, size: u64, share: ShareId, peer: Pid) -> Self {
+        Self {
+            header: start as *const Header,
+            data: (start as usize + HEADER_SIZE) as *mut u8,
+            capacity: size - HEADER_SIZE as u64,
+            share,
+            peer,
+        }
+    }
+
+    fn header(&self) -> &Header {
+        unsafe { &*self.header }
+    }
+
+    fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    /// Gets how many bytes are in the ring, which can't be more than fit even if the peer wrote garbage to the header
+    fn used(&self, written: u64, read: u64) -> u64 {
+        written.wrapping_sub(read).min(self.capacity)
+    }
+
+    /// Splits `len` bytes starting `offset` bytes into the stream into the parts before and after the ring wraps around
+    ///
+    /// Each part is an index into the ring, an index into the caller's buffer and a length
+    fn parts(&self, offset: u64, len: usize) -> [(usize, usize, usize); 2] {
+        let capacity = self.capacity() as usize;
+        let index = (offset % capacity as u64) as usize;
+        let first = len.min(capacity - index);
+
+        [(index, 0, first), (0, first, len - first)]
+    }
+
+    unsafe fn write_at(&self, offset: u64, data: &[u8]) {
+        for (ring_index, data_index, len) in self.parts(offset, data.len()) {
+            ptr::copy_nonoverlapping(data.as_ptr().add(data_index), self.data.add(ring_index), len);
+        }
+    }
+
+    unsafe fn read_at(&self, offset: u64, buf: &mut [u8]) {
+        for (ring_index, buf_index, len) in self.parts(offset, buf.len()) {
+            ptr::copy_nonoverlapping(self.data.add(ring_index), buf.as_mut_ptr().add(buf_index), len);
+        }
+    }
+
+    /// Sets `flag`, then blocks until the peer sends a notification unless `ready` already holds
+    ///
+    /// Setting the flag before checking again means the peer either sees it and sends a notification, or changed the
+    /// ring before the check
+    fn wait(&self, flag: &AtomicBool, ready: impl Fn() -> bool) {
+        flag.store(true, Ordering::SeqCst);
+
+        while !ready() {
+            let _ = await_notif_from(self.peer, 0);
+        }
+
+        flag.store(false, Ordering::SeqCst);
+    }
+
+    /// Wakes up the peer if it's waiting on `flag`
+    fn wake(&self, flag: &AtomicBool) -> Result<(), Error> {
+        if flag.load(Ordering::SeqCst) {
+            notify(Message { pid: self.peer, data0: self.share, ..Default::default() })?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        // the frames are freed once both ends are gone
+        let _ = leave_memshare(self.share);
+    }
+}
+
+/// The writing end of a channel
+pub struct Sender {
+    ring: Ring,
+}
+
+/// The reading end of a channel
+pub struct Receiver {
+    ring: Ring,
+}
+
+/// Creates a channel to `peer` with room for at least `capacity` bytes
+///
+/// Blocks until `peer` receives the grant for it, which it can turn into a `Receiver` with `Receiver::accept`.
+/// Enables the mailbox, since that's where wakeups come from
+pub fn channel_to(peer: Pid, capacity: usize) -> Result<Sender, Error> {
+    let read_write = MapFlags { write: true, execute: false };
+    let pages = align_up(HEADER_SIZE + capacity.max(1), 4096) / 4096;
+
+    // nobody else can join until the grant is received
+    let (share, start) = alloc_memshare(pages as u64, &[ShareGrant::new(getpid(), read_write)])?;
+
+    let header = start as *mut Header;
+
+    unsafe {
+        header.write(Header {
+            written: AtomicU64::new(0),
+            read: AtomicU64::new(0),
+            receiver_waiting: AtomicBool::new(false),
+            sender_waiting: AtomicBool::new(false),
+        });
+    }
+
+    let ring = unsafe { Ring::new(start, (pages * 4096) as u64, share, peer) };
+
+    set_mailbox_enabled(true)?;
+    send_grant(peer, share, read_write, CHANNEL_GRANT, 0)?;
+
+    Ok(Sender { ring })
+}
+
+impl Sender {
+    /// Writes as much of `data` as fits without waiting, returning how many bytes that was
+    pub fn try_send(&mut self, data: &[u8]) -> Result<usize, Error> {
+        let header = self.ring.header();
+        let written = header.written.load(Ordering::Relaxed);
+        let used = self.ring.used(written, header.read.load(Ordering::Acquire));
+
+        let len = data.len().min((self.ring.capacity() - used) as usize);
+
+        if len == 0 {
+            return Ok(0);
+        }
+
+        unsafe { self.ring.write_at(written, &data[..len]) };
+
+        header.written.store(written.wrapping_add(len as u64), Ordering::SeqCst);
+
+        // `used` may be stale, so the receiver could have emptied the ring and started waiting since it was loaded
+        self.ring.wake(&header.receiver_waiting)?;
+
+        Ok(len)
+    }
+
+    /// Writes all of `data`, waiting for the receiver whenever the ring is full
+    pub fn send(&mut self, mut data: &[u8]) -> Result<(), Error> {
+        while !data.is_empty() {
+            let sent = self.try_send(data)?;
+            data = &data[sent..];
+
+            if sent == 0 {
+                let ring = &self.ring;
+                let header = ring.header();
+
+                ring.wait(&header.sender_waiting, || {
+                    ring.used(header.written.load(Ordering::SeqCst), header.read.load(Ordering::SeqCst)) < ring.capacity()
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Receiver {
+    /// Joins the channel from a grant sent by `channel_to`, returning `None` if the message isn't one
+    ///
+    /// Enables the mailbox, since that's where wakeups come from
+    pub fn accept(message: &Message) -> Result<Option<Self>, Error> {
+        if message.data0 != CHANNEL_GRANT {
+            return Ok(None);
+        }
+
+        let (start, size) = join_granted(message)?;
+
+        // shares are made of whole pages, so there's always room past the header
+        let ring = unsafe { Ring::new(start, size, message.data2, message.pid) };
+
+        set_mailbox_enabled(true)?;
+
+        Ok(Some(Self { ring }))
+    }
+
+    /// Reads as much as fits in `buf` without waiting, returning how many bytes that was
+    pub fn try_recv(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let header = self.ring.header();
+        let read = header.read.load(Ordering::Relaxed);
+        let available = self.ring.used(header.written.load(Ordering::Acquire), read);
+
+        let len = buf.len().min(available as usize);
+
+        if len == 0 {
+            return Ok(0);
+        }
+
+        unsafe { self.ring.read_at(read, &mut buf[..len]) };
+
+        header.read.store(read.wrapping_add(len as u64), Ordering::SeqCst);
+
+        // `available` may be stale, so the sender could have filled the ring and started waiting since it was loaded
+        self.ring.wake(&header.sender_waiting)?;
+
+        Ok(len)
+    }
+
+    /// Reads into `buf`, waiting until there's at least one byte if the ring is empty
+    pub fn recv(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            let received = self.try_recv(buf)?;
+
+            if received > 0 {
+                return Ok(received);
+            }
+
+            let header = self.ring.header();
+
+            self.ring.wait(&header.receiver_waiting, || {
+                header.written.load(Ordering::SeqCst) != header.read.load(Ordering::SeqCst)
+            });
+        }
+    }
+}
@@ -5,6 +5,8 @@ mod syscalls;
 mod allocator;
 mod servers;
 
+pub mod channel;
+
 extern crate alloc;
 
 use core::panic::PanicInfo;
@@ -32,8 +34,7 @@ pub(crate) fn align_down(addr: usize, align: usize) -> usize {
 
 /// Align the given address `addr` upwards to alignment `align`.
 pub(crate) fn align_up(addr: usize, align: usize) -> usize {
-    let remainder = addr % align;
-    addr + (align - remainder)
+    align_down(addr + align - 1, align)
 }
 
 pub unsafe fn extract_payload<T>(message: &PayloadMessage) -> Vec<T>
@@ -1,38 +1,24 @@
-use abi::ipc::{ReadMailboxStatus, Message, Pid};
+use abi::{ipc::{Message, Pid, Timeout, WaitFlags}, Error};
 
-use crate::{ipc::{read_mailbox_from, read_mailbox_inner}, sys_yield};
+use crate::ipc::wait_inner;
 
 pub mod graphics;
 pub mod input;
 
-pub fn await_notif(attempts: usize) -> Result<(ReadMailboxStatus, Option<Message>), ReadMailboxStatus> {
-    await_notif_inner(0, false, attempts)
+pub fn await_notif(ticks: u64) -> Result<Message, Error> {
+    await_notif_inner(0, false, ticks)
 }
 
-pub fn await_notif_from(from: Pid, attempts: usize) -> Result<(ReadMailboxStatus, Option<Message>), ReadMailboxStatus> {
-    await_notif_inner(from, true, attempts)
+pub fn await_notif_from(from: Pid, ticks: u64) -> Result<Message, Error> {
+    await_notif_inner(from, true, ticks)
 }
 
-pub fn await_notif_inner(from: Pid, filter: bool, mut attempts: usize) -> Result<(ReadMailboxStatus, Option<Message>), ReadMailboxStatus> {
-    let mut msg = read_mailbox_inner(from, filter);
+/// Blocks until a notification arrives, giving up after `ticks` timer ticks, or never if it's 0
+pub fn await_notif_inner(from: Pid, filter: bool, ticks: u64) -> Result<Message, Error> {
+    let flags = WaitFlags { receive: false, notify: true, filter };
+    let timeout = if ticks > 0 { Timeout::Ticks(ticks) } else { Timeout::Forever };
 
-    if msg.0 == ReadMailboxStatus::Disabled {
-        return Err(ReadMailboxStatus::Disabled);
-    }
+    let (_, message) = wait_inner(flags, &[], from, timeout)?;
 
-    let limited = attempts > 0;
-
-    // you disgust me
-    while if limited { attempts > 0 } else { true } && msg.0 == ReadMailboxStatus::NoMessages {
-        sys_yield();
-        msg = read_mailbox_inner(from, filter);
-
-        if limited { attempts -= 1 };
-    }
-
-    if msg.0 == ReadMailboxStatus::NoMessages {
-        Err(ReadMailboxStatus::NoMessages)
-    } else {
-        Ok(msg)
-    }
+    Ok(message)
 }
@@ -8,64 +8,36 @@
 pub mod serial;
 // pub mod sys_graphics;
 pub mod ipc;
+pub mod endpoint;
 pub mod memshare;
+pub mod topic;
 pub mod dev;
+pub mod vm;
 
-use core::arch::asm;
+use abi::{raw, ipc::PayloadMessage};
 
-use abi::{ConfigRBufferStatus, Syscall};
-
-pub use abi::{Status, InvalidStatusCode};
+pub use abi::{Status, InvalidStatusCode, Error, status_enum, message, encoding, protocol};
 
 pub fn exit() {
-    unsafe {
-        asm!(
-            "mov rax, $0x00",
-            "syscall",
-        );
-    }
+    unsafe { raw::exit() }
 }
 
-pub fn config_rbuffer(size: u64) -> ConfigRBufferStatus {
-    let rax = Syscall::config_rbuffer as u64;
-    let status: u64;
-
-    unsafe {
-        asm!(
-            "syscall",
-            in("rax") rax,
-            in("rdi") size,
-            lateout("rax") status,
-        )
-    }
+/// Sets up a response buffer with room for `slots` payloads of up to `slot_size` bytes at once
+pub fn config_rbuffer(slot_size: u64, slots: u64) -> Result<(), Error> {
+    unsafe { raw::config_rbuffer(slot_size, slots) }.into_result()?;
+    Ok(())
+}
 
-    status.try_into().unwrap()
+/// Frees the response buffer slot holding the payload of `message`, so another payload can use it
+pub fn release_payload(message: &PayloadMessage) -> Result<(), Error> {
+    unsafe { raw::release_rbuffer(message.payload) }.into_result()?;
+    Ok(())
 }
 
 pub fn getpid() -> u64 {
-    let rax = Syscall::getpid as u64;
-    
-    let rdi: u64;
-
-    unsafe {
-        asm!(
-            "mov rdi, $0x00",
-            "syscall",
-            in("rax") rax,
-            lateout("rdi") rdi,
-        );
-    }
-
-    rdi
+    unsafe { raw::getpid(0) }.pid
 }
 
 pub fn sys_yield() {
-    let rax = Syscall::sys_yield as u64;
-
-    unsafe { 
-        asm!(
-            "syscall",
-            in("rax") rax,
-        ); 
-    }
-}
\ No newline at end of file
+    unsafe { raw::sys_yield() }
+}
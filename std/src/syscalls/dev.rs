@@ -1,26 +1,10 @@
-use core::arch::asm;
-
-use abi::Syscall;
+use abi::{raw, Error, Status};
 pub use abi::dev::{FramebufferDescriptor, RequestFbStatus};
 
-pub fn request_fb() -> (RequestFbStatus, Option<FramebufferDescriptor>) {
-    let descriptor = FramebufferDescriptor::default();
-    let descriptor_ptr = &descriptor as *const FramebufferDescriptor;
-    let rax = Syscall::request_fb as u64;
-    let status: u64;
+pub fn request_fb() -> Result<FramebufferDescriptor, Error> {
+    let mut descriptor = FramebufferDescriptor::default();
 
-    unsafe {
-        asm!(
-            "syscall",
-            in("rax") rax,
-            in("rdi") descriptor_ptr,
-            lateout("rax") status,
-        );
-    }
+    unsafe { raw::request_fb(&mut descriptor) }.into_result()?;
 
-    if status < 10 {
-        (status.try_into().unwrap(), Some(descriptor))
-    } else {
-        (status.try_into().unwrap(), None)
-    }
-}
\ No newline at end of file
+    Ok(descriptor)
+}
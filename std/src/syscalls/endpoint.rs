@@ -0,0 +1,89 @@
+use abi::{raw, Error, Status, ipc::{Message, Pid, Timeout}};
+
+pub use abi::endpoint::{
+    EndpointId, EndpointMessage, CreateEndpointStatus, DestroyEndpointStatus, GrantEndpointStatus, RevokeEndpointStatus,
+};
+
+/// Creates an endpoint that only this process can receive on
+pub fn create_endpoint() -> EndpointId {
+    unsafe { raw::create_endpoint() }.id
+}
+
+/// Destroys an endpoint this process created, failing every send to it that hasn't been received yet
+pub fn destroy_endpoint(endpoint: EndpointId) -> Result<(), Error> {
+    unsafe { raw::destroy_endpoint(endpoint) }.into_result()?;
+
+    Ok(())
+}
+
+/// Lets `pid` send to an endpoint with `badge` on its messages
+///
+/// Processes that don't own the endpoint can only pass on their own badge, so `badge` is ignored for them, and can't
+/// change the badge of a process that can already send to it
+pub fn grant_endpoint(endpoint: EndpointId, pid: Pid, badge: u64) -> Result<(), Error> {
+    unsafe { raw::grant_endpoint(endpoint, pid, badge) }.into_result()?;
+
+    Ok(())
+}
+
+/// Stops `pid` from sending to an endpoint this process created
+pub fn revoke_endpoint(endpoint: EndpointId, pid: Pid) -> Result<(), Error> {
+    unsafe { raw::revoke_endpoint(endpoint, pid) }.into_result()?;
+
+    Ok(())
+}
+
+/// Sends a message to whichever process owns an endpoint, blocking until it is received
+pub fn send_endpoint(endpoint: EndpointId, data: [u64; 3]) -> Result<(), Error> {
+    send_endpoint_inner(endpoint, data, Timeout::Forever)
+}
+
+/// Sends a message to an endpoint, blocking until it is received or `ticks` timer ticks have passed
+pub fn send_endpoint_timeout(endpoint: EndpointId, data: [u64; 3], ticks: u64) -> Result<(), Error> {
+    send_endpoint_inner(endpoint, data, Timeout::Ticks(ticks))
+}
+
+/// Sends a message to an endpoint only if its owner is already receiving on it
+pub fn try_send_endpoint(endpoint: EndpointId, data: [u64; 3]) -> Result<(), Error> {
+    send_endpoint_inner(endpoint, data, Timeout::Poll)
+}
+
+pub fn send_endpoint_inner(endpoint: EndpointId, data: [u64; 3], timeout: Timeout) -> Result<(), Error> {
+    let [data0, data1, data2] = data;
+
+    unsafe { raw::send_endpoint(endpoint, data0, data1, data2, timeout) }.into_result()?;
+
+    Ok(())
+}
+
+/// Sends a message to an endpoint and blocks until its owner replies, then returns the reply
+pub fn call_endpoint(endpoint: EndpointId, data: [u64; 3]) -> Result<Message, Error> {
+    let [data0, data1, data2] = data;
+
+    let response = unsafe { raw::call_endpoint(endpoint, data0, data1, data2) };
+    response.status.into_result()?;
+
+    Ok(response.message.unwrap())
+}
+
+/// Blocks until a message is sent to an endpoint this process created
+pub fn receive_endpoint(endpoint: EndpointId) -> Result<EndpointMessage, Error> {
+    receive_endpoint_inner(endpoint, Timeout::Forever)
+}
+
+/// Blocks until a message is sent to an endpoint or `ticks` timer ticks have passed
+pub fn receive_endpoint_timeout(endpoint: EndpointId, ticks: u64) -> Result<EndpointMessage, Error> {
+    receive_endpoint_inner(endpoint, Timeout::Ticks(ticks))
+}
+
+/// Receives a message sent to an endpoint only if a process is already waiting to send one
+pub fn try_receive_endpoint(endpoint: EndpointId) -> Result<EndpointMessage, Error> {
+    receive_endpoint_inner(endpoint, Timeout::Poll)
+}
+
+pub fn receive_endpoint_inner(endpoint: EndpointId, timeout: Timeout) -> Result<EndpointMessage, Error> {
+    let response = unsafe { raw::receive_endpoint(endpoint, timeout) };
+    response.status.into_result()?;
+
+    Ok(response.message.unwrap())
+}
@@ -1,201 +1,215 @@
-use core::arch::asm;
+use abi::{raw, Error, Status, ipc::{MailboxFlags, WaitFlags}};
 
-use abi::{Syscall, ipc::{NotifyStatus, ConfigMailboxStatus, MailboxFlags}, Status};
+pub use abi::ipc::{Message, PayloadMessage, SendStatus, ReceiveStatus, NotifyStatus, ConfigMailboxStatus, Pid, ReadMailboxStatus, ReplyStatus, CancelSendStatus, Timeout, WaitStatus, MailboxInfo, OverflowPolicy};
 
-pub use abi::ipc::{Message, PayloadMessage, SendStatus, ReceiveStatus, Pid, ReadMailboxStatus};
+/// Sends a message to another process, blocking until it is received
+pub fn send_message(message: Message) -> Result<(), Error> {
+    send_message_inner(message, Timeout::Forever)
+}
 
-use crate::serial_println;
+/// Sends a message to another process, blocking until it is received or `ticks` timer ticks have passed
+pub fn send_message_timeout(message: Message, ticks: u64) -> Result<(), Error> {
+    send_message_inner(message, Timeout::Ticks(ticks))
+}
 
-/// Sends a message to another process, blocking until it is received
-pub fn send_message(message: Message) -> SendStatus {
-    let rax = Syscall::send as u64;
-    let Message { pid, data0, data1, data2, data3 } = message;
+/// Sends a message to another process only if it's already waiting to receive one
+pub fn try_send_message(message: Message) -> Result<(), Error> {
+    send_message_inner(message, Timeout::Poll)
+}
 
-    let status: u64;
+pub fn send_message_inner(message: Message, timeout: Timeout) -> Result<(), Error> {
+    let Message { pid, data0, data1, data2, data3 } = message;
 
-    unsafe {
-        asm!(
-            "syscall",
-            in("rax") rax,
-            in("rdi") pid,
-            in("rsi") data0,
-            in("rdx") data1,
-            in("r8") data2,
-            in("r9") data3,
-            lateout("rax") status,
-        );
-    }
+    unsafe { raw::send(pid, data0, data1, data2, data3, timeout) }.into_result()?;
 
-    status.try_into().unwrap()
+    Ok(())
 }
 
 /// Blocks until a message is received, then returns that message
-pub fn receive(whitelist: &[Pid]) -> Message {
-    let rax = Syscall::receive as u64;
-
-    // let status: u64;
-    let pid: Pid;
-    let data0: u64;
-    let data1: u64;
-    let data2: u64;
-    let data3: u64;
-
-    unsafe {
-        asm!(
-            "syscall",
-            in("rax") rax,
-            in("rdi") whitelist.as_ptr(),
-            in("rsi") whitelist.len(),
-            // lateout("rax") status,
-            lateout("rdi") pid,
-            lateout("rsi") data0,
-            lateout("rdx") data1,
-            lateout("r8") data2,
-            lateout("r9") data3,
-        );
-    }
-
-    // serial_println!("Receive status: {}", status);
-    // serial_println!("data0: {}", data0);
-    serial_println!("");
-
-    Message { pid, data0, data1, data2, data3 }
-}
-
-pub fn notify(message: Message) -> NotifyStatus {
-    let rax = Syscall::notify as u64;
-    let Message { pid, data0, data1, data2, data3 } = message;
+pub fn receive(whitelist: &[Pid]) -> Result<Message, Error> {
+    receive_inner(whitelist, Timeout::Forever)
+}
+
+/// Blocks until a message is received or `ticks` timer ticks have passed
+pub fn receive_timeout(whitelist: &[Pid], ticks: u64) -> Result<Message, Error> {
+    receive_inner(whitelist, Timeout::Ticks(ticks))
+}
+
+/// Receives a message only if a process is already waiting to send one
+pub fn try_receive(whitelist: &[Pid]) -> Result<Message, Error> {
+    receive_inner(whitelist, Timeout::Poll)
+}
+
+pub fn receive_inner(whitelist: &[Pid], timeout: Timeout) -> Result<Message, Error> {
+    let response = unsafe { raw::receive(whitelist.as_ptr(), whitelist.len(), timeout) };
+    response.status.into_result()?;
+
+    Ok(response.message.unwrap())
+}
 
-    let status: u64;
+pub fn notify(message: Message) -> Result<(), Error> {
+    let Message { pid, data0, data1, data2, data3 } = message;
 
-    unsafe {
-        asm!(
-            "syscall",
-            in("rax") rax,
-            in("rdi") pid,
-            in("rsi") data0,
-            in("rdx") data1,
-            in("r8") data2,
-            in("r9") data3,
-            lateout("rax") status,
-        );
-    }
+    unsafe { raw::notify(pid, data0, data1, data2, data3) }.into_result()?;
 
-    status.try_into().unwrap()
+    Ok(())
 }
 
 // Reads the oldest message from the mailbox
-pub fn read_mailbox() -> (ReadMailboxStatus, Option<Message>) {
+pub fn read_mailbox() -> Result<(ReadMailboxStatus, Message), Error> {
     read_mailbox_inner(0, false)
 }
 
-pub fn read_mailbox_from(sender_pid: Pid) -> (ReadMailboxStatus, Option<Message>) {
+pub fn read_mailbox_from(sender_pid: Pid) -> Result<(ReadMailboxStatus, Message), Error> {
     read_mailbox_inner(sender_pid, true)
 }
 
 /// Reads the oldest message from the mailbox
 /// 
 /// Can filter to messages from a specific PID, or 0 for any
-pub fn read_mailbox_inner(sender_pid: Pid, filter: bool) -> (ReadMailboxStatus, Option<Message>) {
-    let rax = Syscall::read_mailbox as u64;
-    let rdi = sender_pid;
-    let rsi: u64 = if filter { 1 } else { 0 };
-
-    let status: u64;
-    let pid: Pid;
-    let data0: u64;
-    let data1: u64;
-    let data2: u64;
-    let data3: u64;
-
-    unsafe {
-        asm!(
-            "syscall",
-            in("rax") rax,
-            in("rdi") rdi,
-            in("rsi") rsi,
-            lateout("rax") status,
-            lateout("rdi") pid,
-            lateout("rsi") data0,
-            lateout("rdx") data1,
-            lateout("r8") data2,
-            lateout("r9") data3,
-        );
-    }
-
-    let status: ReadMailboxStatus = status.try_into().unwrap();
-
-    // serial_println!("Receive status: {:?}", status);
-
-    if status.is_err() {
-        (status, None)
-    } else {
-        (status, Some(Message { pid, data0, data1, data2, data3 }))
-    }
-}
-
-pub fn set_mailbox_whitelist(whitelist: &[Pid]) -> ConfigMailboxStatus {
-    let rax = Syscall::config_mailbox as u64;
-    let rdi: u64 = MailboxFlags { enable: true, set_whitelist: true }.into();
-    let rsi = whitelist.as_ptr();
-    let rdx = whitelist.len();
-
-    let status: u64;
-
-    unsafe {
-        asm!(
-            "syscall",
-            in("rax") rax,
-            in("rdi") rdi,
-            in("rsi") rsi,
-            in("rdx") rdx,
-            lateout("rax") status,
-        )
-    }
-
-    status.try_into().unwrap()
-}
-
-pub fn set_mailbox_enabled(to: bool) -> ConfigMailboxStatus {
-    let rax = Syscall::config_mailbox as u64;
-    let rdi: u64 = MailboxFlags { enable: to, set_whitelist: false }.into();
-    let rsi = 0;
-    let rdx = 0;
-
-    let status: u64;
-
-    unsafe {
-        asm!(
-            "syscall",
-            in("rax") rax,
-            in("rdi") rdi,
-            in("rsi") rsi,
-            in("rdx") rdx,
-            lateout("rax") status,
-        )
-    }
-
-    status.try_into().unwrap()
-}
-
-pub fn send_payload(message: PayloadMessage) -> SendStatus {
-    let rax = Syscall::send_payload as u64;
+pub fn read_mailbox_inner(sender_pid: Pid, filter: bool) -> Result<(ReadMailboxStatus, Message), Error> {
+    let response = unsafe { raw::read_mailbox(sender_pid, filter) };
+    let status = response.status.into_result()?;
+
+    Ok((status, response.message.unwrap()))
+}
+
+pub fn set_mailbox_whitelist(whitelist: &[Pid]) -> Result<(), Error> {
+    let flags = MailboxFlags { enable: true, set_whitelist: true, set_capacity: false };
+
+    unsafe { raw::config_mailbox(flags, whitelist.as_ptr(), whitelist.len(), 0, OverflowPolicy::Reject) }.into_result()?;
+
+    Ok(())
+}
+
+pub fn set_mailbox_enabled(to: bool) -> Result<(), Error> {
+    let flags = MailboxFlags { enable: to, set_whitelist: false, set_capacity: false };
+
+    unsafe { raw::config_mailbox(flags, core::ptr::null(), 0, 0, OverflowPolicy::Reject) }.into_result()?;
+
+    Ok(())
+}
+
+/// Enables the mailbox and sets how many notifications it holds and what happens to new ones once it's full
+pub fn set_mailbox_capacity(capacity: usize, policy: OverflowPolicy) -> Result<(), Error> {
+    let flags = MailboxFlags { enable: true, set_whitelist: false, set_capacity: true };
+
+    unsafe { raw::config_mailbox(flags, core::ptr::null(), 0, capacity, policy) }.into_result()?;
+
+    Ok(())
+}
+
+/// Gets how many notifications are in the mailbox, how many it holds and how many were dropped
+pub fn mailbox_info() -> MailboxInfo {
+    unsafe { raw::mailbox_info(false) }
+}
+
+/// Gets the number of dropped notifications and resets it
+pub fn take_dropped_notifs() -> u64 {
+    unsafe { raw::mailbox_info(true) }.dropped
+}
+
+pub fn send_payload(message: PayloadMessage) -> Result<(), Error> {
+    send_payload_inner(message, Timeout::Forever)
+}
+
+/// Sends a payload message, blocking until it is received or `ticks` timer ticks have passed
+pub fn send_payload_timeout(message: PayloadMessage, ticks: u64) -> Result<(), Error> {
+    send_payload_inner(message, Timeout::Ticks(ticks))
+}
+
+/// Sends a payload message only if the recipient is already waiting to receive one
+pub fn try_send_payload(message: PayloadMessage) -> Result<(), Error> {
+    send_payload_inner(message, Timeout::Poll)
+}
+
+pub fn send_payload_inner(message: PayloadMessage, timeout: Timeout) -> Result<(), Error> {
+    let PayloadMessage { pid, data0, data1, payload, payload_len } = message;
+
+    unsafe { raw::send_payload(pid, data0, data1, payload as *const u8, payload_len as usize, timeout) }.into_result()?;
+
+    Ok(())
+}
+
+/// Sends a message to another process and blocks until it replies, then returns the reply
+pub fn call(message: Message) -> Result<Message, Error> {
+    let Message { pid, data0, data1, data2, data3 } = message;
+
+    let response = unsafe { raw::call(pid, data0, data1, data2, data3) };
+    response.status.into_result()?;
+
+    Ok(response.message.unwrap())
+}
+
+/// Sends a payload message to another process and blocks until it replies, then returns the reply
+pub fn call_payload(message: PayloadMessage) -> Result<Message, Error> {
     let PayloadMessage { pid, data0, data1, payload, payload_len } = message;
 
-    let status: u64;
-
-    unsafe {
-        asm!(
-            "syscall",
-            in("rax") rax,
-            in("rdi") pid,
-            in("rsi") data0,
-            in("rdx") data1,
-            in("r8") payload,
-            in("r9") payload_len,
-            lateout("rax") status,
-        );
-    }
-
-    status.try_into().unwrap()
-}
\ No newline at end of file
+    let response = unsafe { raw::call_payload(pid, data0, data1, payload as *const u8, payload_len as usize) };
+    response.status.into_result()?;
+
+    Ok(response.message.unwrap())
+}
+
+/// Replies to a process that's blocked calling this one
+pub fn reply(message: Message) -> Result<(), Error> {
+    let Message { pid, data0, data1, data2, data3 } = message;
+
+    unsafe { raw::reply(pid, data0, data1, data2, data3) }.into_result()?;
+
+    Ok(())
+}
+
+/// Replies to a process that's blocked calling this one, then blocks until a message is received from any process
+pub fn reply_and_receive(message: Message) -> Result<Message, Error> {
+    let Message { pid, data0, data1, data2, data3 } = message;
+
+    let response = unsafe { raw::reply_and_receive(pid, data0, data1, data2, data3) };
+    response.status.into_result()?;
+
+    Ok(response.message.unwrap())
+}
+
+/// Answers the last request if there is one, then blocks until the next one is received from any process
+///
+/// Also returns whether the kernel delivered the request with a payload, which is what servers pass to `dispatch`
+pub fn next_request(response: Option<Message>) -> Result<(Message, bool), Error> {
+    let response = match response {
+        Some(Message { pid, data0, data1, data2, data3 }) => unsafe { raw::reply_and_receive(pid, data0, data1, data2, data3) },
+        None => unsafe { raw::receive(core::ptr::null(), 0, Timeout::Forever) },
+    };
+    let status = response.status.into_result()?;
+
+    Ok((response.message.unwrap(), status == ReceiveStatus::Payload))
+}
+
+/// Fails a send or call from `pid` that's waiting on this process
+pub fn cancel_send(pid: Pid) -> Result<(), Error> {
+    unsafe { raw::cancel_send(pid) }.into_result()?;
+
+    Ok(())
+}
+
+/// Blocks until a message is received from a process in the whitelist or a notification arrives
+///
+/// Only notifications from `notifs_from` count if it's set. The status says which of the two the message is
+pub fn wait(whitelist: &[Pid], notifs_from: Option<Pid>) -> Result<(WaitStatus, Message), Error> {
+    let flags = WaitFlags { receive: true, notify: true, filter: notifs_from.is_some() };
+
+    wait_inner(flags, whitelist, notifs_from.unwrap_or(0), Timeout::Forever)
+}
+
+/// Blocks until a message is received from a process in the whitelist, a notification arrives or `ticks` timer ticks
+/// have passed
+pub fn wait_timeout(whitelist: &[Pid], notifs_from: Option<Pid>, ticks: u64) -> Result<(WaitStatus, Message), Error> {
+    let flags = WaitFlags { receive: true, notify: true, filter: notifs_from.is_some() };
+
+    wait_inner(flags, whitelist, notifs_from.unwrap_or(0), Timeout::Ticks(ticks))
+}
+
+pub fn wait_inner(flags: WaitFlags, whitelist: &[Pid], notif_sender: Pid, timeout: Timeout) -> Result<(WaitStatus, Message), Error> {
+    let response = unsafe { raw::wait(flags, whitelist.as_ptr(), whitelist.len(), notif_sender, timeout) };
+    let status = response.status.into_result()?;
+
+    Ok((status, response.message.unwrap()))
+}
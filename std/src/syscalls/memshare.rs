@@ -1,65 +1,73 @@
-use core::arch::asm;
-
-use abi::{ipc::Pid, Syscall};
-pub use abi::memshare::{CreateShareStatus, JoinShareStatus, ShareId, CreateShareResponse};
+use abi::{ipc::{Pid, Message}, raw, Error, Status};
+pub use abi::memshare::{
+    CreateShareStatus, JoinShareStatus, LeaveShareStatus, DestroyShareStatus, AllocShareStatus,
+    ShareId, ShareGrant, CreateShareResponse, JoinShareResponse, AllocShareResponse,
+};
+use abi::vm::MapFlags;
 
 use crate::align_down;
 
-pub fn create_memshare(start: u64, end: u64, whitelist: &[Pid]) -> CreateShareResponse {
-    let rax = Syscall::create_memshare as u64;
-    let start = align_down(start as usize, 4096);
-    let end = align_down(end as usize, 4096);
-
-    let status: u64;
-    let id: ShareId;
-
-    unsafe {
-        asm!(
-            "syscall",
-            in("rax") rax,
-            in("rdi") start,
-            in("rsi") end,
-            in("rdx") whitelist.as_ptr(),
-            in("r8") whitelist.len(),
-            lateout("rax") status,
-            lateout("rdi") id,
-        )
-    }
-
-    let status: CreateShareStatus = status.try_into().unwrap();
-
-    if (status as u64) < 10 {
-        CreateShareResponse {
-            status,
-            id: Some(id)
-        }
-    } else {
-        CreateShareResponse {
-            status,
-            id: None,
-        }
-    }
+/// Shares the pages from `start` to `end` with the processes in `whitelist`, or with anyone if it's empty
+pub fn create_memshare(start: u64, end: u64, whitelist: &[ShareGrant]) -> Result<ShareId, Error> {
+    let start = align_down(start as usize, 4096) as u64;
+    let end = align_down(end as usize, 4096) as u64;
+
+    let response = unsafe { raw::create_memshare(start, end, whitelist.as_ptr(), whitelist.len()) };
+    response.status.into_result()?;
+
+    Ok(response.id.unwrap())
+}
+
+/// Maps a share at `start` to `end`, failing if `access` is more than the creator granted
+pub fn join_memshare(id: ShareId, start: u64, end: u64, access: MapFlags, blacklist: &[Pid]) -> Result<(), Error> {
+    let start = align_down(start as usize, 4096) as u64;
+    let end = align_down(end as usize, 4096) as u64;
+
+    unsafe { raw::join_memshare(id, start, end, access, blacklist.as_ptr(), blacklist.len()) }.status.into_result()?;
+
+    Ok(())
+}
+
+/// Maps a share wherever there's room, returning where it went and how many bytes it takes up
+pub fn join_memshare_anywhere(id: ShareId, access: MapFlags, blacklist: &[Pid]) -> Result<(u64, u64), Error> {
+    let response = unsafe { raw::join_memshare(id, 0, 0, access, blacklist.as_ptr(), blacklist.len()) };
+    response.status.into_result()?;
+
+    Ok((response.start, response.size))
 }
 
-pub fn join_memshare(id: ShareId, start: u64, end: u64, blacklist: &[Pid]) -> JoinShareStatus {
-    let rax = Syscall::join_memshare as u64;
-    let start = align_down(start as usize, 4096);
-    let end = align_down(end as usize, 4096);
-
-    let status: u64;
-
-    unsafe {
-        asm!(
-            "syscall",
-            in("rax") rax,
-            in("rdi") id,
-            in("rsi") start,
-            in("rdx") end,
-            in("r8") blacklist.as_ptr(),
-            in("r9") blacklist.len(),
-            lateout("rax") status,
-        )
-    }
-
-    status.try_into().unwrap()
-}
\ No newline at end of file
+/// Creates a share out of `pages` fresh zeroed pages, returning its ID and where it was mapped
+pub fn alloc_memshare(pages: u64, whitelist: &[ShareGrant]) -> Result<(ShareId, u64), Error> {
+    let response = unsafe { raw::alloc_memshare(pages, whitelist.as_ptr(), whitelist.len()) };
+    response.status.into_result()?;
+
+    Ok((response.id.unwrap(), response.start))
+}
+
+pub fn leave_memshare(id: ShareId) -> Result<(), Error> {
+    unsafe { raw::leave_memshare(id) }.into_result()?;
+
+    Ok(())
+}
+
+pub fn destroy_memshare(id: ShareId) -> Result<(), Error> {
+    unsafe { raw::destroy_memshare(id) }.into_result()?;
+
+    Ok(())
+}
+
+/// Sends a message to `pid` that lets it join `share` with `access`, blocking until it's received
+///
+/// The recipient can join with `join_granted`, `data0` and `data1` are passed along as usual
+pub fn send_grant(pid: Pid, share: ShareId, access: MapFlags, data0: u64, data1: u64) -> Result<(), Error> {
+    unsafe { raw::send_grant(pid, share, access, data0, data1) }.into_result()?;
+
+    Ok(())
+}
+
+/// Joins the share a message from `send_grant` gave access to, wherever there's room
+///
+/// Returns like `join_memshare_anywhere`
+pub fn join_granted(message: &Message) -> Result<(u64, u64), Error> {
+    join_memshare_anywhere(message.data2, MapFlags::from(message.data3), &[])
+}
@@ -1,21 +1,8 @@
-use core::arch::asm;
-
-use abi::Syscall;
+use abi::raw;
 use alloc::{fmt, string::String};
 
 pub fn serial_print(text: String) {
-    let rax = Syscall::send_serial as u64;
-    let length = text.len();
-
-    unsafe {
-        asm!(
-            "syscall",
-            "nop",
-            in("rax") rax,
-            in("rdi") text.as_ptr(),
-            in("rsi") length,
-        );
-    }
+    unsafe { raw::send_serial(text.as_ptr(), text.len()) };
 }
 
 #[doc(hidden)]
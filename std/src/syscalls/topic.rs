@@ -0,0 +1,39 @@
+use abi::{raw, Error, Status, ipc::Pid};
+
+pub use abi::topic::{TopicId, CreateTopicStatus, DestroyTopicStatus, SubscribeTopicStatus, UnsubscribeTopicStatus, PublishTopicStatus};
+
+/// Creates a topic that only this process can publish to
+pub fn create_topic() -> TopicId {
+    unsafe { raw::create_topic() }.id
+}
+
+/// Destroys a topic this process created
+pub fn destroy_topic(topic: TopicId) -> Result<(), Error> {
+    unsafe { raw::destroy_topic(topic) }.into_result()?;
+
+    Ok(())
+}
+
+/// Subscribes `pid` to a topic, which has to be this process unless it created the topic
+pub fn subscribe_topic(topic: TopicId, pid: Pid) -> Result<(), Error> {
+    unsafe { raw::subscribe_topic(topic, pid) }.into_result()?;
+
+    Ok(())
+}
+
+/// Unsubscribes `pid` from a topic, which has to be this process unless it created the topic
+pub fn unsubscribe_topic(topic: TopicId, pid: Pid) -> Result<(), Error> {
+    unsafe { raw::unsubscribe_topic(topic, pid) }.into_result()?;
+
+    Ok(())
+}
+
+/// Notifies every subscriber of a topic this process created, returning how many of them got it
+pub fn publish_topic(topic: TopicId, data: [u64; 4]) -> Result<u64, Error> {
+    let [data0, data1, data2, data3] = data;
+
+    let response = unsafe { raw::publish_topic(topic, data0, data1, data2, data3) };
+    response.status.into_result()?;
+
+    Ok(response.delivered)
+}
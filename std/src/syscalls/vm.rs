@@ -0,0 +1,31 @@
+use abi::{raw, Error, Status};
+pub use abi::vm::{
+    GrowHeapStatus, GrowHeapResponse, HEAP_START, HEAP_MAX,
+    MapFlags, MapMemoryStatus, MapMemoryResponse, UnmapMemoryStatus, ProtectMemoryStatus, MAP_START, MAP_END,
+};
+
+/// Maps at least `size` more bytes onto the end of the heap, returning the start and length of the new pages
+pub fn grow_heap(size: u64) -> Result<(u64, u64), Error> {
+    let response = unsafe { raw::grow_heap(size) };
+    response.status.into_result()?;
+
+    Ok((response.start, response.size))
+}
+
+/// Maps `size` bytes of zeroed memory at `start`, or wherever there's room if it's `None`, returning where it ended up
+pub fn map_memory(start: Option<u64>, size: u64, flags: MapFlags) -> Result<u64, Error> {
+    let response = unsafe { raw::map_memory(start.unwrap_or(0), size, flags) };
+    response.status.into_result()?;
+
+    Ok(response.start)
+}
+
+pub fn unmap_memory(start: u64, size: u64) -> Result<(), Error> {
+    unsafe { raw::unmap_memory(start, size) }.into_result()?;
+    Ok(())
+}
+
+pub fn protect_memory(start: u64, size: u64, flags: MapFlags) -> Result<(), Error> {
+    unsafe { raw::protect_memory(start, size, flags) }.into_result()?;
+    Ok(())
+}
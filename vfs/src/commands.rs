@@ -1,41 +1,30 @@
 pub mod api;
 
-use std::{ipc::{PayloadMessage, Message}, extract_payload};
+use std::ipc::Pid;
 
 use alloc::{vec::Vec, string::String, boxed::Box};
-use api::OpenStatus;
+use api::{CreateStatus, OpenStatus, Server};
 
 use crate::{cache::Cache, Path};
 
-pub fn open(cache: &mut Cache, request: PayloadMessage) -> Message {
-    let path_buf: Vec<u8> = unsafe { extract_payload(&request) };
-    let Ok(path_str) = String::from_utf8(path_buf) else {
-        return Message {
-            pid: request.pid,
-            data0: OpenStatus::InvalidUtf8 as u64,
-            ..Default::default()
-        }
-    };
+impl Server for Cache<'_> {
+    fn open(&mut self, _from: Pid, path: &[u8]) -> OpenStatus {
+        let Ok(path_str) = String::from_utf8(path.into()) else {
+            return OpenStatus::InvalidUtf8;
+        };
 
-    let path = Path::new(path_str);
+        let path = Path::new(path_str);
 
-    Message { ..Default::default() }
-}
+        OpenStatus::Success
+    }
+
+    fn create(&mut self, _from: Pid, flags: u8, path: &[u8]) -> CreateStatus {
+        let Ok(path_str) = String::from_utf8(path.into()) else {
+            return CreateStatus::InvalidUtf8;
+        };
 
-pub fn create(cache: &mut Cache, request: PayloadMessage) -> Message {
-    let path_buf: Vec<u8> = unsafe { extract_payload(&request) };
-    let Ok(path_str) = String::from_utf8(path_buf) else {
-        return Message {
-            pid: request.pid,
-            data0: OpenStatus::InvalidUtf8 as u64,
-            ..Default::default()
-        }
-    };
-
-    let path = Path::new(path_str);
-    let flags = ((request.data0 & 0xFF) as u8).into();
-
-    cache.create_vnode(path, flags);
-        
-    Message { ..Default::default() }
-}
\ No newline at end of file
+        let path = Path::new(path_str);
+
+        self.create_vnode(path, flags.into())
+    }
+}
@@ -5,33 +5,26 @@
 
 extern crate alloc;
 
-use std::{serial_println, config_rbuffer, ipc::{receive, notify}};
+use std::{serial_println, config_rbuffer, ipc::{Message, next_request}};
 
-use vfs::Command;
+use vfs::dispatch;
 use vfs::cache::Cache;
-use vfs::commands;
 
 #[no_mangle]
 pub unsafe extern fn _start() {
     serial_println!("[VFS] Started");
-    config_rbuffer(4096);
+    config_rbuffer(4096, 1).unwrap();
 
     let mut cache = Cache::new();
     
+    let mut response: Option<Message> = None;
+    
     loop {
-        let request = receive(&[]);
-
-        let opcode = ((request.data0 >> 56) & 0xFF) as u8;
-
-        let Ok(command): Result<Command, _> = opcode.try_into() else {
-            panic!("[VFS] Invalid command: {:#04X}", opcode);
-        };
-
-        let response = match command {
-            Command::open => commands::open(&mut cache, request.into()),
-            _ => todo!(),
+        // answer the last request while waiting for the next one
+        let Ok((request, payload)) = next_request(response.take()) else {
+            continue;
         };
 
-        notify(response);
+        response = Some(dispatch(&mut cache, &request, payload));
     }
 }